@@ -1,12 +1,151 @@
 //! CLI parsing helpers and clap App.
-use clap::{App, Arg, ArgMatches, SubCommand};
+use clap::{App, AppSettings, Arg, ArgGroup, ArgMatches, SubCommand};
 
+use crate::csv::CsvOptions;
+use crate::ical_import::IcsFilter;
+use crate::invoice::InvoiceFormat;
+use crate::rtw_core::activity::OngoingActivity;
 use crate::rtw_core::clock::{Clock, Time};
 use crate::rtw_core::datetimew::DateTimeW;
-use crate::rtw_core::{ActivityId, Description, Tags};
+use crate::rtw_core::durationw::DurationW;
+use crate::rtw_core::{ActivitySelector, Description, Metadata, Tags};
 use crate::time_tools::TimeTools;
+use std::ffi::OsString;
+use std::path::PathBuf;
 use std::str::FromStr;
 
+/// Output format of `rtw summary` and `rtw` (current activities)
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum OutputFormat {
+    /// Human readable
+    #[default]
+    Text,
+    /// JSON array
+    Json,
+    /// JSON Lines: one JSON object per line
+    JsonLines,
+}
+
+/// Display options of `rtw summary`
+#[derive(Debug, Clone, Default)]
+pub struct SummaryOptions {
+    pub display_id: bool,
+    pub display_uid: bool,
+    pub display_description: bool,
+    pub report: bool,
+    /// Tree report of hierarchical tags down to this depth (`summary --report --depth`)
+    pub depth: Option<usize>,
+    /// Report per tag instead of per title (`summary --by-tag`)
+    pub by_tag: bool,
+    /// Split activity durations evenly between their tags (`summary --by-tag --split-evenly`)
+    pub split_evenly: bool,
+    /// Keep activities matching these tag filters (`summary --include-tag --exclude-tag`)
+    pub tag_filter: TagFilter,
+    pub format: OutputFormat,
+    /// Keep activities matching all these metadata filters (`summary --where`)
+    pub metadata_filter: MetadataFilter,
+}
+
+/// `key` (field is set) or `key=value` (field has this value) conditions, all must match
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct MetadataFilter(pub Vec<(String, Option<String>)>);
+
+/// Include/exclude tag filters
+///
+/// A filter tag also matches its hierarchical sub-tags e.g. `client` matches `client:acme`.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct TagFilter {
+    /// Keep activities with at least one of these tags (all activities if empty)
+    pub include: Tags,
+    /// Drop activities with any of these tags
+    pub exclude: Tags,
+}
+
+impl TagFilter {
+    pub fn matches(&self, tags: &[String], separator: &str) -> bool {
+        let has = |filter_tag: &String| {
+            tags.iter().any(|tag| {
+                tag == filter_tag
+                    || (!separator.is_empty()
+                        && tag
+                            .strip_prefix(filter_tag.as_str())
+                            .is_some_and(|rest| rest.starts_with(separator)))
+            })
+        };
+        (self.include.is_empty() || self.include.iter().any(has)) && !self.exclude.iter().any(has)
+    }
+}
+
+impl MetadataFilter {
+    pub fn matches(&self, metadata: &Metadata) -> bool {
+        self.0.iter().all(
+            |(key, value_maybe)| match (metadata.get(key), value_maybe) {
+                (None, _) => false,
+                (Some(_), None) => true,
+                (Some(value), Some(expected)) => value == expected,
+            },
+        )
+    }
+}
+
+/// Changes requested by `rtw edit` or `rtw modify`, `None` fields are left unchanged
+#[derive(Debug, Clone, Default)]
+pub struct ActivityEdit {
+    pub start_time: Option<DateTimeW>,
+    pub stop_time: Option<DateTimeW>,
+    pub tags: Option<Tags>,
+    /// `Some("")` removes the description
+    pub description: Option<Description>,
+}
+
+impl ActivityEdit {
+    /// Apply changes except stop time (uid is kept)
+    pub fn apply(self, activity: OngoingActivity) -> OngoingActivity {
+        let description = match self.description {
+            None => activity.description,
+            Some(description) if description.is_empty() => None,
+            Some(description) => Some(description),
+        };
+        OngoingActivity {
+            start_time: self.start_time.unwrap_or(activity.start_time),
+            tags: self.tags.unwrap_or(activity.tags),
+            description,
+            ..activity
+        }
+    }
+}
+
+/// Activities to merge (see `merge`)
+#[derive(Debug, Clone)]
+pub enum MergeSelection {
+    /// Explicit activities
+    Activities(Vec<ActivitySelector>),
+    /// Consecutive activities with same tags separated by at most this gap
+    Gap(DurationW),
+}
+
+/// Output format of `rtw dump`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum DumpFormat {
+    /// include ongoing activities (ending now)
+    ICal(bool),
+    Timewarrior,
+    Csv(CsvOptions),
+    Json,
+    JsonLines,
+}
+
+/// Activities source of `rtw import`
+#[derive(Debug, Clone)]
+pub enum ImportSource {
+    /// Timewarrior data directory
+    Timewarrior(PathBuf),
+    /// CSV file
+    Csv(PathBuf, CsvOptions),
+    /// iCalendar file
+    Ics(PathBuf, IcsFilter),
+}
+
 // 09:00 foo -> (09:00, foo)
 // foo -> (Now, foo)
 // last friday 8pm foo -> (last friday 8pm, foo)
@@ -70,11 +209,178 @@ fn split_time_range(tokens: &[String], clock: &dyn Clock) -> anyhow::Result<(Tim
     }
 }
 
+fn metadata_arg() -> Arg<'static, 'static> {
+    Arg::with_name("set")
+        .long("set")
+        .takes_value(true)
+        .multiple(true)
+        .number_of_values(1)
+        .value_name("KEY=VALUE")
+        .help("set a metadata field e.g. '--set ticket=ABC-123' (repeatable)")
+}
+
+/// Parse `key=value` pairs, the last value wins if a key is repeated
+fn parse_metadata(m: &ArgMatches) -> anyhow::Result<Metadata> {
+    let mut metadata = Metadata::new();
+    for pair in m.values_of("set").into_iter().flatten() {
+        match pair.split_once('=') {
+            Some((key, value)) if !key.trim().is_empty() => {
+                metadata.insert(key.trim().to_string(), value.to_string());
+            }
+            _ => {
+                return Err(anyhow::anyhow!(
+                    "invalid metadata (expected KEY=VALUE): {}",
+                    pair
+                ))
+            }
+        }
+    }
+    Ok(metadata)
+}
+
+fn values_or_empty(m: &ArgMatches, name: &str) -> Vec<String> {
+    m.values_of(name)
+        .map_or_else(Vec::new, |values| values.map(String::from).collect())
+}
+
+fn parse_depth(m: &ArgMatches) -> anyhow::Result<Option<usize>> {
+    match m.value_of("depth") {
+        None => Ok(None),
+        Some(depth) => match usize::from_str(depth) {
+            Ok(depth) if depth > 0 => Ok(Some(depth)),
+            _ => Err(anyhow::anyhow!("invalid depth (expected N > 0): {}", depth)),
+        },
+    }
+}
+
+fn parse_metadata_filter(m: &ArgMatches) -> MetadataFilter {
+    MetadataFilter(
+        m.values_of("where")
+            .into_iter()
+            .flatten()
+            .map(|condition| match condition.split_once('=') {
+                None => (condition.to_string(), None),
+                Some((key, value)) => (key.to_string(), Some(value.to_string())),
+            })
+            .collect(),
+    )
+}
+
+fn skip_overlapping_arg() -> Arg<'static, 'static> {
+    Arg::with_name("skip_overlapping")
+        .long("skip-overlapping")
+        .help(concat!(
+            "skip activities overlapping existing ones instead of failing\n",
+            "use `rtw --overlap import ...` to import them anyway"
+        ))
+}
+
+fn output_format_arg() -> Arg<'static, 'static> {
+    Arg::with_name("format")
+        .long("format")
+        .takes_value(true)
+        .possible_values(&["text", "json", "jsonl"])
+        .default_value("text")
+}
+
+pub fn parse_output_format(m: &ArgMatches) -> OutputFormat {
+    match m.value_of("format") {
+        Some("json") => OutputFormat::Json,
+        Some("jsonl") => OutputFormat::JsonLines,
+        _ => OutputFormat::Text,
+    }
+}
+
+fn delimiter_arg() -> Arg<'static, 'static> {
+    Arg::with_name("delimiter")
+        .long("delimiter")
+        .takes_value(true)
+        .help("CSV delimiter e.g. ';' or 'tab' (default ',')")
+}
+
+fn time_format_arg() -> Arg<'static, 'static> {
+    Arg::with_name("time_format")
+        .long("time-format")
+        .takes_value(true)
+        .help("CSV time format e.g. '%Y-%m-%d %H:%M' (default '%Y-%m-%dT%H:%M:%S')")
+}
+
+fn parse_csv_options(m: &ArgMatches) -> anyhow::Result<CsvOptions> {
+    let default = CsvOptions::default();
+    let delimiter = match m.value_of("delimiter") {
+        None => default.delimiter,
+        Some("tab") | Some("\\t") => '\t',
+        Some(delimiter) => {
+            let mut chars = delimiter.chars();
+            match (chars.next(), chars.next()) {
+                (Some(c), None) if c != '"' && c != '\n' => c,
+                _ => return Err(anyhow::anyhow!("invalid delimiter: {}", delimiter)),
+            }
+        }
+    };
+    let time_format = m
+        .value_of("time_format")
+        .map(String::from)
+        .unwrap_or(default.time_format);
+    Ok(CsvOptions {
+        delimiter,
+        time_format,
+    })
+}
+
+/// Built-in subcommands (see `get_app`), other subcommands are external (plugins)
+pub const BUILTIN_SUBCOMMANDS: [&str; 27] = [
+    "start",
+    "track",
+    "stop",
+    "summary",
+    "dump",
+    "invoice",
+    "continue",
+    "day",
+    "week",
+    "timeline",
+    "delete",
+    "cancel",
+    "edit",
+    "split",
+    "merge",
+    "modify",
+    "pause",
+    "resume",
+    "import",
+    "undo",
+    "redo",
+    "completion",
+    "serve",
+    "rpc",
+    "serve-ics",
+    "status",
+    "help",
+];
+
+/// Built-in subcommands similar to `name` (e.g. `summary` for `sumary`), most similar first
+pub fn similar_subcommands(name: &str) -> Vec<&'static str> {
+    let mut similar: Vec<(f64, &'static str)> = BUILTIN_SUBCOMMANDS
+        .iter()
+        .map(|subcommand| (strsim::jaro_winkler(name, subcommand), *subcommand))
+        // same threshold as clap suggestions
+        .filter(|(confidence, _subcommand)| *confidence > 0.8)
+        .collect();
+    similar.sort_by(|(a, _), (b, _)| b.partial_cmp(a).unwrap_or(std::cmp::Ordering::Equal));
+    similar
+        .into_iter()
+        .map(|(_confidence, subcommand)| subcommand)
+        .collect()
+}
+
 pub fn get_app() -> App<'static, 'static> {
     App::new(crate_name!())
         .version(crate_version!())
         .author("PicoJr")
         .about("rust time tracking CLI")
+        .setting(AppSettings::AllowExternalSubcommands)
+        .after_help("Unknown subcommands run rtw-<name> executables found in PATH (see commands.md)")
         .arg(
             Arg::with_name("directory")
                 .short("d")
@@ -115,6 +421,10 @@ pub fn get_app() -> App<'static, 'static> {
                 .required(false)
                 .help("dry run: don't write anything to the filesystem"),
         )
+        .arg(output_format_arg().help(concat!(
+            "output format of current activities\n",
+            "see commands.md for the JSON schema"
+        )))
         .subcommand(
             SubCommand::with_name("start")
                 .about("Start new activity")
@@ -133,7 +443,8 @@ pub fn get_app() -> App<'static, 'static> {
                         .long("description")
                         .takes_value(true)
                         .help("long activity description"),
-                ),
+                )
+                .arg(metadata_arg()),
         )
         .subcommand(
             SubCommand::with_name("track")
@@ -154,7 +465,8 @@ pub fn get_app() -> App<'static, 'static> {
                         .long("description")
                         .takes_value(true)
                         .help("long activity description"),
-                ),
+                )
+                .arg(metadata_arg()),
         )
         .subcommand(
             SubCommand::with_name("stop")
@@ -173,7 +485,7 @@ pub fn get_app() -> App<'static, 'static> {
                         .long("id")
                         .takes_value(true)
                         .help(concat!(
-                            "optional activity id\n",
+                            "optional activity id or uid\n",
                             "current activity is stopped when omitted"
                         )),
                 ),
@@ -212,6 +524,11 @@ pub fn get_app() -> App<'static, 'static> {
                         .long("id")
                         .help("display activities id"),
                 )
+                .arg(
+                    Arg::with_name("uid")
+                        .long("uid")
+                        .help("display activities uid (stable, unlike id)"),
+                )
                 .arg(
                     Arg::with_name("description")
                         .short("d")
@@ -223,7 +540,67 @@ pub fn get_app() -> App<'static, 'static> {
                         .short("r")
                         .long("report")
                         .help("sum up activities with same tag together"),
-                ),
+                )
+                .arg(
+                    Arg::with_name("depth")
+                        .long("depth")
+                        .takes_value(true)
+                        .value_name("N")
+                        .requires("report")
+                        .help(concat!(
+                            "tree report of hierarchical tags (e.g. 'client:acme:backend')\n",
+                            "durations are rolled up to N levels"
+                        )),
+                )
+                .arg(
+                    Arg::with_name("by_tag")
+                        .long("by-tag")
+                        .conflicts_with("report")
+                        .help(concat!(
+                            "sum up durations per tag instead of per title\n",
+                            "each activity counts for every tag it carries"
+                        )),
+                )
+                .arg(
+                    Arg::with_name("split_evenly")
+                        .long("split-evenly")
+                        .requires("by_tag")
+                        .help("split activity durations evenly between their tags"),
+                )
+                .arg(
+                    Arg::with_name("include_tag")
+                        .long("include-tag")
+                        .takes_value(true)
+                        .multiple(true)
+                        .number_of_values(1)
+                        .value_name("TAG")
+                        .help("keep activities with at least one of these tags (repeatable)"),
+                )
+                .arg(
+                    Arg::with_name("exclude_tag")
+                        .long("exclude-tag")
+                        .takes_value(true)
+                        .multiple(true)
+                        .number_of_values(1)
+                        .value_name("TAG")
+                        .help("drop activities with any of these tags (repeatable)"),
+                )
+                .arg(
+                    Arg::with_name("where")
+                        .long("where")
+                        .takes_value(true)
+                        .multiple(true)
+                        .number_of_values(1)
+                        .value_name("KEY[=VALUE]")
+                        .help(concat!(
+                            "keep activities with this metadata field (and value)\n",
+                            "e.g '--where client=acme --where billable'"
+                        )),
+                )
+                .arg(output_format_arg().help(concat!(
+                    "output format\n",
+                    "see commands.md for the JSON schema"
+                ))),
         )
         .subcommand(
             SubCommand::with_name("dump")
@@ -232,8 +609,33 @@ pub fn get_app() -> App<'static, 'static> {
                     "examples:\n",
                     "rtw dump > today.ics\n",
                     "rtw dump --lastweek > lastweek.ics\n",
-                    "rtw dump last friday - now > recent.ics\n"
+                    "rtw dump last friday - now > recent.ics\n",
+                    "rtw dump --format timewarrior --output-dir ~/.timewarrior/data 2020-01-01T00:00:00 - now\n"
                 ))
+                .arg(
+                    Arg::with_name("format")
+                        .long("format")
+                        .takes_value(true)
+                        .possible_values(&["ical", "timewarrior", "csv", "json", "jsonl"])
+                        .default_value("ical")
+                        .help("output format"),
+                )
+                .arg(delimiter_arg())
+                .arg(time_format_arg())
+                .arg(
+                    Arg::with_name("ongoing")
+                        .long("ongoing")
+                        .help("include ongoing activities as events ending now (ical format only)"),
+                )
+                .arg(
+                    Arg::with_name("output_dir")
+                        .long("output-dir")
+                        .takes_value(true)
+                        .help(concat!(
+                            "write Timewarrior monthly data files (YYYY-MM.data) to this directory\n",
+                            "instead of stdout (timewarrior format only)"
+                        )),
+                )
                 .arg(
                     Arg::with_name("tokens")
                         .multiple(true)
@@ -261,13 +663,64 @@ pub fn get_app() -> App<'static, 'static> {
                         .help("activities done this week"),
                 ),
         )
+        .subcommand(
+            SubCommand::with_name("invoice")
+                .about("Render an invoice of a client's activities (Markdown or HTML)")
+                .after_help(concat!(
+                    "rates, currency and rounding are set in the config (billing)\n",
+                    "examples:\n",
+                    "rtw invoice --client acme --lastweek\n",
+                    "rtw invoice --client acme --format html 2020-12-01T00:00:00 - 2021-01-01T00:00:00 > acme.html\n"
+                ))
+                .arg(
+                    Arg::with_name("client")
+                        .long("client")
+                        .takes_value(true)
+                        .required(true)
+                        .help("activities with this client metadata or tag (or sub-tag)"),
+                )
+                .arg(
+                    Arg::with_name("format")
+                        .long("format")
+                        .takes_value(true)
+                        .possible_values(&["markdown", "html"])
+                        .default_value("markdown")
+                        .help("output format"),
+                )
+                .arg(
+                    Arg::with_name("tokens")
+                        .multiple(true)
+                        .required(false)
+                        .conflicts_with_all(&["yesterday", "lastweek", "week"])
+                        .help(concat!(
+                            "optional interval time clue\n",
+                            "start - end\n",
+                            "e.g 'last monday - now' "
+                        )),
+                )
+                .arg(
+                    Arg::with_name("yesterday")
+                        .long("yesterday")
+                        .help("activities done yesterday"),
+                )
+                .arg(
+                    Arg::with_name("lastweek")
+                        .long("lastweek")
+                        .help("activities done last week"),
+                )
+                .arg(
+                    Arg::with_name("week")
+                        .long("week")
+                        .help("activities done this week"),
+                ),
+        )
         .subcommand(
             SubCommand::with_name("continue")
                 .about("Continue a finished activity")
                 .arg(
-                    Arg::with_name("id")
-                        .required(false)
-                        .help("activity id (when id is not provided continue last activity)"),
+                    Arg::with_name("id").required(false).help(
+                        "activity id or uid (when id is not provided continue last activity)",
+                    ),
                 ),
         )
         .subcommand(SubCommand::with_name("day").about("Display the current day as a timeline"))
@@ -289,7 +742,11 @@ pub fn get_app() -> App<'static, 'static> {
         .subcommand(
             SubCommand::with_name("delete")
                 .about("Delete activity")
-                .arg(Arg::with_name("id").required(true).help("activity id")),
+                .arg(
+                    Arg::with_name("id")
+                        .required(true)
+                        .help("activity id or uid"),
+                ),
         )
         .subcommand(
             SubCommand::with_name("cancel")
@@ -299,11 +756,274 @@ pub fn get_app() -> App<'static, 'static> {
                         .long("id")
                         .takes_value(true)
                         .help(concat!(
-                            "optional activity id\n",
-                            "current activity is stopped when omitted"
+                            "optional activity id or uid\n",
+                            "current activity is cancelled when omitted"
+                        )),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("edit")
+                .about("Edit a finished activity")
+                .after_help(concat!(
+                    "examples:\n",
+                    "rtw edit 0 --start 09:00\n",
+                    "rtw edit 2 --stop \"10 min ago\" --tags foo bar\n",
+                    "rtw edit 0 --description \"\"\n"
+                ))
+                .arg(
+                    Arg::with_name("id")
+                        .required(true)
+                        .help("activity id or uid"),
+                )
+                .arg(
+                    Arg::with_name("start")
+                        .long("start")
+                        .takes_value(true)
+                        .help("new start time clue e.g. '09:00'"),
+                )
+                .arg(
+                    Arg::with_name("stop")
+                        .long("stop")
+                        .takes_value(true)
+                        .help("new stop time clue e.g. '4 min ago'"),
+                )
+                .arg(
+                    Arg::with_name("tags")
+                        .long("tags")
+                        .multiple(true)
+                        .takes_value(true)
+                        .help("new tags"),
+                )
+                .arg(
+                    Arg::with_name("description")
+                        .short("d")
+                        .long("description")
+                        .takes_value(true)
+                        .help("new long activity description (empty to remove it)"),
+                )
+                .group(
+                    ArgGroup::with_name("edits")
+                        .args(&["start", "stop", "tags", "description"])
+                        .multiple(true)
+                        .required(true),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("split")
+                .about("Split a finished activity in 2 adjacent activities")
+                .after_help(concat!(
+                    "examples:\n",
+                    "rtw split 0 10:30\n",
+                    "rtw split 2 \"2 hours ago\" review\n"
+                ))
+                .arg(
+                    Arg::with_name("id")
+                        .required(true)
+                        .help("activity id or uid"),
+                )
+                .arg(
+                    Arg::with_name("tokens")
+                        .multiple(true)
+                        .required(true)
+                        .help(concat!(
+                            "split time clue e.g. '10:30'\n",
+                            "followed by optional tags of the second activity"
+                        )),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("merge")
+                .about("Merge finished activities into a single activity")
+                .after_help(concat!(
+                    "examples:\n",
+                    "rtw merge 0 1\n",
+                    "rtw merge --gap 5min\n",
+                    "gaps between merged activities are recorded as breaks\n"
+                ))
+                .arg(
+                    Arg::with_name("id")
+                        .multiple(true)
+                        .min_values(2)
+                        .required_unless("gap")
+                        .help("ids or uids of the activities to merge"),
+                )
+                .arg(
+                    Arg::with_name("gap")
+                        .long("gap")
+                        .takes_value(true)
+                        .conflicts_with("id")
+                        .help(concat!(
+                            "merge consecutive activities with same tags\n",
+                            "separated by at most this duration e.g. '5min', '1h', '00:10:00'"
                         )),
                 ),
         )
+        .subcommand(
+            SubCommand::with_name("modify")
+                .about("Modify ongoing activity")
+                .after_help(concat!(
+                    "examples:\n",
+                    "rtw modify --start \"10 min ago\"\n",
+                    "rtw modify --tags foo bar\n",
+                    "rtw modify --id 1 --description \"fix #42\"\n"
+                ))
+                .arg(
+                    Arg::with_name("id")
+                        .long("id")
+                        .takes_value(true)
+                        .help(concat!(
+                            "optional activity id or uid\n",
+                            "current activity is modified when omitted"
+                        )),
+                )
+                .arg(
+                    Arg::with_name("start")
+                        .long("start")
+                        .takes_value(true)
+                        .help("new start time clue e.g. '09:00'"),
+                )
+                .arg(
+                    Arg::with_name("tags")
+                        .long("tags")
+                        .multiple(true)
+                        .takes_value(true)
+                        .help("new tags"),
+                )
+                .arg(
+                    Arg::with_name("description")
+                        .short("d")
+                        .long("description")
+                        .takes_value(true)
+                        .help("new long activity description (empty to remove it)"),
+                )
+                .group(
+                    ArgGroup::with_name("edits")
+                        .args(&["start", "tags", "description"])
+                        .multiple(true)
+                        .required(true),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("pause")
+                .about("Pause ongoing activity (start a break)")
+                .arg(
+                    Arg::with_name("time")
+                        .multiple(true)
+                        .required(false)
+                        .help(concat!(
+                            "optional time clue e.g. 4min ago\n",
+                            "current time is used when omitted"
+                        )),
+                )
+                .arg(
+                    Arg::with_name("id")
+                        .long("id")
+                        .takes_value(true)
+                        .help(concat!(
+                            "optional activity id or uid\n",
+                            "current activity is paused when omitted"
+                        )),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("resume")
+                .about("Resume paused activity (stop the break)")
+                .arg(
+                    Arg::with_name("time")
+                        .multiple(true)
+                        .required(false)
+                        .help(concat!(
+                            "optional time clue e.g. 4min ago\n",
+                            "current time is used when omitted"
+                        )),
+                )
+                .arg(
+                    Arg::with_name("id")
+                        .long("id")
+                        .takes_value(true)
+                        .help(concat!(
+                            "optional activity id or uid\n",
+                            "current activity is resumed when omitted"
+                        )),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("import")
+                .about("Import activities from other time trackers")
+                .setting(AppSettings::SubcommandRequiredElseHelp)
+                .subcommand(
+                    SubCommand::with_name("timewarrior")
+                        .about("Import Timewarrior intervals (YYYY-MM.data files)")
+                        .after_help(concat!(
+                            "examples:\n",
+                            "rtw import timewarrior ~/.timewarrior/data\n",
+                            "rtw --overlap import timewarrior ~/.timewarrior/data\n"
+                        ))
+                        .arg(
+                            Arg::with_name("dir")
+                                .required(true)
+                                .help("Timewarrior data directory"),
+                        )
+                        .arg(skip_overlapping_arg()),
+                )
+                .subcommand(
+                    SubCommand::with_name("csv")
+                        .about("Import finished activities from a CSV file")
+                        .after_help(concat!(
+                            "columns (header required): id,start,stop,duration,tags,description\n",
+                            "start and stop are required, other columns are optional\n",
+                            "examples:\n",
+                            "rtw import csv activities.csv\n",
+                            "rtw import csv --delimiter ';' --time-format '%d/%m/%Y %H:%M' activities.csv\n"
+                        ))
+                        .arg(
+                            Arg::with_name("file")
+                                .required(true)
+                                .help("CSV file"),
+                        )
+                        .arg(delimiter_arg())
+                        .arg(time_format_arg())
+                        .arg(skip_overlapping_arg()),
+                )
+                .subcommand(
+                    SubCommand::with_name("ics")
+                        .about("Import events from an iCalendar file")
+                        .after_help(concat!(
+                            "event summary words become tags\n",
+                            "all-day events are skipped, recurring events are not expanded\n",
+                            "examples:\n",
+                            "rtw import ics calendar.ics\n",
+                            "rtw import ics calendar.ics --tags meeting --from 'last monday' --to now\n"
+                        ))
+                        .arg(
+                            Arg::with_name("file")
+                                .required(true)
+                                .help("iCalendar (.ics) file"),
+                        )
+                        .arg(
+                            Arg::with_name("tags")
+                                .long("tags")
+                                .multiple(true)
+                                .takes_value(true)
+                                .help("import only events with one of these tags"),
+                        )
+                        .arg(
+                            Arg::with_name("from")
+                                .long("from")
+                                .takes_value(true)
+                                .help("import only events starting after this time clue e.g. 'last monday'"),
+                        )
+                        .arg(
+                            Arg::with_name("to")
+                                .long("to")
+                                .takes_value(true)
+                                .help("import only events starting before this time clue e.g. 'yesterday'"),
+                        )
+                        .arg(skip_overlapping_arg()),
+                ),
+        )
+        .subcommand(SubCommand::with_name("undo").about("Undo last change"))
+        .subcommand(SubCommand::with_name("redo").about("Redo last undone change"))
         .subcommand(
             SubCommand::with_name("completion")
                 .about("generate completion file")
@@ -314,6 +1034,85 @@ pub fn get_app() -> App<'static, 'static> {
                         .required(true),
                 ),
         )
+        .subcommand(
+            SubCommand::with_name("serve")
+                .about("Serve a REST/JSON API over HTTP (localhost)")
+                .after_help(concat!(
+                    "endpoints:\n",
+                    "GET    /activities?range=..&from=..&to=..&tags=..&ongoing=true\n",
+                    "GET    /activities/ongoing\n",
+                    "POST   /activities/start   {\"tags\": [..], \"description\": .., \"start\": ..}\n",
+                    "POST   /activities/stop    {\"id\": .., \"time\": ..}\n",
+                    "POST   /activities/cancel  {\"id\": ..}\n",
+                    "POST   /activities/track   {\"start\": .., \"stop\": .., \"tags\": [..], \"description\": ..}\n",
+                    "DELETE /activities/<id>\n",
+                    "add ?dry=true to a POST or DELETE request to plan it without applying it\n",
+                    "examples:\n",
+                    "rtw serve --port 8081\n",
+                    "curl -X POST -d '{\"tags\": [\"foo\"]}' http://127.0.0.1:8081/activities/start\n"
+                ))
+                .arg(
+                    Arg::with_name("port")
+                        .long("port")
+                        .takes_value(true)
+                        .default_value("8081")
+                        .help("port (0 picks a free port)"),
+                )
+                .arg(
+                    Arg::with_name("host")
+                        .long("host")
+                        .takes_value(true)
+                        .default_value("127.0.0.1")
+                        .help("address to listen on"),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("rpc")
+                .about("Serve JSON-RPC 2.0 requests on stdin/stdout (one message per line)")
+                .after_help(concat!(
+                    "methods:\n",
+                    "get_ongoing_activities, filter_activities {from, to, tags}\n",
+                    "start_activity {tags, description, start, dry_run}\n",
+                    "stop_ongoing_activity {id, time, dry_run}, cancel_ongoing_activity {id, dry_run}\n",
+                    "track_activity {start, stop, tags, description, dry_run}, delete_activity {id, dry_run}\n",
+                    "notification: ongoing_changed {ongoing}\n",
+                    "example:\n",
+                    "echo '{\"jsonrpc\": \"2.0\", \"id\": 1, \"method\": \"get_ongoing_activities\"}' | rtw rpc\n"
+                ))
+                .arg(
+                    Arg::with_name("poll")
+                        .long("poll")
+                        .takes_value(true)
+                        .default_value("2s")
+                        .help("interval between checks of ongoing activities e.g. 500ms, 2s"),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("serve-ics")
+                .about("Serve an iCalendar feed of activities over HTTP")
+                .after_help(concat!(
+                    "query parameters:\n",
+                    "range=today|yesterday|week|lastweek, from=<time clue>, to=<time clue>\n",
+                    "tags=<tag>,<tag> (activities with one of these tags), ongoing=true\n",
+                    "examples:\n",
+                    "rtw serve-ics --port 8080\n",
+                    "curl 'http://127.0.0.1:8080/rtw.ics?range=lastweek&tags=meeting'\n"
+                ))
+                .arg(
+                    Arg::with_name("port")
+                        .long("port")
+                        .takes_value(true)
+                        .default_value("8080")
+                        .help("port (0 picks a free port)"),
+                )
+                .arg(
+                    Arg::with_name("host")
+                        .long("host")
+                        .takes_value(true)
+                        .default_value("127.0.0.1")
+                        .help("address to listen on"),
+                ),
+        )
         .subcommand(
             SubCommand::with_name("status")
                 .about("print status data, suitable for use in status bar or prompts")
@@ -321,9 +1120,9 @@ pub fn get_app() -> App<'static, 'static> {
                     Arg::with_name("format")
                         .long("format")
                         .takes_value(true)
-                        .help(concat!(
-                        "format string e.g. \"{id} {ongoing} {start} {human_duration} {duration}\""
-                    )),
+                        .help(
+                        "format string e.g. \"{id} {uid} {ongoing} {start} {human_duration} {duration} {net_duration} {paused}\"",
+                    ),
                 ),
         )
 }
@@ -331,8 +1130,9 @@ pub fn get_app() -> App<'static, 'static> {
 pub fn parse_start_args(
     start_m: &ArgMatches,
     clock: &dyn Clock,
-) -> anyhow::Result<(Time, Tags, Option<Description>)> {
+) -> anyhow::Result<(Time, Tags, Option<Description>, Metadata)> {
     let description = start_m.value_of("description").map(|s| s.to_string());
+    let metadata = parse_metadata(start_m)?;
     let values_arg = start_m.values_of("tokens"); // optional time clue, tags
     if let Some(values) = values_arg {
         let values: Tags = values.map(String::from).collect();
@@ -340,7 +1140,7 @@ pub fn parse_start_args(
         return if tags.is_empty() {
             Err(anyhow::anyhow!("no tags provided"))
         } else {
-            Ok((time, tags, description))
+            Ok((time, tags, description, metadata))
         };
     }
     Err(anyhow::anyhow!("neither time clue nor tags provided")) // it should be prevented by clap
@@ -349,21 +1149,25 @@ pub fn parse_start_args(
 pub fn parse_track_args(
     track_m: &ArgMatches,
     clock: &dyn Clock,
-) -> anyhow::Result<(Time, Time, Tags, Option<Description>)> {
+) -> anyhow::Result<(Time, Time, Tags, Option<Description>, Metadata)> {
     let description = track_m.value_of("description").map(|s| s.to_string());
+    let metadata = parse_metadata(track_m)?;
     let values_arg = track_m
         .values_of("tokens")
         .expect("start time, end time and at least 1 tag required");
     let values: Tags = values_arg.map(String::from).collect();
     let (range_start, range_end, activity_tags) = split_time_range_from_tags(&values, clock)?;
-    Ok((range_start, range_end, activity_tags, description))
+    Ok((range_start, range_end, activity_tags, description, metadata))
 }
 
 pub fn parse_stop_args(
     stop_m: &ArgMatches,
     clock: &dyn Clock,
-) -> anyhow::Result<(Time, Option<ActivityId>)> {
-    let stopped_id_maybe = stop_m.value_of("id").map(usize::from_str).transpose()?;
+) -> anyhow::Result<(Time, Option<ActivitySelector>)> {
+    let stopped_id_maybe = stop_m
+        .value_of("id")
+        .map(ActivitySelector::from_str)
+        .transpose()?;
     let time_arg = stop_m.values_of("time");
     if let Some(values) = time_arg {
         let values: Vec<String> = values.map(String::from).collect();
@@ -375,23 +1179,41 @@ pub fn parse_stop_args(
     }
 }
 
-pub fn parse_continue_args(continue_m: &ArgMatches) -> anyhow::Result<Option<ActivityId>> {
-    let continue_id_maybe = continue_m.value_of("id").map(usize::from_str).transpose()?;
+pub fn parse_continue_args(continue_m: &ArgMatches) -> anyhow::Result<Option<ActivitySelector>> {
+    let continue_id_maybe = continue_m
+        .value_of("id")
+        .map(ActivitySelector::from_str)
+        .transpose()?;
     Ok(continue_id_maybe)
 }
 
-pub fn parse_cancel_args(cancel_m: &ArgMatches) -> anyhow::Result<Option<ActivityId>> {
-    let cancelled_id_maybe = cancel_m.value_of("id").map(usize::from_str).transpose()?;
+pub fn parse_cancel_args(cancel_m: &ArgMatches) -> anyhow::Result<Option<ActivitySelector>> {
+    let cancelled_id_maybe = cancel_m
+        .value_of("id")
+        .map(ActivitySelector::from_str)
+        .transpose()?;
     Ok(cancelled_id_maybe)
 }
 
 pub fn parse_summary_args(
     summary_m: &ArgMatches,
     clock: &dyn Clock,
-) -> anyhow::Result<((DateTimeW, DateTimeW), bool, bool, bool)> {
-    let display_id = summary_m.is_present("id");
-    let report = summary_m.is_present("report");
-    let display_description = summary_m.is_present("description");
+) -> anyhow::Result<((DateTimeW, DateTimeW), SummaryOptions)> {
+    let options = SummaryOptions {
+        display_id: summary_m.is_present("id"),
+        display_uid: summary_m.is_present("uid"),
+        display_description: summary_m.is_present("description"),
+        report: summary_m.is_present("report"),
+        depth: parse_depth(summary_m)?,
+        by_tag: summary_m.is_present("by_tag"),
+        split_evenly: summary_m.is_present("split_evenly"),
+        tag_filter: TagFilter {
+            include: values_or_empty(summary_m, "include_tag"),
+            exclude: values_or_empty(summary_m, "exclude_tag"),
+        },
+        format: parse_output_format(summary_m),
+        metadata_filter: parse_metadata_filter(summary_m),
+    };
     let values_arg = summary_m.values_of("tokens");
     if let Some(values) = values_arg {
         let values: Vec<String> = values.map(String::from).collect();
@@ -400,12 +1222,7 @@ pub fn parse_summary_args(
             Ok((range_start, range_end)) => {
                 let range_start = clock.date_time(range_start);
                 let range_end = clock.date_time(range_end);
-                Ok((
-                    (range_start, range_end),
-                    display_id,
-                    display_description,
-                    report,
-                ))
+                Ok(((range_start, range_end), options))
             }
             Err(e) => Err(anyhow::anyhow!(e)),
         };
@@ -421,7 +1238,49 @@ pub fn parse_summary_args(
             clock.today_range()
         }
     };
-    Ok((range, display_id, display_description, report))
+    Ok((range, options))
+}
+
+pub fn parse_invoice_args(
+    invoice_m: &ArgMatches,
+    clock: &dyn Clock,
+) -> anyhow::Result<((DateTimeW, DateTimeW), String, InvoiceFormat)> {
+    let (range, _options) = parse_summary_args(invoice_m, clock)?;
+    let client = invoice_m
+        .value_of("client")
+        .expect("client is required")
+        .to_string();
+    let format = match invoice_m.value_of("format") {
+        Some("html") => InvoiceFormat::Html,
+        _ => InvoiceFormat::Markdown,
+    };
+    Ok((range, client, format))
+}
+
+pub fn parse_dump_args(
+    dump_m: &ArgMatches,
+    clock: &dyn Clock,
+) -> anyhow::Result<((DateTimeW, DateTimeW), DumpFormat, Option<PathBuf>)> {
+    let (range, _options) = parse_summary_args(dump_m, clock)?;
+    let format = match dump_m.value_of("format") {
+        Some("timewarrior") => DumpFormat::Timewarrior,
+        Some("csv") => DumpFormat::Csv(parse_csv_options(dump_m)?),
+        Some("json") => DumpFormat::Json,
+        Some("jsonl") => DumpFormat::JsonLines,
+        _ => DumpFormat::ICal(dump_m.is_present("ongoing")),
+    };
+    if dump_m.is_present("ongoing") && !matches!(format, DumpFormat::ICal(_)) {
+        return Err(anyhow::anyhow!(
+            "--ongoing is only supported by the ical format"
+        ));
+    }
+    let output_dir = dump_m.value_of("output_dir").map(PathBuf::from);
+    if output_dir.is_some() && format != DumpFormat::Timewarrior {
+        return Err(anyhow::anyhow!(
+            "--output-dir is only supported by the timewarrior format"
+        ));
+    }
+    Ok((range, format, output_dir))
 }
 
 pub fn parse_timeline_args(
@@ -446,8 +1305,8 @@ pub fn parse_timeline_args(
     }
 }
 
-pub fn parse_delete_args(delete_m: &ArgMatches) -> anyhow::Result<ActivityId> {
-    let id_opt = delete_m.value_of("id").map(usize::from_str);
+pub fn parse_delete_args(delete_m: &ArgMatches) -> anyhow::Result<ActivitySelector> {
+    let id_opt = delete_m.value_of("id").map(ActivitySelector::from_str);
     if let Some(Ok(id)) = id_opt {
         Ok(id)
     } else {
@@ -455,6 +1314,163 @@ pub fn parse_delete_args(delete_m: &ArgMatches) -> anyhow::Result<ActivityId> {
     }
 }
 
+fn parse_activity_edit(edit_m: &ArgMatches, clock: &dyn Clock) -> anyhow::Result<ActivityEdit> {
+    let parse_time = |arg: &str| -> anyhow::Result<Option<DateTimeW>> {
+        edit_m
+            .value_of(arg)
+            .map(|time_str| {
+                TimeTools::time_from_str(time_str, clock).map(|time| clock.date_time(time))
+            })
+            .transpose()
+    };
+    Ok(ActivityEdit {
+        start_time: parse_time("start")?,
+        stop_time: parse_time("stop")?,
+        tags: edit_m
+            .values_of("tags")
+            .map(|values| values.map(String::from).collect()),
+        description: edit_m.value_of("description").map(String::from),
+    })
+}
+
+pub fn parse_edit_args(
+    edit_m: &ArgMatches,
+    clock: &dyn Clock,
+) -> anyhow::Result<(ActivitySelector, ActivityEdit)> {
+    let id = parse_delete_args(edit_m)?;
+    let edit = parse_activity_edit(edit_m, clock)?;
+    Ok((id, edit))
+}
+
+pub fn parse_split_args(
+    split_m: &ArgMatches,
+    clock: &dyn Clock,
+) -> anyhow::Result<(ActivitySelector, Time, Option<Tags>)> {
+    let id = parse_delete_args(split_m)?;
+    let values: Tags = split_m
+        .values_of("tokens")
+        .expect("split time required")
+        .map(String::from)
+        .collect();
+    let (time, tags) = split_time_clue_from_tags(&values, clock);
+    if tags.len() == values.len() {
+        Err(anyhow::anyhow!("no split time provided"))
+    } else if tags.is_empty() {
+        Ok((id, time, None))
+    } else {
+        Ok((id, time, Some(tags)))
+    }
+}
+
+pub fn parse_merge_args(merge_m: &ArgMatches) -> anyhow::Result<MergeSelection> {
+    if let Some(gap) = merge_m.value_of("gap") {
+        Ok(MergeSelection::Gap(DurationW::from_str(gap)?))
+    } else {
+        let ids = merge_m
+            .values_of("id")
+            .expect("ids required")
+            .map(ActivitySelector::from_str)
+            .collect::<anyhow::Result<Vec<ActivitySelector>>>()?;
+        Ok(MergeSelection::Activities(ids))
+    }
+}
+
+pub fn parse_modify_args(
+    modify_m: &ArgMatches,
+    clock: &dyn Clock,
+) -> anyhow::Result<(Option<ActivitySelector>, ActivityEdit)> {
+    let modified_id_maybe = modify_m
+        .value_of("id")
+        .map(ActivitySelector::from_str)
+        .transpose()?;
+    let edit = parse_activity_edit(modify_m, clock)?;
+    Ok((modified_id_maybe, edit))
+}
+
+pub fn parse_import_args(
+    import_m: &ArgMatches,
+    clock: &dyn Clock,
+) -> anyhow::Result<(ImportSource, bool)> {
+    match import_m.subcommand() {
+        ("timewarrior", Some(sub_m)) => {
+            let dir = sub_m.value_of("dir").expect("data directory required");
+            Ok((
+                ImportSource::Timewarrior(PathBuf::from(dir)),
+                sub_m.is_present("skip_overlapping"),
+            ))
+        }
+        ("csv", Some(sub_m)) => {
+            let file = sub_m.value_of("file").expect("CSV file required");
+            Ok((
+                ImportSource::Csv(PathBuf::from(file), parse_csv_options(sub_m)?),
+                sub_m.is_present("skip_overlapping"),
+            ))
+        }
+        ("ics", Some(sub_m)) => {
+            let file = sub_m.value_of("file").expect("iCalendar file required");
+            let parse_time = |arg: &str| -> anyhow::Result<Option<DateTimeW>> {
+                sub_m
+                    .value_of(arg)
+                    .map(|time_str| {
+                        TimeTools::time_from_str(time_str, clock).map(|time| clock.date_time(time))
+                    })
+                    .transpose()
+            };
+            let filter = IcsFilter {
+                tags: sub_m
+                    .values_of("tags")
+                    .map(|values| values.map(String::from).collect())
+                    .unwrap_or_default(),
+                from: parse_time("from")?,
+                to: parse_time("to")?,
+            };
+            Ok((
+                ImportSource::Ics(PathBuf::from(file), filter),
+                sub_m.is_present("skip_overlapping"),
+            ))
+        }
+        _ => Err(anyhow::anyhow!("unknown import source")), // it should be prevented by clap
+    }
+}
+
+/// Name and arguments of an external subcommand e.g. `rtw report --month` (see `plugins`)
+pub fn parse_external_args(matches: &ArgMatches) -> Option<(String, Vec<OsString>)> {
+    match matches.subcommand() {
+        (name, Some(sub_m)) => {
+            if BUILTIN_SUBCOMMANDS.contains(&name) {
+                None
+            } else {
+                let args = sub_m
+                    .values_of_os("")
+                    .map_or_else(Vec::new, |values| values.map(OsString::from).collect());
+                Some((name.to_string(), args))
+            }
+        }
+        _ => None,
+    }
+}
+
+pub fn parse_serve_args(serve_m: &ArgMatches) -> anyhow::Result<(String, u16)> {
+    let host = serve_m.value_of("host").expect("host has a default value");
+    let port = serve_m.value_of("port").expect("port has a default value");
+    let port = u16::from_str(port).map_err(|_| anyhow::anyhow!("invalid port: {}", port))?;
+    Ok((host.to_string(), port))
+}
+
+pub fn parse_rpc_args(rpc_m: &ArgMatches) -> anyhow::Result<std::time::Duration> {
+    let poll = rpc_m.value_of("poll").expect("poll has a default value");
+    let invalid = || anyhow::anyhow!("invalid poll interval: {}", poll);
+    let (value, unit_millis) = match poll.strip_suffix("ms") {
+        Some(millis) => (millis, 1),
+        None => (poll.strip_suffix('s').unwrap_or(poll), 1000),
+    };
+    let value = u64::from_str(value).map_err(|_| invalid())?;
+    match value.checked_mul(unit_millis) {
+        None | Some(0) => Err(invalid()),
+        Some(millis) => Ok(std::time::Duration::from_millis(millis)),
+    }
+}
+
 pub fn parse_completion_args(completion_m: &ArgMatches) -> anyhow::Result<clap::Shell> {
     let shell_maybe = completion_m.value_of("shell");
     match shell_maybe {
@@ -477,7 +1493,8 @@ pub fn parse_status_args(status_m: &ArgMatches) -> Option<String> {
 mod tests {
     use crate::chrono_clock::ChronoClock;
     use crate::cli_helper::{
-        split_time_clue_from_tags, split_time_range, split_time_range_from_tags,
+        get_app, parse_rpc_args, similar_subcommands, split_time_clue_from_tags, split_time_range,
+        split_time_range_from_tags, TagFilter, BUILTIN_SUBCOMMANDS,
     };
     use crate::rtw_core::clock::Time;
     use crate::rtw_core::Tags;
@@ -583,4 +1600,61 @@ mod tests {
         assert!(time_range.is_ok());
         assert_eq!(time_range.unwrap().1, Time::Now)
     }
+
+    #[test]
+    fn test_tag_filter() {
+        let filter = TagFilter {
+            include: vec![String::from("client:acme"), String::from("review")],
+            exclude: vec![String::from("meeting")],
+        };
+        let tags = |tags: &[&str]| -> Tags { tags.iter().map(|t| t.to_string()).collect() };
+        assert!(filter.matches(&tags(&["review", "foo"]), ":"));
+        assert!(filter.matches(&tags(&["client:acme:backend"]), ":"));
+        assert!(!filter.matches(&tags(&["client:acmex"]), ":"));
+        assert!(!filter.matches(&tags(&["client:acme:backend"]), ""));
+        assert!(!filter.matches(&tags(&["review", "meeting"]), ":"));
+        assert!(!filter.matches(&tags(&["foo"]), ":"));
+        assert!(TagFilter::default().matches(&tags(&["foo"]), ":"));
+    }
+
+    #[test]
+    fn test_parse_rpc_args() {
+        let poll = |poll: &str| {
+            let matches = get_app().get_matches_from(vec!["rtw", "rpc", "--poll", poll]);
+            parse_rpc_args(matches.subcommand_matches("rpc").unwrap())
+        };
+        assert_eq!(poll("500ms").unwrap().as_millis(), 500);
+        assert_eq!(poll("2s").unwrap().as_millis(), 2000);
+        assert!(poll("0").is_err());
+        assert!(poll("18446744073709551615s").is_err());
+    }
+
+    #[test]
+    fn test_builtin_subcommands() {
+        let mut app = get_app();
+        // clap adds the help subcommand when building the app
+        let _ = app.get_matches_from_safe_borrow(vec!["rtw"]);
+        let mut help = vec![];
+        app.write_help(&mut help).unwrap();
+        let help = String::from_utf8(help).unwrap();
+        // subcommand names start the lines of the SUBCOMMANDS section
+        let mut names: Vec<&str> = help
+            .lines()
+            .skip_while(|line| *line != "SUBCOMMANDS:")
+            .skip(1)
+            .take_while(|line| !line.is_empty())
+            .filter(|line| line.starts_with("    ") && !line[4..].starts_with(' '))
+            .filter_map(|line| line.split_whitespace().next())
+            .collect();
+        names.sort_unstable();
+        let mut builtin = BUILTIN_SUBCOMMANDS.to_vec();
+        builtin.sort_unstable();
+        assert_eq!(names, builtin);
+    }
+
+    #[test]
+    fn test_similar_subcommands() {
+        assert_eq!(similar_subcommands("sumary").first(), Some(&"summary"));
+        assert!(similar_subcommands("team-report").is_empty());
+    }
 }
@@ -0,0 +1,352 @@
+//! CSV export and import of finished activities.
+//!
+//! Columns: `id,start,stop,duration,tags,description,breaks`
+//!
+//! * `id`: activity uid
+//! * `start`, `stop`: local times (see `CsvOptions::time_format`)
+//! * `duration`: `HH:MM:SS` without breaks (ignored on import)
+//! * `tags`: tags separated by spaces, tags with spaces or quotes are quoted as in Timewarrior data files
+//! * `breaks`: `start - stop` local times separated by `|`
+//!
+//! A `rounded_duration` column (`duration` rounded, see `rounding` config) is appended when rounding is configured.
+use crate::rtw_config::Rounding;
+use crate::rtw_core::activity::{Activity, Break, OngoingActivity};
+use crate::rtw_core::datetimew::DateTimeW;
+use crate::rtw_core::{ActivityUid, Tags, DATETIME_FMT};
+use crate::timewarrior::{format_tag, tokenize};
+use anyhow::anyhow;
+use chrono::{DateTime, Local, NaiveDateTime, TimeZone};
+use std::path::Path;
+use uuid::Uuid;
+
+/// Namespace used to derive uids of imported rows without id
+///
+/// Importing the same row twice yields the same uid.
+const CSV_UID_NAMESPACE: Uuid = Uuid::from_u128(0x51c4_0e7a_2b9d_4a83_9f16_c2e8_7d05_b3a4);
+
+const HEADER: [&str; 7] = [
+    "id",
+    "start",
+    "stop",
+    "duration",
+    "tags",
+    "description",
+    "breaks",
+];
+
+/// Separates breaks in the `breaks` column
+const BREAK_SEPARATOR: char = '|';
+
+/// Separates start and stop times of a break
+const BREAK_TIMES_SEPARATOR: &str = " - ";
+
+/// Appended to `HEADER` with rounding
+const ROUNDED_DURATION_COLUMN: &str = "rounded_duration";
+
+/// CSV delimiter and time format
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CsvOptions {
+    pub delimiter: char,
+    /// chrono format string e.g. `%Y-%m-%d %H:%M`
+    pub time_format: String,
+}
+
+impl Default for CsvOptions {
+    fn default() -> Self {
+        CsvOptions {
+            delimiter: ',',
+            time_format: DATETIME_FMT.to_string(),
+        }
+    }
+}
+
+fn format_field(field: &str, delimiter: char) -> String {
+    if field.contains([delimiter, '"', '\n', '\r']) {
+        format!("\"{}\"", field.replace('"', "\"\""))
+    } else {
+        field.to_string()
+    }
+}
+
+fn format_row(fields: &[String], delimiter: char) -> String {
+    fields
+        .iter()
+        .map(|field| format_field(field, delimiter))
+        .collect::<Vec<String>>()
+        .join(&delimiter.to_string())
+}
+
+fn format_datetime(d: DateTimeW, time_format: &str) -> String {
+    let local: DateTime<Local> = d.into();
+    local.format(time_format).to_string()
+}
+
+fn format_tags(tags: &[String]) -> String {
+    tags.iter()
+        .map(|tag| format_tag(tag))
+        .collect::<Vec<String>>()
+        .join(" ")
+}
+
+fn format_breaks(activity: &Activity, time_format: &str) -> String {
+    activity
+        .get_breaks()
+        .iter()
+        .map(|b| {
+            format!(
+                "{}{}{}",
+                format_datetime(b.start_time, time_format),
+                BREAK_TIMES_SEPARATOR,
+                format_datetime(b.stop_time.unwrap_or(activity.get_stop_time()), time_format)
+            )
+        })
+        .collect::<Vec<String>>()
+        .join(&BREAK_SEPARATOR.to_string())
+}
+
+/// Export activities to CSV (with header)
+pub(crate) fn export_activities_to_csv(
+    activities: &[Activity],
+    options: &CsvOptions,
+    rounding: Option<Rounding>,
+) -> String {
+    let mut header: Vec<String> = HEADER.iter().map(|column| column.to_string()).collect();
+    if rounding.is_some() {
+        header.push(ROUNDED_DURATION_COLUMN.to_string());
+    }
+    let mut csv = format_row(&header, options.delimiter);
+    csv.push('\n');
+    for activity in activities {
+        let mut row = vec![
+            activity.get_uid().to_string(),
+            format_datetime(activity.get_start_time(), &options.time_format),
+            format_datetime(activity.get_stop_time(), &options.time_format),
+            activity.get_net_duration().to_string(),
+            format_tags(&activity.get_tags()),
+            activity.get_description().unwrap_or_default(),
+            format_breaks(activity, &options.time_format),
+        ];
+        if let Some(rounding) = rounding {
+            row.push(rounding.round(activity.get_net_duration()).to_string());
+        }
+        csv.push_str(&format_row(&row, options.delimiter));
+        csv.push('\n');
+    }
+    csv
+}
+
+// Split CSV content into rows of fields (RFC 4180: quoted fields may contain delimiters, quotes and newlines).
+// Returns (line number, fields) pairs, empty lines are skipped.
+fn parse_rows(content: &str, delimiter: char) -> anyhow::Result<Vec<(usize, Vec<String>)>> {
+    let mut rows = vec![];
+    let mut fields: Vec<String> = vec![];
+    let mut field = String::new();
+    let mut line_number = 1;
+    let mut row_line_number = 1;
+    let mut chars = content.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '"' if field.is_empty() => loop {
+                match chars.next() {
+                    Some('"') if chars.peek() == Some(&'"') => {
+                        chars.next();
+                        field.push('"');
+                    }
+                    Some('"') => break,
+                    Some(c) => {
+                        if c == '\n' {
+                            line_number += 1;
+                        }
+                        field.push(c);
+                    }
+                    None => return Err(anyhow!("line {}: unterminated quote", row_line_number)),
+                }
+            },
+            '\r' if chars.peek() == Some(&'\n') => {}
+            '\n' => {
+                fields.push(std::mem::take(&mut field));
+                if fields.len() > 1 || !fields[0].is_empty() {
+                    rows.push((row_line_number, std::mem::take(&mut fields)));
+                }
+                fields.clear();
+                line_number += 1;
+                row_line_number = line_number;
+            }
+            c if c == delimiter => fields.push(std::mem::take(&mut field)),
+            c => field.push(c),
+        }
+    }
+    fields.push(field);
+    if fields.len() > 1 || !fields[0].is_empty() {
+        rows.push((row_line_number, fields));
+    }
+    Ok(rows)
+}
+
+fn parse_datetime(s: &str, time_format: &str) -> anyhow::Result<DateTimeW> {
+    let naive = NaiveDateTime::parse_from_str(s.trim(), time_format)
+        .map_err(|_| anyhow!("invalid time: {}", s))?;
+    Local
+        .from_local_datetime(&naive)
+        .single()
+        .map(DateTimeW::from)
+        .ok_or_else(|| anyhow!("ambiguous local time: {}", s))
+}
+
+fn parse_tags(s: &str) -> anyhow::Result<Tags> {
+    Ok(tokenize(s)?.into_iter().map(|(tag, _quoted)| tag).collect())
+}
+
+fn parse_breaks(s: &str, time_format: &str) -> anyhow::Result<Vec<Break>> {
+    s.split(BREAK_SEPARATOR)
+        .filter(|b| !b.trim().is_empty())
+        .map(|b| match b.split_once(BREAK_TIMES_SEPARATOR) {
+            Some((start, stop)) => Ok(Break {
+                start_time: parse_datetime(start, time_format)?,
+                stop_time: Some(parse_datetime(stop, time_format)?),
+            }),
+            None => Err(anyhow!("invalid break: {}", b.trim())),
+        })
+        .collect()
+}
+
+/// Read activities from CSV file `path`
+///
+/// Columns are found using the header (`start` and `stop` are required, unknown columns are ignored).
+///
+/// Returns valid activities and error messages of invalid rows.
+pub(crate) fn read_csv_activities(
+    path: &Path,
+    options: &CsvOptions,
+) -> anyhow::Result<(Vec<Activity>, Vec<String>)> {
+    let content = std::fs::read_to_string(path)?;
+    let mut rows = parse_rows(&content, options.delimiter)?.into_iter();
+    let header: Vec<String> = match rows.next() {
+        None => return Ok((vec![], vec![])),
+        Some((_line, header)) => header.iter().map(|h| h.trim().to_lowercase()).collect(),
+    };
+    let column = |name: &str| header.iter().position(|h| h == name);
+    let (start_column, stop_column) = match (column("start"), column("stop")) {
+        (Some(start), Some(stop)) => (start, stop),
+        _ => return Err(anyhow!("missing start or stop column in CSV header")),
+    };
+    let (id_column, tags_column, description_column, breaks_column) = (
+        column("id"),
+        column("tags"),
+        column("description"),
+        column("breaks"),
+    );
+    let mut activities = vec![];
+    let mut invalid_rows = vec![];
+    for (line_number, row) in rows {
+        let field = |index: Option<usize>| -> Option<&str> {
+            index
+                .and_then(|i| row.get(i))
+                .map(|f| f.as_str())
+                .filter(|f| !f.trim().is_empty())
+        };
+        let activity = (|| -> anyhow::Result<Activity> {
+            let start_time = parse_datetime(
+                field(Some(start_column)).ok_or_else(|| anyhow!("missing start"))?,
+                &options.time_format,
+            )?;
+            let stop_time = parse_datetime(
+                field(Some(stop_column)).ok_or_else(|| anyhow!("missing stop"))?,
+                &options.time_format,
+            )?;
+            let uid: ActivityUid = match field(id_column) {
+                Some(id) => {
+                    Uuid::parse_str(id.trim()).map_err(|_| anyhow!("invalid id: {}", id))?
+                }
+                None => Uuid::new_v5(&CSV_UID_NAMESPACE, row.join("\u{1f}").as_bytes()),
+            };
+            let tags = field(tags_column)
+                .map(parse_tags)
+                .transpose()?
+                .unwrap_or_default();
+            let description = field(description_column).map(String::from);
+            let breaks = field(breaks_column)
+                .map(|breaks| parse_breaks(breaks, &options.time_format))
+                .transpose()?
+                .unwrap_or_default();
+            OngoingActivity {
+                uid,
+                breaks,
+                ..OngoingActivity::new(start_time, tags, description)
+            }
+            .into_activity(stop_time)
+        })();
+        match activity {
+            Ok(activity) => activities.push(activity),
+            Err(e) => invalid_rows.push(format!("line {}: {}", line_number, e)),
+        }
+    }
+    Ok((activities, invalid_rows))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::csv::{export_activities_to_csv, parse_rows, read_csv_activities, CsvOptions};
+    use crate::rtw_config::{Rounding, RoundingMode, RoundingScope};
+    use crate::rtw_core::activity::OngoingActivity;
+    use crate::rtw_core::datetimew::DateTimeW;
+    use chrono::{Local, TimeZone};
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_parse_rows() {
+        let rows = parse_rows("a,\"b,\"\"c\"\"\"\n\n\"multi\nline\",d\r\n", ',').unwrap();
+        assert_eq!(
+            rows,
+            vec![
+                (1, vec![String::from("a"), String::from("b,\"c\"")]),
+                (3, vec![String::from("multi\nline"), String::from("d")]),
+            ]
+        );
+        assert!(parse_rows("\"unterminated", ',').is_err());
+    }
+
+    #[test]
+    fn test_export_then_import() {
+        let start: DateTimeW = Local.ymd(2020, 12, 25).and_hms(9, 0, 0).into();
+        let stop: DateTimeW = Local.ymd(2020, 12, 25).and_hms(10, 0, 0).into();
+        let activity = OngoingActivity::new(
+            start,
+            vec![String::from("foo"), String::from("bar baz")],
+            Some(String::from("a; \"quoted\" description")),
+        )
+        .pause(Local.ymd(2020, 12, 25).and_hms(9, 30, 0).into())
+        .unwrap()
+        .resume(Local.ymd(2020, 12, 25).and_hms(9, 45, 0).into())
+        .unwrap()
+        .into_activity(stop)
+        .unwrap();
+        let options = CsvOptions {
+            delimiter: ';',
+            time_format: String::from("%d/%m/%Y %H:%M"),
+        };
+        let csv = export_activities_to_csv(std::slice::from_ref(&activity), &options, None);
+        assert!(csv.starts_with("id;start;stop;duration;tags;description;breaks\n"));
+        assert!(
+            csv.contains(";25/12/2020 09:00;25/12/2020 10:00;00:45:00;\"foo \"\"bar baz\"\"\";")
+        );
+        assert!(csv.ends_with(";25/12/2020 09:30 - 25/12/2020 09:45\n"));
+        let test_dir = tempdir().expect("could not create temp directory");
+        let path = test_dir.path().join("activities.csv");
+        let invalid_row = "\n;25/12/2020 11:00;25/12/2020 10:00;;foo;\n";
+        std::fs::write(&path, format!("{}{}", csv, invalid_row)).unwrap();
+        let (activities, invalid_rows) = read_csv_activities(&path, &options).unwrap();
+        assert_eq!(activities, vec![activity.clone()]);
+        assert_eq!(invalid_rows.len(), 1);
+        assert!(invalid_rows[0].starts_with("line 4: "));
+        let rounding = Rounding {
+            increment_minutes: 30,
+            mode: RoundingMode::Up,
+            scope: RoundingScope::Activity,
+        };
+        let rounded = export_activities_to_csv(&[activity], &options, Some(rounding));
+        assert!(rounded
+            .starts_with("id;start;stop;duration;tags;description;breaks;rounded_duration\n"));
+        assert!(rounded.ends_with(";25/12/2020 09:30 - 25/12/2020 09:45;01:00:00\n"));
+    }
+}
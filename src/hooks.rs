@@ -0,0 +1,140 @@
+//! User hooks run before and after start, stop, track, cancel and delete.
+//!
+//! A hook receives the affected activity as JSON on stdin (see `ActivityJson`, without `id`)
+//! and the hook name (e.g. `pre_stop`) in `RTW_HOOK`.
+//! Hook output goes to stderr (stdout may be used by `rtw rpc`).
+use crate::json_export::ActivityJson;
+use crate::rtw_config::{HookSet, Hooks};
+use anyhow::anyhow;
+use serde_json::Value;
+use std::io::Write;
+use std::path::Path;
+use std::process::{Command, ExitStatus, Stdio};
+
+/// Mutations with hooks
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum HookEvent {
+    Start,
+    Stop,
+    Track,
+    Cancel,
+    Delete,
+}
+
+impl HookEvent {
+    fn name(self) -> &'static str {
+        match self {
+            HookEvent::Start => "start",
+            HookEvent::Stop => "stop",
+            HookEvent::Track => "track",
+            HookEvent::Cancel => "cancel",
+            HookEvent::Delete => "delete",
+        }
+    }
+
+    fn executable(self, hook_set: &HookSet) -> Option<&Path> {
+        match self {
+            HookEvent::Start => hook_set.start.as_deref(),
+            HookEvent::Stop => hook_set.stop.as_deref(),
+            HookEvent::Track => hook_set.track.as_deref(),
+            HookEvent::Cancel => hook_set.cancel.as_deref(),
+            HookEvent::Delete => hook_set.delete.as_deref(),
+        }
+    }
+}
+
+impl Hooks {
+    /// True if a pre or post hook is configured for `event`
+    pub(crate) fn is_hooked(&self, event: HookEvent) -> bool {
+        event.executable(&self.pre).is_some() || event.executable(&self.post).is_some()
+    }
+
+    /// Run the pre hook of `event` if any, fails if the hook fails (the mutation must be aborted)
+    pub(crate) fn run_pre(&self, event: HookEvent, activity: &ActivityJson) -> anyhow::Result<()> {
+        match event.executable(&self.pre) {
+            None => Ok(()),
+            Some(executable) => {
+                let hook = format!("pre_{}", event.name());
+                match run_hook(executable, &hook, activity) {
+                    Ok(status) if status.success() => Ok(()),
+                    Ok(status) => Err(anyhow!(
+                        "{} hook failed ({}), {} aborted",
+                        hook,
+                        status,
+                        event.name()
+                    )),
+                    Err(e) => Err(anyhow!(
+                        "{} hook {}: {}, {} aborted",
+                        hook,
+                        executable.display(),
+                        e,
+                        event.name()
+                    )),
+                }
+            }
+        }
+    }
+
+    /// Run the post hook of `event` if any, failures are only reported (the mutation is done)
+    pub(crate) fn run_post(&self, event: HookEvent, activity: &ActivityJson) {
+        if let Some(executable) = event.executable(&self.post) {
+            let hook = format!("post_{}", event.name());
+            match run_hook(executable, &hook, activity) {
+                Ok(status) if status.success() => {}
+                Ok(status) => eprintln!("{} hook failed ({})", hook, status),
+                Err(e) => eprintln!("{} hook {}: {}", hook, executable.display(), e),
+            }
+        }
+    }
+}
+
+fn run_hook(executable: &Path, hook: &str, activity: &ActivityJson) -> anyhow::Result<ExitStatus> {
+    let mut activity = serde_json::to_value(activity)?;
+    if let Value::Object(fields) = &mut activity {
+        // ids are relative to the state before or after the change, they are meaningless here
+        fields.remove("id");
+    }
+    let mut child = Command::new(executable)
+        .env("RTW_HOOK", hook)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::from(std::io::stderr()))
+        .spawn()?;
+    if let Some(mut stdin) = child.stdin.take() {
+        // the hook may exit without reading stdin
+        let _ = writeln!(stdin, "{}", activity);
+    }
+    Ok(child.wait()?)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::hooks::HookEvent;
+    use crate::json_export::ActivityJson;
+    use crate::rtw_config::{HookSet, Hooks};
+    use crate::rtw_core::activity::OngoingActivity;
+    use crate::rtw_core::datetimew::DateTimeW;
+    use chrono::{Local, TimeZone};
+    use std::path::PathBuf;
+
+    #[test]
+    fn test_run_pre() {
+        let start: DateTimeW = Local.ymd(2020, 12, 25).and_hms(9, 0, 0).into();
+        let ongoing = OngoingActivity::new(start, vec![String::from("foo")], None);
+        let activity = ActivityJson::from_ongoing(0, &ongoing, start);
+        let hooks = Hooks {
+            pre: HookSet {
+                start: Some(PathBuf::from("true")),
+                stop: Some(PathBuf::from("false")),
+                cancel: Some(PathBuf::from("/nonexistent/hook")),
+                ..HookSet::default()
+            },
+            ..Hooks::default()
+        };
+        assert!(hooks.is_hooked(HookEvent::Start));
+        assert!(!hooks.is_hooked(HookEvent::Track));
+        assert!(hooks.run_pre(HookEvent::Start, &activity).is_ok());
+        assert!(hooks.run_pre(HookEvent::Track, &activity).is_ok());
+        assert!(hooks.run_pre(HookEvent::Stop, &activity).is_err());
+        assert!(hooks.run_pre(HookEvent::Cancel, &activity).is_err());
+    }
+}
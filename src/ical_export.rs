@@ -1,6 +1,6 @@
 use crate::rtw_core::activity::Activity;
 use crate::rtw_core::datetimew::DateTimeW;
-use chrono::{DateTime, Local};
+use chrono::{DateTime, Local, Utc};
 use icalendar::Calendar;
 use icalendar::CalendarDateTime;
 use icalendar::Component;
@@ -9,28 +9,45 @@ use icalendar::Event;
 impl From<DateTimeW> for CalendarDateTime {
     fn from(d: DateTimeW) -> Self {
         let local: DateTime<Local> = d.into();
-        local.naive_utc().into()
+        // UTC times (`Z` suffix) are not shifted by calendar apps
+        local.with_timezone(&Utc).into()
     }
 }
 
+/// Non-standard property holding one metadata field (`key=value`), read back by `ical_import`
+pub(crate) const METADATA_PROPERTY: &str = "X-RTW-METADATA";
+
+// Escape TEXT values (RFC 5545 3.3.11), newlines are escaped by `icalendar`
+fn escape_text(s: &str) -> String {
+    s.replace('\\', "\\\\")
+        .replace(';', "\\;")
+        .replace(',', "\\,")
+}
+
 impl From<Activity> for Event {
     fn from(a: Activity) -> Self {
-        let start_time = a.get_start_time();
-        let stop_time = a.get_stop_time();
-        let title = a.get_title();
-        match a.get_description() {
-            None => Event::new()
-                .summary(title.as_str())
-                .starts(start_time)
-                .ends(stop_time)
-                .done(),
-            Some(description) => Event::new()
-                .summary(title.as_str())
-                .description(&description)
-                .starts(start_time)
-                .ends(stop_time)
-                .done(),
+        let mut event = Event::new();
+        // the activity uid is stable, re-importing the calendar updates events instead of duplicating them
+        event
+            .uid(&a.get_uid().to_string())
+            .summary(&escape_text(&a.get_title()))
+            .starts(a.get_start_time())
+            .ends(a.get_stop_time());
+        let tags = a.get_tags();
+        if !tags.is_empty() {
+            let categories: Vec<String> = tags.iter().map(|tag| escape_text(tag)).collect();
+            event.add_property("CATEGORIES", &categories.join(","));
+        }
+        if let Some(description) = a.get_description() {
+            event.description(&escape_text(&description));
+        }
+        for (key, value) in a.get_metadata() {
+            event.add_multi_property(
+                METADATA_PROPERTY,
+                &escape_text(&format!("{}={}", key, value)),
+            );
         }
+        event.done()
     }
 }
 
@@ -42,3 +59,57 @@ pub(crate) fn export_activities_to_ical(activities: &[Activity]) -> Calendar {
     }
     calendar
 }
+
+#[cfg(test)]
+mod tests {
+    use crate::ical_export::export_activities_to_ical;
+    use crate::ical_import::{read_ics_activities, IcsFilter};
+    use crate::rtw_core::activity::OngoingActivity;
+    use crate::rtw_core::datetimew::DateTimeW;
+    use chrono::{Local, TimeZone, Utc};
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_export_then_import() {
+        let start: DateTimeW = Utc
+            .ymd(2020, 12, 25)
+            .and_hms(8, 0, 0)
+            .with_timezone(&Local)
+            .into();
+        let stop: DateTimeW = Utc
+            .ymd(2020, 12, 25)
+            .and_hms(9, 0, 0)
+            .with_timezone(&Local)
+            .into();
+        let activity = OngoingActivity::new(
+            start,
+            vec![String::from("foo"), String::from("b,ar")],
+            Some(String::from("notes; more\nnotes")),
+        )
+        .with_metadata(
+            vec![
+                (String::from("ticket"), String::from("ABC-123")),
+                (String::from("client"), String::from("acme, inc")),
+            ]
+            .into_iter()
+            .collect(),
+        )
+        .into_activity(stop)
+        .unwrap();
+        let calendar = export_activities_to_ical(std::slice::from_ref(&activity)).to_string();
+        assert!(calendar.contains(&format!("UID:{}\r\n", activity.get_uid())));
+        assert!(calendar.contains("DTSTART:20201225T080000Z\r\n"));
+        assert!(calendar.contains("DTEND:20201225T090000Z\r\n"));
+        assert!(calendar.contains("CATEGORIES:foo,b\\,ar\r\n"));
+        assert!(calendar.contains("DESCRIPTION:notes\\; more\\nnotes\r\n"));
+        assert!(calendar.contains("X-RTW-METADATA:client=acme\\, inc\r\n"));
+        assert!(calendar.contains("X-RTW-METADATA:ticket=ABC-123\r\n"));
+        let test_dir = tempdir().expect("could not create temp directory");
+        let path = test_dir.path().join("calendar.ics");
+        std::fs::write(&path, calendar).unwrap();
+        let (activities, invalid_events) =
+            read_ics_activities(&path, &IcsFilter::default()).unwrap();
+        assert!(invalid_events.is_empty());
+        assert_eq!(activities, vec![activity]);
+    }
+}
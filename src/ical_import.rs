@@ -0,0 +1,415 @@
+//! Import finished activities from iCalendar (ics) files.
+//!
+//! Each VEVENT becomes an activity: CATEGORIES (or SUMMARY words) → tags, DESCRIPTION → description,
+//! DTSTART/DTEND (or DURATION) → start and stop times, X-RTW-METADATA → metadata.
+//! Recurring events are not expanded.
+use crate::ical_export::METADATA_PROPERTY;
+use crate::rtw_core::activity::{Activity, OngoingActivity};
+use crate::rtw_core::datetimew::DateTimeW;
+use crate::rtw_core::{ActivityUid, Description, Metadata, Tags};
+use anyhow::anyhow;
+use chrono::{Duration, Local, NaiveDateTime, TimeZone, Utc};
+use chrono_tz::Tz;
+use std::path::Path;
+use uuid::Uuid;
+
+/// Namespace used to derive uids of imported events
+///
+/// Importing the same event twice yields the same uid.
+const ICS_UID_NAMESPACE: Uuid = Uuid::from_u128(0xc7a2_61f0_8e3b_4d59_b0a7_5f12_e94c_3d68);
+
+/// Keep events matching these filters
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct IcsFilter {
+    /// Keep events with at least one of these tags (all events if empty)
+    pub tags: Tags,
+    /// Keep events starting at or after this time
+    pub from: Option<DateTimeW>,
+    /// Keep events starting at or before this time
+    pub to: Option<DateTimeW>,
+}
+
+impl IcsFilter {
+    fn matches(&self, activity: &Activity) -> bool {
+        let start_time = activity.get_start_time();
+        (self.tags.is_empty() || activity.get_tags().iter().any(|t| self.tags.contains(t)))
+            && self.from.is_none_or(|from| from <= start_time)
+            && self.to.is_none_or(|to| start_time <= to)
+    }
+}
+
+/// A content line: `NAME;PARAM=VALUE:value`
+#[derive(Debug, Clone, PartialEq)]
+struct Property {
+    name: String,
+    params: Vec<(String, String)>,
+    value: String,
+}
+
+impl Property {
+    fn param(&self, name: &str) -> Option<&str> {
+        self.params
+            .iter()
+            .find(|(param, _)| param == name)
+            .map(|(_, value)| value.as_str())
+    }
+}
+
+// Join folded lines (continuation lines start with a space or a tab).
+// Returns (line number, unfolded line) pairs.
+fn unfold(content: &str) -> Vec<(usize, String)> {
+    let mut lines: Vec<(usize, String)> = vec![];
+    for (line_number, line) in content.lines().enumerate() {
+        match (
+            line.strip_prefix(' ').or_else(|| line.strip_prefix('\t')),
+            lines.last_mut(),
+        ) {
+            (Some(continuation), Some((_, previous))) => previous.push_str(continuation),
+            _ => lines.push((line_number + 1, line.to_string())),
+        }
+    }
+    lines
+}
+
+fn parse_property(line: &str) -> anyhow::Result<Property> {
+    // the value starts after the first colon which is not inside a quoted parameter value
+    let mut quoted = false;
+    let colon = line
+        .char_indices()
+        .find(|(_, c)| {
+            if *c == '"' {
+                quoted = !quoted;
+            }
+            *c == ':' && !quoted
+        })
+        .map(|(i, _)| i)
+        .ok_or_else(|| anyhow!("invalid content line: {}", line))?;
+    let (name_and_params, value) = (&line[..colon], &line[colon + 1..]);
+    let mut parts = name_and_params.split(';');
+    let name = parts.next().unwrap_or("").to_uppercase();
+    let params = parts
+        .filter_map(|param| {
+            param
+                .split_once('=')
+                .map(|(name, value)| (name.to_uppercase(), value.trim_matches('"').to_string()))
+        })
+        .collect();
+    Ok(Property {
+        name,
+        params,
+        value: value.to_string(),
+    })
+}
+
+fn unescape_text(s: &str) -> String {
+    let mut unescaped = String::new();
+    let mut chars = s.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' => match chars.next() {
+                Some('n') | Some('N') => unescaped.push('\n'),
+                Some(escaped) => unescaped.push(escaped),
+                None => {}
+            },
+            c => unescaped.push(c),
+        }
+    }
+    unescaped
+}
+
+// Split a list value (e.g. CATEGORIES) on unescaped commas and unescape each item.
+fn split_text_list(s: &str) -> Vec<String> {
+    let mut items = vec![];
+    let mut item = String::new();
+    let mut chars = s.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' => {
+                item.push(c);
+                if let Some(escaped) = chars.next() {
+                    item.push(escaped);
+                }
+            }
+            ',' => items.push(unescape_text(&std::mem::take(&mut item))),
+            c => item.push(c),
+        }
+    }
+    items.push(unescape_text(&item));
+    items
+}
+
+fn parse_datetime(property: &Property) -> anyhow::Result<DateTimeW> {
+    let value = property.value.trim();
+    if property.param("VALUE") == Some("DATE") || !value.contains('T') {
+        return Err(anyhow!("all-day events are not supported"));
+    }
+    let (naive_value, utc) = match value.strip_suffix('Z') {
+        Some(naive_value) => (naive_value, true),
+        None => (value, false),
+    };
+    let naive = NaiveDateTime::parse_from_str(naive_value, "%Y%m%dT%H%M%S")
+        .map_err(|_| anyhow!("invalid datetime: {}", value))?;
+    let local = if utc {
+        Utc.from_utc_datetime(&naive).with_timezone(&Local)
+    } else if let Some(tzid) = property.param("TZID") {
+        let tz: Tz = tzid
+            .parse()
+            .map_err(|_| anyhow!("unknown time zone: {}", tzid))?;
+        tz.from_local_datetime(&naive)
+            .earliest()
+            .ok_or_else(|| anyhow!("invalid local time: {} ({})", value, tzid))?
+            .with_timezone(&Local)
+    } else {
+        // floating time
+        Local
+            .from_local_datetime(&naive)
+            .earliest()
+            .ok_or_else(|| anyhow!("invalid local time: {}", value))?
+    };
+    Ok(local.into())
+}
+
+// Parse durations such as `PT1H30M`, `P1D` or `-PT15M`
+fn parse_duration(s: &str) -> anyhow::Result<Duration> {
+    let invalid = || anyhow!("invalid duration: {}", s);
+    let (sign, rest) = match s.trim().strip_prefix('-') {
+        Some(rest) => (-1, rest),
+        None => (1, s.trim().trim_start_matches('+')),
+    };
+    let rest = rest.strip_prefix('P').ok_or_else(invalid)?;
+    let mut seconds = 0;
+    let mut number = String::new();
+    for c in rest.chars() {
+        match c {
+            'T' => {}
+            '0'..='9' => number.push(c),
+            unit => {
+                let value: i64 = number.parse().map_err(|_| invalid())?;
+                number.clear();
+                seconds += value
+                    * match unit {
+                        'W' => 7 * 24 * 3600,
+                        'D' => 24 * 3600,
+                        'H' => 3600,
+                        'M' => 60,
+                        'S' => 1,
+                        _ => return Err(invalid()),
+                    };
+            }
+        }
+    }
+    if number.is_empty() {
+        Ok(Duration::seconds(sign * seconds))
+    } else {
+        Err(invalid())
+    }
+}
+
+fn event_uid(properties: &[Property]) -> ActivityUid {
+    let get = |name: &str| {
+        properties
+            .iter()
+            .find(|p| p.name == name)
+            .map(|p| p.value.trim())
+    };
+    match (get("UID"), get("RECURRENCE-ID")) {
+        (Some(uid), None) => Uuid::parse_str(uid)
+            .unwrap_or_else(|_| Uuid::new_v5(&ICS_UID_NAMESPACE, uid.as_bytes())),
+        (Some(uid), Some(recurrence_id)) => Uuid::new_v5(
+            &ICS_UID_NAMESPACE,
+            format!("{}/{}", uid, recurrence_id).as_bytes(),
+        ),
+        (None, _) => {
+            let content: Vec<String> = properties
+                .iter()
+                .filter(|p| p.name != "DTSTAMP")
+                .map(|p| format!("{}:{}", p.name, p.value))
+                .collect();
+            Uuid::new_v5(&ICS_UID_NAMESPACE, content.join("\n").as_bytes())
+        }
+    }
+}
+
+fn event_to_activity(properties: &[Property]) -> anyhow::Result<Activity> {
+    let get = |name: &str| properties.iter().find(|p| p.name == name);
+    let start_time = parse_datetime(get("DTSTART").ok_or_else(|| anyhow!("missing DTSTART"))?)?;
+    let stop_time = match (get("DTEND"), get("DURATION")) {
+        (Some(dtend), _) => parse_datetime(dtend)?,
+        (None, Some(duration)) => {
+            let start: chrono::DateTime<Local> = start_time.into();
+            (start + parse_duration(&duration.value)?).into()
+        }
+        (None, None) => return Err(anyhow!("missing DTEND")),
+    };
+    let categories: Tags = properties
+        .iter()
+        .filter(|p| p.name == "CATEGORIES")
+        .flat_map(|p| split_text_list(&p.value))
+        .map(|tag| tag.trim().to_string())
+        .filter(|tag| !tag.is_empty())
+        .collect();
+    let tags: Tags = if categories.is_empty() {
+        get("SUMMARY")
+            .map(|summary| {
+                unescape_text(&summary.value)
+                    .split_whitespace()
+                    .map(String::from)
+                    .collect()
+            })
+            .unwrap_or_default()
+    } else {
+        categories
+    };
+    let description: Option<Description> = get("DESCRIPTION")
+        .map(|description| unescape_text(&description.value))
+        .filter(|description| !description.is_empty());
+    let metadata: Metadata = properties
+        .iter()
+        .filter(|p| p.name == METADATA_PROPERTY)
+        .filter_map(|p| {
+            unescape_text(&p.value)
+                .split_once('=')
+                .map(|(key, value)| (key.to_string(), value.to_string()))
+        })
+        .collect();
+    OngoingActivity {
+        uid: event_uid(properties),
+        metadata,
+        ..OngoingActivity::new(start_time, tags, description)
+    }
+    .into_activity(stop_time)
+}
+
+/// Read events of iCalendar file `path` matching `filter`
+///
+/// Returns activities and error messages of invalid events.
+pub(crate) fn read_ics_activities(
+    path: &Path,
+    filter: &IcsFilter,
+) -> anyhow::Result<(Vec<Activity>, Vec<String>)> {
+    let content = std::fs::read_to_string(path)?;
+    let mut activities = vec![];
+    let mut invalid_events = vec![];
+    // (line number of BEGIN:VEVENT, event properties) while inside an event
+    let mut event: Option<(usize, Vec<Property>)> = None;
+    // nested components inside an event (e.g. VALARM) are ignored
+    let mut nested_depth = 0;
+    for (line_number, line) in unfold(&content) {
+        if line.trim().is_empty() {
+            continue;
+        }
+        let property = parse_property(&line)
+            .map_err(|e| anyhow!("{}:{}: {}", path.display(), line_number, e))?;
+        let value = property.value.trim().to_uppercase();
+        match (property.name.as_str(), event.as_mut()) {
+            ("BEGIN", None) if value == "VEVENT" => event = Some((line_number, vec![])),
+            ("BEGIN", Some(_)) => nested_depth += 1,
+            ("END", Some(_)) if nested_depth > 0 => nested_depth -= 1,
+            ("END", Some(_)) if value == "VEVENT" => {
+                if let Some((event_line_number, properties)) = event.take() {
+                    match event_to_activity(&properties) {
+                        Ok(activity) if filter.matches(&activity) => activities.push(activity),
+                        Ok(_filtered_out) => {}
+                        Err(e) => invalid_events.push(format!("line {}: {}", event_line_number, e)),
+                    }
+                }
+            }
+            (_, Some((_, properties))) if nested_depth == 0 => properties.push(property),
+            _ => {}
+        }
+    }
+    Ok((activities, invalid_events))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::ical_import::{parse_duration, read_ics_activities, IcsFilter};
+    use crate::rtw_core::datetimew::DateTimeW;
+    use chrono::{TimeZone, Utc};
+    use tempfile::tempdir;
+
+    const ICS: &str = "BEGIN:VCALENDAR\r
+VERSION:2.0\r
+BEGIN:VEVENT\r
+UID:meeting-1@example.com\r
+SUMMARY:meeting weekly\r
+DESCRIPTION:agenda:\\n- planning\\, review\r
+DTSTART;TZID=Europe/Paris:20201225T090000\r
+DTEND;TZID=Europe/Paris:20201225T10\r
+ 0000\r
+BEGIN:VALARM\r
+DESCRIPTION:reminder\r
+END:VALARM\r
+END:VEVENT\r
+BEGIN:VEVENT\r
+UID:meeting-2@example.com\r
+SUMMARY:lunch with team\r
+CATEGORIES:lunch,team\\, sales\r
+DTSTART:20201225T120000Z\r
+DURATION:PT45M\r
+END:VEVENT\r
+BEGIN:VEVENT\r
+UID:holiday@example.com\r
+SUMMARY:holiday\r
+DTSTART;VALUE=DATE:20201225\r
+END:VEVENT\r
+END:VCALENDAR\r
+";
+
+    #[test]
+    fn test_parse_duration() {
+        assert_eq!(parse_duration("PT1H30M").unwrap().num_minutes(), 90);
+        assert_eq!(parse_duration("P1DT1S").unwrap().num_seconds(), 86401);
+        assert_eq!(parse_duration("-PT15M").unwrap().num_minutes(), -15);
+        assert!(parse_duration("1H").is_err());
+        assert!(parse_duration("PT1").is_err());
+    }
+
+    #[test]
+    fn test_read_ics_activities() {
+        let test_dir = tempdir().expect("could not create temp directory");
+        let path = test_dir.path().join("calendar.ics");
+        std::fs::write(&path, ICS).unwrap();
+        let (activities, invalid_events) =
+            read_ics_activities(&path, &IcsFilter::default()).unwrap();
+        assert_eq!(activities.len(), 2);
+        let meeting = &activities[0];
+        assert_eq!(meeting.get_title(), "meeting weekly");
+        assert_eq!(
+            meeting.get_description(),
+            Some(String::from("agenda:\n- planning, review"))
+        );
+        let start: DateTimeW = Utc
+            .ymd(2020, 12, 25)
+            .and_hms(8, 0, 0)
+            .with_timezone(&chrono::Local)
+            .into();
+        assert_eq!(meeting.get_start_time(), start);
+        assert_eq!(meeting.get_duration().to_string(), "01:00:00");
+        assert_eq!(activities[1].get_duration().to_string(), "00:45:00");
+        assert_eq!(
+            activities[1].get_tags(),
+            vec![String::from("lunch"), String::from("team, sales")]
+        );
+        assert_eq!(
+            invalid_events,
+            vec![String::from("line 21: all-day events are not supported")]
+        );
+        // same uids when imported again
+        let (again, _) = read_ics_activities(&path, &IcsFilter::default()).unwrap();
+        assert_eq!(again[0].get_uid(), meeting.get_uid());
+        let filter = IcsFilter {
+            tags: vec![String::from("lunch")],
+            ..IcsFilter::default()
+        };
+        let (lunch, _) = read_ics_activities(&path, &filter).unwrap();
+        assert_eq!(lunch.len(), 1);
+        let filter = IcsFilter {
+            from: Some(start),
+            to: Some(start),
+            ..IcsFilter::default()
+        };
+        let (meetings, _) = read_ics_activities(&path, &filter).unwrap();
+        assert_eq!(meetings.len(), 1);
+    }
+}
@@ -0,0 +1,180 @@
+//! iCalendar subscription feed served over HTTP (`rtw serve-ics`).
+//!
+//! Every request rebuilds the calendar from storage (see `export_activities_to_ical`).
+use crate::ical_export::export_activities_to_ical;
+use crate::rtw_core::activity::Activity;
+use crate::rtw_core::clock::Clock;
+use crate::rtw_core::datetimew::DateTimeW;
+use crate::rtw_core::service::ActivityService;
+use crate::rtw_core::Tags;
+use crate::time_tools::TimeTools;
+use anyhow::anyhow;
+use chrono::{Local, TimeZone};
+use tiny_http::{Header, Method, Request, Response, Server};
+
+/// Feed query parameters e.g. `/?range=lastweek&tags=foo,bar&ongoing=true`
+#[derive(Debug, Clone, Default, PartialEq)]
+pub(crate) struct FeedQuery {
+    /// Keep activities started within this range (all activities if `None`)
+    pub range: Option<(DateTimeW, DateTimeW)>,
+    /// Keep activities with at least one of these tags (all activities if empty)
+    pub tags: Tags,
+    /// Include ongoing activities as events ending now
+    pub ongoing: bool,
+}
+
+/// Parse query string `query` (without `?`)
+///
+/// * `range`: `today`, `yesterday`, `week` or `lastweek`
+/// * `from`, `to`: time clues e.g. `last monday` (`to` defaults to now)
+/// * `tags`: comma separated tags, may be repeated
+/// * `ongoing`: `true` or `false`
+pub(crate) fn parse_feed_query(query: &str, clock: &dyn Clock) -> anyhow::Result<FeedQuery> {
+    let mut feed_query = FeedQuery::default();
+    let (mut from, mut to) = (None, None);
+    let parse_time = |time_str: &str| -> anyhow::Result<DateTimeW> {
+        TimeTools::time_from_str(time_str, clock).map(|time| clock.date_time(time))
+    };
+    for (key, value) in form_urlencoded::parse(query.as_bytes()) {
+        match key.as_ref() {
+            "range" => {
+                feed_query.range = Some(match value.as_ref() {
+                    "today" => clock.today_range(),
+                    "yesterday" => clock.yesterday_range(),
+                    "week" => clock.this_week_range(),
+                    "lastweek" => clock.last_week_range(),
+                    other => return Err(anyhow!("invalid range: {}", other)),
+                })
+            }
+            "from" => from = Some(parse_time(&value)?),
+            "to" => to = Some(parse_time(&value)?),
+            "tags" => feed_query.tags.extend(
+                value
+                    .split(',')
+                    .map(str::trim)
+                    .filter(|tag| !tag.is_empty())
+                    .map(String::from),
+            ),
+            "ongoing" => {
+                feed_query.ongoing = value
+                    .parse()
+                    .map_err(|_| anyhow!("invalid ongoing value: {}", value))?
+            }
+            other => return Err(anyhow!("unknown query parameter: {}", other)),
+        }
+    }
+    match (from, to, feed_query.range.is_some()) {
+        (None, None, _) => {}
+        (_, _, true) => return Err(anyhow!("range cannot be combined with from/to")),
+        (from, to, false) => {
+            feed_query.range = Some((
+                from.unwrap_or_else(|| Local.timestamp(0, 0).into()),
+                to.unwrap_or_else(|| clock.get_time()),
+            ))
+        }
+    }
+    Ok(feed_query)
+}
+
+/// Activities of the feed, finished activities first
+pub(crate) fn feed_activities<S: ActivityService>(
+    service: &S,
+    query: &FeedQuery,
+    now: DateTimeW,
+) -> anyhow::Result<Vec<Activity>> {
+    let mut activities: Vec<Activity> = match query.range {
+        None => service.get_finished_activities()?,
+        Some((range_start, range_end)) => {
+            service.filter_activities_by_range(range_start, range_end)?
+        }
+    }
+    .into_iter()
+    .map(|(_id, a)| a)
+    .collect();
+    if query.ongoing {
+        activities.extend(
+            service
+                .get_ongoing_activities()?
+                .into_iter()
+                .filter(|(_id, a)| {
+                    query
+                        .range
+                        .is_none_or(|(start, end)| start <= a.start_time && a.start_time <= end)
+                })
+                .filter_map(|(_id, a)| a.into_activity(now).ok()),
+        );
+    }
+    activities
+        .retain(|a| query.tags.is_empty() || a.get_tags().iter().any(|t| query.tags.contains(t)));
+    Ok(activities)
+}
+
+fn respond<S: ActivityService>(
+    request: &Request,
+    service: &S,
+    clock: &dyn Clock,
+) -> Response<std::io::Cursor<Vec<u8>>> {
+    if request.method() != &Method::Get {
+        return Response::from_string("method not allowed\n").with_status_code(405);
+    }
+    let query = request.url().split_once('?').map_or("", |(_path, q)| q);
+    let feed_query = match parse_feed_query(query, clock) {
+        Ok(feed_query) => feed_query,
+        Err(e) => return Response::from_string(format!("{}\n", e)).with_status_code(400),
+    };
+    match feed_activities(service, &feed_query, clock.get_time()) {
+        Ok(activities) => {
+            let calendar = export_activities_to_ical(&activities);
+            let content_type = Header::from_bytes("Content-Type", "text/calendar; charset=utf-8")
+                .expect("valid header");
+            Response::from_string(calendar.to_string()).with_header(content_type)
+        }
+        Err(e) => Response::from_string(format!("{}\n", e)).with_status_code(500),
+    }
+}
+
+/// Serve the feed on `host:port` until the process is killed
+///
+/// Port 0 picks a free port, the actual address is printed.
+pub(crate) fn serve_ics<S: ActivityService>(
+    service: &S,
+    clock: &dyn Clock,
+    host: &str,
+    port: u16,
+) -> anyhow::Result<()> {
+    let server = Server::http((host, port)).map_err(|e| anyhow!(e))?;
+    match server.server_addr().to_ip() {
+        Some(address) => println!("Serving iCalendar feed on http://{}/", address),
+        None => println!("Serving iCalendar feed"),
+    }
+    for request in server.incoming_requests() {
+        let response = respond(&request, service, clock);
+        if let Err(e) = request.respond(response) {
+            eprintln!("could not send response: {}", e);
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::chrono_clock::ChronoClock;
+    use crate::ics_feed::parse_feed_query;
+    use crate::rtw_core::clock::Clock;
+
+    #[test]
+    fn test_parse_feed_query() {
+        let clock = ChronoClock {};
+        let query = parse_feed_query("range=lastweek&tags=foo,bar&tags=baz", &clock).unwrap();
+        assert_eq!(query.range, Some(clock.last_week_range()));
+        assert_eq!(query.tags, vec!["foo", "bar", "baz"]);
+        assert!(!query.ongoing);
+        let query = parse_feed_query("from=last%20monday&ongoing=true", &clock).unwrap();
+        assert!(query.range.is_some());
+        assert!(query.ongoing);
+        assert!(parse_feed_query("", &clock).unwrap().range.is_none());
+        assert!(parse_feed_query("range=month", &clock).is_err());
+        assert!(parse_feed_query("range=week&from=09:00", &clock).is_err());
+        assert!(parse_feed_query("foo=bar", &clock).is_err());
+    }
+}
@@ -0,0 +1,344 @@
+//! Invoices (`rtw invoice`): billable activities of a client grouped into line items.
+//!
+//! An activity belongs to client `X` if its `client` metadata is `X` or if it has tag `X`
+//! (or a hierarchical sub-tag e.g. `X:backend`).
+//! Line items group activities with the same title and rate, their net duration (breaks excluded)
+//! is rounded (see `Billing::rounding`, per activity or per item) then multiplied by the hourly rate.
+use crate::cli_helper::TagFilter;
+use crate::rtw_config::Billing;
+use crate::rtw_core::activity::Activity;
+use crate::rtw_core::datetimew::DateTimeW;
+use crate::rtw_core::durationw::DurationW;
+use crate::rtw_core::Tags;
+use anyhow::anyhow;
+
+/// Metadata key naming the client of an activity
+pub(crate) const CLIENT_KEY: &str = "client";
+
+/// Output format of `rtw invoice`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum InvoiceFormat {
+    Markdown,
+    Html,
+}
+
+/// Activities with the same title billed at the same rate
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct LineItem {
+    pub title: String,
+    pub segments: usize,
+    /// Net duration
+    pub duration: DurationW,
+    /// Rounded net duration (billed)
+    pub billed: DurationW,
+    /// Hourly rate
+    pub rate: f64,
+    pub amount: f64,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct Invoice {
+    pub client: String,
+    pub range: (DateTimeW, DateTimeW),
+    pub currency: String,
+    pub items: Vec<LineItem>,
+}
+
+fn hours(d: DurationW) -> f64 {
+    crate::json_export::seconds(d) as f64 / 3600.0
+}
+
+/// Amounts are rounded to cents
+fn round_amount(amount: f64) -> f64 {
+    (amount * 100.0).round() / 100.0
+}
+
+impl Invoice {
+    pub fn total_billed(&self) -> DurationW {
+        self.items.iter().map(|item| item.billed).sum()
+    }
+
+    pub fn total_amount(&self) -> f64 {
+        round_amount(self.items.iter().map(|item| item.amount).sum())
+    }
+
+    fn money(&self, amount: f64) -> String {
+        if self.currency.is_empty() {
+            format!("{:.2}", amount)
+        } else {
+            format!("{:.2} {}", amount, self.currency)
+        }
+    }
+}
+
+fn is_client_activity(activity: &Activity, client: &str, separator: &str) -> bool {
+    let client_tag = TagFilter {
+        include: vec![client.to_string()],
+        exclude: vec![],
+    };
+    activity.get_metadata().get(CLIENT_KEY).map(String::as_str) == Some(client)
+        || client_tag.matches(&activity.get_tags(), separator)
+}
+
+/// Rate of the most specific matching tag, else client rate, else default rate
+fn hourly_rate(tags: &Tags, client: &str, billing: &Billing, separator: &str) -> Option<f64> {
+    billing
+        .tag_rates
+        .iter()
+        .filter(|(rate_tag, _rate)| {
+            TagFilter {
+                include: vec![rate_tag.to_string()],
+                exclude: vec![],
+            }
+            .matches(tags, separator)
+        })
+        .max_by_key(|(rate_tag, _rate)| rate_tag.len())
+        .map(|(_rate_tag, rate)| *rate)
+        .or_else(|| billing.client_rates.get(client).copied())
+        .or(billing.default_rate)
+}
+
+/// Build the invoice of `client` from `activities` (already filtered by range)
+///
+/// Fails if an activity has no rate.
+pub(crate) fn build_invoice(
+    activities: &[Activity],
+    client: &str,
+    range: (DateTimeW, DateTimeW),
+    billing: &Billing,
+    separator: &str,
+) -> anyhow::Result<Invoice> {
+    // line items with the net durations of their activities
+    let mut items: Vec<(LineItem, Vec<DurationW>)> = vec![];
+    for activity in activities
+        .iter()
+        .filter(|a| is_client_activity(a, client, separator))
+    {
+        let title = activity.get_title();
+        let rate = hourly_rate(&activity.get_tags(), client, billing, separator)
+            .ok_or_else(|| anyhow!("no rate for {} (see billing in config)", title))?;
+        match items
+            .iter_mut()
+            // tags `["a b"]` and `["a", "b"]` share a title but may have different rates
+            .find(|(item, _durations)| item.title == title && item.rate == rate)
+        {
+            Some((item, durations)) => {
+                item.segments += 1;
+                item.duration = item.duration + activity.get_net_duration();
+                durations.push(activity.get_net_duration());
+            }
+            None => items.push((
+                LineItem {
+                    title,
+                    segments: 1,
+                    duration: activity.get_net_duration(),
+                    billed: DurationW::default(),
+                    rate,
+                    amount: 0.0,
+                },
+                vec![activity.get_net_duration()],
+            )),
+        }
+    }
+    let items = items
+        .into_iter()
+        .map(|(item, durations)| {
+            let billed = match billing.rounding {
+                None => item.duration,
+                Some(rounding) => rounding.round_group(&durations),
+            };
+            LineItem {
+                billed,
+                amount: round_amount(hours(billed) * item.rate),
+                ..item
+            }
+        })
+        .collect();
+    Ok(Invoice {
+        client: client.to_string(),
+        range,
+        currency: billing.currency.clone(),
+        items,
+    })
+}
+
+fn escape_markdown(s: &str) -> String {
+    s.replace('|', "\\|")
+}
+
+fn escape_html(s: &str) -> String {
+    s.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+pub(crate) fn render_markdown(invoice: &Invoice) -> String {
+    let mut lines = vec![
+        format!("# Invoice: {}", escape_markdown(&invoice.client)),
+        String::new(),
+        format!("Period: {} - {}", invoice.range.0, invoice.range.1),
+        String::new(),
+        String::from("| Item | Segments | Hours | Rate | Amount |"),
+        String::from("|---|---:|---:|---:|---:|"),
+    ];
+    for item in invoice.items.iter() {
+        lines.push(format!(
+            "| {} | {} | {:.2} | {} | {} |",
+            escape_markdown(&item.title),
+            item.segments,
+            hours(item.billed),
+            invoice.money(item.rate),
+            invoice.money(item.amount)
+        ));
+    }
+    lines.push(format!(
+        "| **Total** | | **{:.2}** | | **{}** |",
+        hours(invoice.total_billed()),
+        invoice.money(invoice.total_amount())
+    ));
+    format!("{}\n", lines.join("\n"))
+}
+
+pub(crate) fn render_html(invoice: &Invoice) -> String {
+    let client = escape_html(&invoice.client);
+    let mut lines = vec![
+        String::from("<!DOCTYPE html>"),
+        String::from("<html>"),
+        format!(
+            "<head><meta charset=\"utf-8\"><title>Invoice: {}</title></head>",
+            client
+        ),
+        String::from("<body>"),
+        format!("<h1>Invoice: {}</h1>", client),
+        format!("<p>Period: {} - {}</p>", invoice.range.0, invoice.range.1),
+        String::from("<table>"),
+        String::from(
+            "<tr><th>Item</th><th>Segments</th><th>Hours</th><th>Rate</th><th>Amount</th></tr>",
+        ),
+    ];
+    for item in invoice.items.iter() {
+        lines.push(format!(
+            "<tr><td>{}</td><td>{}</td><td>{:.2}</td><td>{}</td><td>{}</td></tr>",
+            escape_html(&item.title),
+            item.segments,
+            hours(item.billed),
+            escape_html(&invoice.money(item.rate)),
+            escape_html(&invoice.money(item.amount))
+        ));
+    }
+    lines.push(format!(
+        "<tr><th>Total</th><td></td><th>{:.2}</th><td></td><th>{}</th></tr>",
+        hours(invoice.total_billed()),
+        escape_html(&invoice.money(invoice.total_amount()))
+    ));
+    lines.push(String::from("</table>"));
+    lines.push(String::from("</body>"));
+    lines.push(String::from("</html>"));
+    format!("{}\n", lines.join("\n"))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::invoice::{build_invoice, render_markdown};
+    use crate::rtw_config::{Billing, Rounding, RoundingMode, RoundingScope};
+    use crate::rtw_core::activity::{Activity, OngoingActivity};
+    use crate::rtw_core::datetimew::DateTimeW;
+    use chrono::{Local, TimeZone};
+
+    fn activity(
+        start: (u32, u32),
+        stop: (u32, u32),
+        tags: &[&str],
+        client: Option<&str>,
+    ) -> Activity {
+        let start: DateTimeW = Local.ymd(2020, 12, 25).and_hms(start.0, start.1, 0).into();
+        let stop: DateTimeW = Local.ymd(2020, 12, 25).and_hms(stop.0, stop.1, 0).into();
+        let metadata = client
+            .map(|client| (String::from("client"), client.to_string()))
+            .into_iter()
+            .collect();
+        OngoingActivity::new(start, tags.iter().map(|t| t.to_string()).collect(), None)
+            .with_metadata(metadata)
+            .into_activity(stop)
+            .unwrap()
+    }
+
+    #[test]
+    fn test_build_invoice() {
+        let activities = vec![
+            activity((9, 0), (9, 50), &["acme:backend"], None),
+            activity((10, 0), (10, 20), &["acme:backend"], None),
+            activity((11, 0), (11, 10), &["review"], Some("acme")),
+            activity((12, 0), (13, 0), &["globex"], None),
+        ];
+        let billing = Billing {
+            currency: String::from("EUR"),
+            default_rate: Some(50.0),
+            tag_rates: vec![(String::from("review"), 80.0)].into_iter().collect(),
+            client_rates: vec![(String::from("acme"), 100.0)].into_iter().collect(),
+            rounding: Some(Rounding {
+                increment_minutes: 15,
+                mode: RoundingMode::Up,
+                scope: RoundingScope::Group,
+            }),
+        };
+        let range = (
+            activities[0].get_start_time(),
+            activities[3].get_stop_time(),
+        );
+        let invoice = build_invoice(&activities, "acme", range, &billing, ":").unwrap();
+        assert_eq!(invoice.items.len(), 2);
+        let backend = &invoice.items[0];
+        assert_eq!(backend.segments, 2);
+        assert_eq!(backend.duration.to_string(), "01:10:00");
+        assert_eq!(backend.billed.to_string(), "01:15:00");
+        assert_eq!(backend.amount, 125.0);
+        let review = &invoice.items[1];
+        assert_eq!(review.rate, 80.0);
+        assert_eq!(review.amount, 20.0);
+        assert_eq!(invoice.total_amount(), 145.0);
+        let markdown = render_markdown(&invoice);
+        assert!(markdown.contains("| acme:backend | 2 | 1.25 | 100.00 EUR | 125.00 EUR |\n"));
+        assert!(markdown.contains("| **Total** | | **1.50** | | **145.00 EUR** |\n"));
+        let per_activity = Billing {
+            rounding: billing.rounding.map(|rounding| Rounding {
+                scope: RoundingScope::Activity,
+                ..rounding
+            }),
+            ..billing.clone()
+        };
+        let invoice = build_invoice(&activities, "acme", range, &per_activity, ":").unwrap();
+        // 00:50:00 -> 01:00:00 and 00:20:00 -> 00:30:00
+        assert_eq!(invoice.items[0].billed.to_string(), "01:30:00");
+        let no_rate = Billing {
+            default_rate: None,
+            ..billing
+        };
+        assert!(build_invoice(&activities, "globex", range, &no_rate, ":").is_err());
+    }
+
+    #[test]
+    fn test_same_title_different_rates() {
+        let activities = vec![
+            activity((9, 0), (10, 0), &["review", "x"], Some("acme")),
+            activity((10, 0), (11, 0), &["review x"], Some("acme")),
+        ];
+        let billing = Billing {
+            tag_rates: vec![(String::from("review"), 80.0)].into_iter().collect(),
+            client_rates: vec![(String::from("acme"), 100.0)].into_iter().collect(),
+            ..Billing::default()
+        };
+        let range = (
+            activities[0].get_start_time(),
+            activities[1].get_stop_time(),
+        );
+        let invoice = build_invoice(&activities, "acme", range, &billing, ":").unwrap();
+        let items: Vec<(&str, f64)> = invoice
+            .items
+            .iter()
+            .map(|item| (item.title.as_str(), item.amount))
+            .collect();
+        assert_eq!(items, vec![("review x", 80.0), ("review x", 100.0)]);
+    }
+}
@@ -0,0 +1,266 @@
+//! Journal of applied mutations, used by `undo` and `redo`.
+use crate::json_storage::{lock_exclusive, write_atomically};
+use crate::rtw_core::activity::{Activity, OngoingActivity};
+use serde::{Deserialize, Serialize};
+use std::fs::File;
+use std::path::{Path, PathBuf};
+
+/// Number of entries kept in the journal (oldest entries are dropped first)
+const JOURNAL_MAX_LEN: usize = 100;
+
+/// A primitive storage change
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum Change {
+    AddFinished(Activity),
+    RemoveFinished(Activity),
+    AddOngoing(OngoingActivity),
+    RemoveOngoing(OngoingActivity),
+}
+
+impl Change {
+    /// Return the change reverting this one
+    pub fn invert(&self) -> Change {
+        match self {
+            Change::AddFinished(a) => Change::RemoveFinished(a.clone()),
+            Change::RemoveFinished(a) => Change::AddFinished(a.clone()),
+            Change::AddOngoing(a) => Change::RemoveOngoing(a.clone()),
+            Change::RemoveOngoing(a) => Change::AddOngoing(a.clone()),
+        }
+    }
+}
+
+/// Changes made by one mutation
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct JournalEntry {
+    /// Command that made the changes (`start`, `stop`...)
+    pub command: String,
+    /// Changes in the order they were applied
+    pub changes: Vec<Change>,
+}
+
+impl JournalEntry {
+    pub fn new(command: &str, changes: Vec<Change>) -> Self {
+        JournalEntry {
+            command: command.to_string(),
+            changes,
+        }
+    }
+
+    /// Return the changes reverting this entry, in the order they must be applied
+    pub fn inverted_changes(&self) -> Vec<Change> {
+        self.changes.iter().rev().map(Change::invert).collect()
+    }
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+struct JournalContent {
+    /// Applied entries, last applied last
+    done: Vec<JournalEntry>,
+    /// Undone entries, last undone last
+    undone: Vec<JournalEntry>,
+}
+
+fn read_content(path: &Path) -> anyhow::Result<JournalContent> {
+    if path.exists() {
+        Ok(serde_json::from_reader(File::open(path)?)?)
+    } else {
+        Ok(JournalContent::default())
+    }
+}
+
+/// Journal persisted as a Json file next to the storage files
+///
+/// Updates hold the journal lock (`.rtw.journal.lock`), mutations take it before
+/// the storage unit (see `Journal::atomically`) so that processes lock in the same order.
+pub struct Journal {
+    path: PathBuf,
+    content: JournalContent,
+    /// Lock held by `atomically`
+    held_lock: Option<File>,
+}
+
+impl Journal {
+    /// Load journal from `path`, empty if the file does not exist yet
+    pub fn load(path: PathBuf) -> anyhow::Result<Self> {
+        let content = read_content(&path)?;
+        Ok(Journal {
+            path,
+            content,
+            held_lock: None,
+        })
+    }
+
+    fn lock(&self) -> anyhow::Result<File> {
+        Ok(lock_exclusive(
+            &self.path.with_file_name(".rtw.journal.lock"),
+        )?)
+    }
+
+    /// Run `f` holding the journal lock, the journal is re-read first
+    ///
+    /// Entries read by `f` can't be changed by other processes until `f` returns.
+    pub fn atomically<T, F>(&mut self, f: F) -> anyhow::Result<T>
+    where
+        F: FnOnce(&mut Journal) -> anyhow::Result<T>,
+    {
+        if self.held_lock.is_some() {
+            return f(self);
+        }
+        self.held_lock = Some(self.lock()?);
+        let result = read_content(&self.path).and_then(|content| {
+            self.content = content;
+            f(self)
+        });
+        self.held_lock = None;
+        result
+    }
+
+    // re-read, modify then write the journal while holding the lock
+    fn update<F>(&mut self, f: F) -> anyhow::Result<()>
+    where
+        F: FnOnce(&mut JournalContent),
+    {
+        let _lock = match self.held_lock {
+            Some(_) => None,
+            None => Some(self.lock()?),
+        };
+        self.content = read_content(&self.path)?;
+        f(&mut self.content);
+        write_atomically(&self.path, &self.content)?;
+        Ok(())
+    }
+
+    /// Record a newly applied entry, previously undone entries can no longer be redone
+    pub fn record(&mut self, entry: JournalEntry) -> anyhow::Result<()> {
+        if entry.changes.is_empty() {
+            return Ok(());
+        }
+        self.update(|content| {
+            content.done.push(entry);
+            if content.done.len() > JOURNAL_MAX_LEN {
+                let excess = content.done.len() - JOURNAL_MAX_LEN;
+                content.done.drain(..excess);
+            }
+            content.undone.clear();
+        })
+    }
+
+    /// Last applied entry if any
+    pub fn last_done(&self) -> Option<&JournalEntry> {
+        self.content.done.last()
+    }
+
+    /// Last undone entry if any
+    pub fn last_undone(&self) -> Option<&JournalEntry> {
+        self.content.undone.last()
+    }
+
+    /// Move last applied entry to undone entries
+    pub fn mark_undone(&mut self) -> anyhow::Result<()> {
+        self.update(|content| {
+            if let Some(entry) = content.done.pop() {
+                content.undone.push(entry);
+            }
+        })
+    }
+
+    /// Move last undone entry back to applied entries
+    pub fn mark_redone(&mut self) -> anyhow::Result<()> {
+        self.update(|content| {
+            if let Some(entry) = content.undone.pop() {
+                content.done.push(entry);
+            }
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::journal::{Change, Journal, JournalEntry, JOURNAL_MAX_LEN};
+    use crate::rtw_core::activity::OngoingActivity;
+    use crate::rtw_core::datetimew::DateTimeW;
+    use chrono::{Local, TimeZone};
+    use tempfile::tempdir;
+
+    fn ongoing(tag: &str) -> OngoingActivity {
+        let start: DateTimeW = Local.ymd(2020, 12, 25).and_hms(9, 0, 0).into();
+        OngoingActivity::new(start, vec![tag.to_string()], None)
+    }
+
+    #[test]
+    fn test_inverted_changes() {
+        let foo = ongoing("foo");
+        let stopped = foo
+            .clone()
+            .into_activity(Local.ymd(2020, 12, 25).and_hms(10, 0, 0).into())
+            .unwrap();
+        let entry = JournalEntry::new(
+            "stop",
+            vec![
+                Change::RemoveOngoing(foo.clone()),
+                Change::AddFinished(stopped.clone()),
+            ],
+        );
+        assert_eq!(
+            entry.inverted_changes(),
+            vec![Change::RemoveFinished(stopped), Change::AddOngoing(foo)]
+        );
+    }
+
+    #[test]
+    fn test_undo_redo_persisted() {
+        let test_dir = tempdir().expect("could not create temp directory");
+        let path = test_dir.path().join(".rtw.journal.json");
+        let mut journal = Journal::load(path.clone()).unwrap();
+        assert!(journal.last_done().is_none());
+        let entry = JournalEntry::new("start", vec![Change::AddOngoing(ongoing("foo"))]);
+        journal.record(entry.clone()).unwrap();
+        journal.mark_undone().unwrap();
+        let mut journal = Journal::load(path.clone()).unwrap();
+        assert!(journal.last_done().is_none());
+        assert_eq!(journal.last_undone(), Some(&entry));
+        journal.mark_redone().unwrap();
+        let journal = Journal::load(path).unwrap();
+        assert_eq!(journal.last_done(), Some(&entry));
+        assert!(journal.last_undone().is_none());
+    }
+
+    #[test]
+    fn test_atomically_reads_latest_entries() {
+        let test_dir = tempdir().expect("could not create temp directory");
+        let path = test_dir.path().join(".rtw.journal.json");
+        let mut stale = Journal::load(path.clone()).unwrap();
+        let mut other = Journal::load(path).unwrap();
+        let entry = JournalEntry::new("start", vec![Change::AddOngoing(ongoing("foo"))]);
+        other.record(entry.clone()).unwrap();
+        assert!(stale.last_done().is_none());
+        let last_done = stale
+            .atomically(|journal| Ok(journal.last_done().cloned()))
+            .unwrap();
+        assert_eq!(last_done, Some(entry));
+    }
+
+    #[test]
+    fn test_record_clears_undone_and_is_bounded() {
+        let test_dir = tempdir().expect("could not create temp directory");
+        let path = test_dir.path().join(".rtw.journal.json");
+        let mut journal = Journal::load(path).unwrap();
+        journal
+            .record(JournalEntry::new(
+                "start",
+                vec![Change::AddOngoing(ongoing("foo"))],
+            ))
+            .unwrap();
+        journal.mark_undone().unwrap();
+        for _ in 0..=JOURNAL_MAX_LEN {
+            journal
+                .record(JournalEntry::new(
+                    "start",
+                    vec![Change::AddOngoing(ongoing("bar"))],
+                ))
+                .unwrap();
+        }
+        assert!(journal.last_undone().is_none());
+        assert_eq!(journal.content.done.len(), JOURNAL_MAX_LEN);
+    }
+}
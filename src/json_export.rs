@@ -0,0 +1,195 @@
+//! JSON and JSON Lines output for scripting.
+//!
+//! The schema is stable (see commands.md), it does not depend on the storage format.
+use crate::rtw_config::Rounding;
+use crate::rtw_core::activity::{Activity, Break, OngoingActivity};
+use crate::rtw_core::datetimew::DateTimeW;
+use crate::rtw_core::durationw::DurationW;
+use crate::rtw_core::{ActivityId, Description, Metadata, Tags};
+use chrono::{DateTime, Duration, Local, SecondsFormat};
+use serde::Serialize;
+
+/// A finished or ongoing activity
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub(crate) struct ActivityJson {
+    /// Relative id (see `ActivityId`), absent for planned or applied changes
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub id: Option<ActivityId>,
+    pub uid: String,
+    pub start: String,
+    /// `null` for ongoing activities
+    pub stop: Option<String>,
+    /// Seconds (until now for ongoing activities)
+    pub duration: i64,
+    /// Seconds without breaks
+    pub net_duration: i64,
+    /// Seconds without breaks rounded (see `rounding` config), absent without rounding
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub rounded_net_duration: Option<i64>,
+    pub tags: Tags,
+    pub description: Option<Description>,
+    /// Key-value fields (`{}` when none)
+    pub metadata: Metadata,
+    pub breaks: Vec<BreakJson>,
+    pub ongoing: bool,
+    pub paused: bool,
+}
+
+/// A break, `stop` is `null` while paused
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub(crate) struct BreakJson {
+    pub start: String,
+    pub stop: Option<String>,
+}
+
+/// Activities with same tags summed up (see `summary --report`)
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub(crate) struct ReportJson {
+    pub tags: Tags,
+    /// Seconds
+    pub duration: i64,
+    /// Seconds without breaks
+    pub net_duration: i64,
+    /// Seconds without breaks rounded (see `rounding` config), absent without rounding
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub rounded_net_duration: Option<i64>,
+    pub segments: usize,
+}
+
+/// Node of the hierarchical tags report (see `summary --report --depth`), in depth-first order
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub(crate) struct TagNodeJson {
+    /// Tag segments from the root e.g. `["client", "acme"]`
+    pub path: Vec<String>,
+    /// Seconds
+    pub duration: i64,
+    /// Seconds without breaks
+    pub net_duration: i64,
+    /// Seconds without breaks rounded (see `rounding` config), absent without rounding
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub rounded_net_duration: Option<i64>,
+    pub segments: usize,
+    /// Share of the total net duration
+    pub percent: f64,
+}
+
+/// ISO 8601 timestamp with offset e.g. `2020-12-25T09:00:00+01:00`
+pub(crate) fn timestamp(d: DateTimeW) -> String {
+    let local: DateTime<Local> = d.into();
+    local.to_rfc3339_opts(SecondsFormat::Secs, false)
+}
+
+pub(crate) fn seconds(d: DurationW) -> i64 {
+    Duration::from(d).num_seconds()
+}
+
+fn breaks_json(breaks: &[Break]) -> Vec<BreakJson> {
+    breaks
+        .iter()
+        .map(|b| BreakJson {
+            start: timestamp(b.start_time),
+            stop: b.stop_time.map(timestamp),
+        })
+        .collect()
+}
+
+impl ActivityJson {
+    pub fn from_finished(id: ActivityId, activity: &Activity) -> Self {
+        ActivityJson {
+            id: Some(id),
+            uid: activity.get_uid().to_string(),
+            start: timestamp(activity.get_start_time()),
+            stop: Some(timestamp(activity.get_stop_time())),
+            duration: seconds(activity.get_duration()),
+            net_duration: seconds(activity.get_net_duration()),
+            rounded_net_duration: None,
+            tags: activity.get_tags(),
+            description: activity.get_description(),
+            metadata: activity.get_metadata(),
+            breaks: breaks_json(&activity.get_breaks()),
+            ongoing: false,
+            paused: false,
+        }
+    }
+
+    /// Finished activity with its rounded net duration (if `rounding`)
+    pub fn from_finished_rounded(
+        id: ActivityId,
+        activity: &Activity,
+        rounding: Option<Rounding>,
+    ) -> Self {
+        ActivityJson {
+            rounded_net_duration: rounding
+                .map(|rounding| seconds(rounding.round(activity.get_net_duration()))),
+            ..ActivityJson::from_finished(id, activity)
+        }
+    }
+
+    pub fn from_ongoing(id: ActivityId, activity: &OngoingActivity, now: DateTimeW) -> Self {
+        ActivityJson {
+            id: Some(id),
+            uid: activity.uid.to_string(),
+            start: timestamp(activity.start_time),
+            stop: None,
+            duration: seconds(now - activity.start_time),
+            net_duration: seconds(activity.get_net_duration(now)),
+            rounded_net_duration: None,
+            tags: activity.tags.clone(),
+            description: activity.description.clone(),
+            metadata: activity.metadata.clone(),
+            breaks: breaks_json(&activity.breaks),
+            ongoing: true,
+            paused: activity.is_paused(),
+        }
+    }
+
+    /// Drop the relative id, it refers to the state before or after a change
+    pub fn without_id(self) -> Self {
+        ActivityJson { id: None, ..self }
+    }
+}
+
+/// Serialize `items` as a JSON array or as JSON Lines (one item per line)
+pub(crate) fn to_json_output<T: Serialize>(items: &[T], lines: bool) -> serde_json::Result<String> {
+    if lines {
+        items
+            .iter()
+            .map(|item| serde_json::to_string(item).map(|line| format!("{}\n", line)))
+            .collect()
+    } else {
+        serde_json::to_string_pretty(items).map(|array| format!("{}\n", array))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::json_export::{to_json_output, ActivityJson};
+    use crate::rtw_core::activity::OngoingActivity;
+    use crate::rtw_core::datetimew::DateTimeW;
+    use chrono::{Local, TimeZone};
+
+    #[test]
+    fn test_activity_json() {
+        let start: DateTimeW = Local.ymd(2020, 12, 25).and_hms(9, 0, 0).into();
+        let pause: DateTimeW = Local.ymd(2020, 12, 25).and_hms(9, 30, 0).into();
+        let now: DateTimeW = Local.ymd(2020, 12, 25).and_hms(10, 0, 0).into();
+        let ongoing = OngoingActivity::new(start, vec![String::from("foo")], None)
+            .pause(pause)
+            .unwrap();
+        let json = ActivityJson::from_ongoing(0, &ongoing, now);
+        assert_eq!(json.duration, 3600);
+        assert_eq!(json.net_duration, 1800);
+        assert!(json.ongoing && json.paused);
+        assert!(json.start.starts_with("2020-12-25T09:00:00"));
+        let finished = ActivityJson::from_finished(1, &ongoing.into_activity(now).unwrap());
+        assert_eq!(
+            finished.stop.as_deref().map(|s| &s[..19]),
+            Some("2020-12-25T10:00:00")
+        );
+        assert!(!finished.ongoing && !finished.paused);
+        let lines = to_json_output(&[json, finished], true).unwrap();
+        assert_eq!(lines.lines().count(), 2);
+        assert!(lines.starts_with("{\"id\":0,\"uid\":"));
+        assert_eq!(to_json_output::<ActivityJson>(&[], false).unwrap(), "[]\n");
+    }
+}
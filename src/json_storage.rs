@@ -1,10 +1,12 @@
 //! Store activities (current, finished) as Json files.
 use crate::rtw_core::activity::{Activity, OngoingActivity};
 use crate::rtw_core::storage::Storage;
-use crate::rtw_core::ActivityId;
+use crate::rtw_core::{ActivityId, ActivityUid};
+use fs2::FileExt;
 use itertools::Itertools;
 use serde::{Deserialize, Serialize};
 use std::fs::{File, OpenOptions};
+use std::io::{BufWriter, Write};
 use std::path::{Path, PathBuf};
 use thiserror::Error;
 
@@ -39,6 +41,10 @@ pub enum JsonStorageError {
 pub struct JsonStorage {
     current_path: PathBuf,
     finished_path: PathBuf,
+    lock_path: PathBuf,
+    /// Lock held by the current unit (see `Storage::begin`) and its nesting depth
+    unit_lock: Option<File>,
+    unit_depth: usize,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -48,9 +54,23 @@ pub struct OngoingActivities {
 
 impl JsonStorage {
     pub fn new(current_path: PathBuf, finished_path: PathBuf) -> Self {
+        let lock_path = finished_path.with_file_name(".rtw.lock");
         JsonStorage {
             current_path,
             finished_path,
+            lock_path,
+            unit_lock: None,
+            unit_depth: 0,
+        }
+    }
+
+    /// Take an exclusive advisory lock on the storage directory (unless a unit already holds it)
+    ///
+    /// The lock is released when the returned file is dropped.
+    fn lock(&self) -> Result<Option<File>, JsonStorageError> {
+        match self.unit_lock {
+            Some(_) => Ok(None),
+            None => lock_exclusive(&self.lock_path).map(Some),
         }
     }
 
@@ -84,35 +104,53 @@ impl JsonStorage {
         finished_activities.activities.sort();
         Ok((0..finished_activities.activities.len())
             .rev()
-            .zip(finished_activities.activities)
+            .zip(
+                finished_activities
+                    .activities
+                    .into_iter()
+                    .map(Activity::with_legacy_uid),
+            )
             .collect())
     }
 }
 
+/// Take an exclusive advisory lock on `lock_path`, released when the returned file is dropped
+pub(crate) fn lock_exclusive(lock_path: &Path) -> Result<File, JsonStorageError> {
+    let lock_file = OpenOptions::new()
+        .create(true)
+        .write(true)
+        .truncate(false)
+        .open(lock_path)?;
+    FileExt::lock_exclusive(&lock_file)?;
+    Ok(lock_file)
+}
+
+/// Write `value` to a temporary file then rename it to `path`
+///
+/// Readers either see the previous content or the new one, never a partial write.
+pub(crate) fn write_atomically<T: Serialize>(
+    path: &Path,
+    value: &T,
+) -> Result<(), JsonStorageError> {
+    let tmp_path = path.with_extension("json.tmp");
+    let mut writer = BufWriter::new(File::create(&tmp_path)?);
+    serde_json::to_writer(&mut writer, value)?;
+    writer.flush()?;
+    writer.get_ref().sync_all()?;
+    drop(writer);
+    std::fs::rename(&tmp_path, path)?;
+    Ok(())
+}
+
 impl Storage for JsonStorage {
     type StorageError = JsonStorageError;
 
     fn write_activity(&mut self, activity: Activity) -> Result<(), Self::StorageError> {
-        if !Path::exists(&self.finished_path) {
-            let file = File::create(&self.finished_path)?;
-            let activities: Activities = vec![activity];
-            let finished_activities = FinishedActivities {
-                semver: Some(crate_version!().to_string()),
-                activities,
-            };
-            serde_json::to_writer(file, &finished_activities)?;
-            Ok(())
-        } else {
-            let mut finished_activities = self.get_finished_activities()?;
-            finished_activities.activities.push(activity);
-            let file = OpenOptions::new()
-                .write(true)
-                .truncate(true)
-                .open(&self.finished_path)?;
-            finished_activities.semver = Some(crate_version!().to_string());
-            serde_json::to_writer(file, &finished_activities)?;
-            Ok(())
-        }
+        let _lock = self.lock()?;
+        let mut finished_activities = self.get_finished_activities()?;
+        finished_activities.activities.push(activity);
+        finished_activities.semver = Some(crate_version!().to_string());
+        write_atomically(&self.finished_path, &finished_activities)
     }
 
     fn filter_activities<P>(&self, p: P) -> Result<Vec<ActivityWithId>, Self::StorageError>
@@ -128,22 +166,25 @@ impl Storage for JsonStorage {
         self.get_sorted_activities()
     }
 
-    fn delete_activity(&self, id: usize) -> Result<Option<Activity>, Self::StorageError> {
-        let finished_activities = self.get_sorted_activities()?;
-        let (removed, kept): (Vec<&ActivityWithId>, Vec<&ActivityWithId>) = finished_activities
+    fn delete_activity(&self, uid: ActivityUid) -> Result<Option<Activity>, Self::StorageError> {
+        let _lock = self.lock()?;
+        let mut activities: Vec<Activity> = self
+            .get_sorted_activities()?
+            .into_iter()
+            .map(|(_, a)| a)
+            .collect();
+        let removed = activities
             .iter()
-            .partition(|(finished_id, _)| *finished_id == id);
-        let kept: Vec<&Activity> = kept.iter().map(|(_, a)| a).collect();
-        let file = OpenOptions::new()
-            .create(true)
-            .write(true)
-            .truncate(true)
-            .open(&self.finished_path)?;
-        serde_json::to_writer(file, &kept)?;
-        Ok(match removed.as_slice() {
-            [(_, removed)] => Some(removed.clone()),
-            _ => None,
-        })
+            .position(|a| a.get_uid() == uid)
+            .map(|position| activities.remove(position));
+        if removed.is_some() {
+            let kept = FinishedActivities {
+                semver: Some(crate_version!().to_string()),
+                activities,
+            };
+            write_atomically(&self.finished_path, &kept)?;
+        }
+        Ok(removed)
     }
 
     fn get_ongoing_activities(&self) -> Result<Vec<OngoingActivityWithId>, Self::StorageError> {
@@ -154,8 +195,8 @@ impl Storage for JsonStorage {
             let ongoing_activities: OngoingActivities = serde_json::from_reader(file)?;
             Ok(ongoing_activities
                 .ongoing
-                .iter()
-                .cloned()
+                .into_iter()
+                .map(OngoingActivity::with_legacy_uid)
                 .sorted()
                 .enumerate()
                 .collect())
@@ -164,28 +205,24 @@ impl Storage for JsonStorage {
 
     fn get_ongoing_activity(
         &self,
-        id: ActivityId,
+        uid: ActivityUid,
     ) -> Result<Option<OngoingActivity>, Self::StorageError> {
         let ongoing_activities = self.get_ongoing_activities()?;
         let ongoing = ongoing_activities
-            .iter()
-            .find(|(a_id, _a)| *a_id == id)
-            .map(|(_a_id, a)| a);
-        Ok(ongoing.cloned())
+            .into_iter()
+            .map(|(_a_id, a)| a)
+            .find(|a| a.uid == uid);
+        Ok(ongoing)
     }
 
     fn add_ongoing_activity(
         &mut self,
         activity: OngoingActivity,
     ) -> Result<(), Self::StorageError> {
+        let _lock = self.lock()?;
         let ongoing_activities = self.get_ongoing_activities()?;
-        let file = OpenOptions::new()
-            .write(true)
-            .create(true)
-            .truncate(true)
-            .open(&self.current_path)?;
-        serde_json::to_writer(
-            file,
+        write_atomically(
+            &self.current_path,
             &OngoingActivities {
                 ongoing: ongoing_activities
                     .iter()
@@ -195,33 +232,46 @@ impl Storage for JsonStorage {
                     .chain(std::iter::once(activity))
                     .collect(),
             },
-        )?;
-        Ok(())
+        )
     }
 
     fn remove_ongoing_activity(
         &mut self,
-        id: ActivityId,
+        uid: ActivityUid,
     ) -> Result<Option<OngoingActivity>, Self::StorageError> {
-        let ongoing_activities = self.get_ongoing_activities()?;
-        let (removed, kept): (Vec<OngoingActivityWithId>, Vec<OngoingActivityWithId>) =
-            ongoing_activities
-                .iter()
-                .cloned()
-                .partition(|(a_id, _a)| *a_id == id);
-        let file = OpenOptions::new()
-            .write(true)
-            .create(true)
-            .truncate(true)
-            .open(&self.current_path)?;
-        let kept_without_id: Vec<OngoingActivity> =
-            kept.iter().cloned().sorted().map(|(_a_id, a)| a).collect();
-        serde_json::to_writer(
-            file,
+        let _lock = self.lock()?;
+        let mut ongoing_activities: Vec<OngoingActivity> = self
+            .get_ongoing_activities()?
+            .into_iter()
+            .map(|(_a_id, a)| a)
+            .collect();
+        let removed = ongoing_activities
+            .iter()
+            .position(|a| a.uid == uid)
+            .map(|position| ongoing_activities.remove(position));
+        write_atomically(
+            &self.current_path,
             &OngoingActivities {
-                ongoing: kept_without_id,
+                ongoing: ongoing_activities,
             },
         )?;
-        Ok(removed.first().cloned().map(|(_a_id, a)| a))
+        Ok(removed)
+    }
+
+    fn begin(&mut self) -> Result<(), Self::StorageError> {
+        if self.unit_depth == 0 {
+            self.unit_lock = Some(lock_exclusive(&self.lock_path)?);
+        }
+        self.unit_depth += 1;
+        Ok(())
+    }
+
+    fn end(&mut self, _commit: bool) -> Result<(), Self::StorageError> {
+        self.unit_depth = self.unit_depth.saturating_sub(1);
+        if self.unit_depth == 0 {
+            // writes are already done (one atomic rename per file), only the lock is released
+            self.unit_lock = None;
+        }
+        Ok(())
     }
 }
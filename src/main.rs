@@ -2,25 +2,47 @@
 extern crate clap;
 
 use crate::chrono_clock::ChronoClock;
-use crate::cli_helper::get_app;
+use crate::cli_helper::{get_app, parse_external_args, parse_rpc_args, parse_serve_args};
+use crate::ics_feed::serve_ics;
+use crate::journal::Journal;
 use crate::json_storage::JsonStorage;
+use crate::plugins::{run_plugin, PluginContext};
+use crate::rest_api::serve_api;
+use crate::rpc::serve_rpc;
 use crate::rtw_cli::{dry_run_action, run, run_mutation};
-use crate::rtw_config::{load_config, RtwConfig};
+use crate::rtw_config::{find_config_path, load_config, RtwConfig, StorageBackend};
+use crate::rtw_core::storage::Storage;
 use crate::service::Service;
-use std::path::PathBuf;
+use crate::sqlite_storage::SqliteStorage;
+use clap::ArgMatches;
+use std::path::{Path, PathBuf};
 use std::str::FromStr;
 
 mod chrono_clock;
 mod cli_helper;
+mod csv;
+mod hooks;
 mod ical_export;
+mod ical_import;
+mod ics_feed;
+mod invoice;
+mod journal;
+mod json_export;
 mod json_storage;
+mod plugins;
+mod rest_api;
+mod rounding;
+mod rpc;
 mod rtw_cli;
 mod rtw_config;
 mod rtw_core;
 mod service;
+mod sqlite_storage;
 mod status;
+mod tag_tree;
 mod time_tools;
 mod timeline;
+mod timewarrior;
 
 fn main() -> anyhow::Result<()> {
     let clock = ChronoClock {};
@@ -48,21 +70,94 @@ fn main() -> anyhow::Result<()> {
     };
     let current_activity_path = storage_dir.join(".rtw.json");
     let finished_activity_path = storage_dir.join(".rtwh.json");
-    let mut service = Service::new(JsonStorage::new(
-        current_activity_path,
-        finished_activity_path,
-    ));
+    let json_storage = JsonStorage::new(current_activity_path, finished_activity_path);
 
     #[cfg(windows)]
     {
         ansi_term::enable_ansi_support().unwrap_or(());
     }
-    let action = run(&matches, &clock)?;
-    let mutation = dry_run_action(action, &service, &clock, &config)?;
+    match config.storage_backend {
+        StorageBackend::Json => {
+            run_with_storage(json_storage, &storage_dir, &matches, &clock, &config)
+        }
+        StorageBackend::Sqlite => {
+            let database_path = storage_dir.join(".rtw.sqlite");
+            let mut sqlite_storage = SqliteStorage::open(&database_path)?;
+            // one-shot migration from json files (recorded in the database)
+            sqlite_storage.import_from(&json_storage)?;
+            run_with_storage(sqlite_storage, &storage_dir, &matches, &clock, &config)
+        }
+    }
+}
+
+fn run_with_storage<S: Storage>(
+    storage: S,
+    storage_dir: &Path,
+    matches: &ArgMatches,
+    clock: &ChronoClock,
+    config: &RtwConfig,
+) -> anyhow::Result<()> {
+    let mut service = Service::new(storage);
+    let journal_path = storage_dir.join(".rtw.journal.json");
+    match matches.subcommand() {
+        ("serve", Some(sub_m)) => {
+            // long-running: each request goes through `dry_run_action` then `run_mutation`
+            let (host, port) = parse_serve_args(sub_m)?;
+            return serve_api(
+                &mut service,
+                clock,
+                config,
+                &journal_path,
+                &host,
+                port,
+                matches.is_present("dry-run"),
+            );
+        }
+        ("serve-ics", Some(sub_m)) if !matches.is_present("dry-run") => {
+            // long-running and read-only: blocks serving requests until the process is killed
+            let (host, port) = parse_serve_args(sub_m)?;
+            return serve_ics(&service, clock, &host, port);
+        }
+        ("rpc", Some(sub_m)) => {
+            // long-running: stdout is reserved to JSON-RPC messages
+            let poll_interval = parse_rpc_args(sub_m)?;
+            return serve_rpc(
+                &mut service,
+                clock,
+                config,
+                &journal_path,
+                poll_interval,
+                matches.is_present("dry-run"),
+            );
+        }
+        _ => {}
+    }
+    if let Some((name, args)) = parse_external_args(matches) {
+        let config_path = if matches.is_present("default") {
+            None
+        } else {
+            find_config_path()
+        };
+        let context = PluginContext {
+            storage_dir,
+            config_path: config_path.as_deref(),
+        };
+        return run_plugin(&name, &args, &service, clock, &context);
+    }
+    let mut journal = Journal::load(journal_path)?;
+    let action = run(matches, clock)?;
+    let mutation = dry_run_action(
+        action,
+        &service,
+        clock,
+        config,
+        &journal,
+        &mut std::io::stdout(),
+    )?;
     if matches.is_present("dry-run") {
         println!("(dry-run) nothing done");
         Ok(())
     } else {
-        run_mutation(mutation, &mut service, &config)
+        run_mutation(mutation, &mut service, clock, config, &mut journal)
     }
 }
@@ -0,0 +1,144 @@
+//! External subcommands (git-style): `rtw <name> [args]` runs `rtw-<name> [args]` from `PATH`.
+//!
+//! Plugins get the following environment variables:
+//!
+//! * `RTW_STORAGE_DIR`: resolved storage directory
+//! * `RTW_CONFIG`: config file path (unset when no config file is used)
+//! * `RTW_SNAPSHOT`: path to a JSON snapshot of ongoing and finished activities (see `SnapshotJson`),
+//!   removed when the plugin exits
+use crate::cli_helper::similar_subcommands;
+use crate::json_export::ActivityJson;
+use crate::rtw_core::clock::Clock;
+use crate::rtw_core::service::ActivityService;
+use anyhow::anyhow;
+use serde::Serialize;
+use std::ffi::OsString;
+use std::io::{BufWriter, ErrorKind, Write};
+use std::path::Path;
+use std::process::Command;
+
+/// Plugin executable prefix
+const PLUGIN_PREFIX: &str = "rtw-";
+
+/// Activities given to plugins (`RTW_SNAPSHOT`)
+#[derive(Debug, Serialize)]
+pub(crate) struct SnapshotJson {
+    pub ongoing: Vec<ActivityJson>,
+    pub finished: Vec<ActivityJson>,
+}
+
+impl SnapshotJson {
+    pub fn new<S: ActivityService>(service: &S, clock: &dyn Clock) -> anyhow::Result<Self> {
+        let now = clock.get_time();
+        Ok(SnapshotJson {
+            ongoing: service
+                .get_ongoing_activities()?
+                .iter()
+                .map(|(id, a)| ActivityJson::from_ongoing(*id, a, now))
+                .collect(),
+            finished: service
+                .get_finished_activities()?
+                .iter()
+                .map(|(id, a)| ActivityJson::from_finished(*id, a))
+                .collect(),
+        })
+    }
+}
+
+/// Context passed to plugins through the environment
+pub(crate) struct PluginContext<'a> {
+    pub storage_dir: &'a Path,
+    pub config_path: Option<&'a Path>,
+}
+
+// Suggest built-in subcommands when a plugin is not found (e.g. a typo)
+fn not_found_hint(name: &str) -> String {
+    match similar_subcommands(name).as_slice() {
+        [] => String::new(),
+        similar => format!("\n\n\tDid you mean {}?", similar.join(" or ")),
+    }
+}
+
+/// Run plugin `rtw-<name>` with `args`, fails if it is not found or if it fails
+pub(crate) fn run_plugin<S: ActivityService>(
+    name: &str,
+    args: &[OsString],
+    service: &S,
+    clock: &dyn Clock,
+    context: &PluginContext,
+) -> anyhow::Result<()> {
+    let executable = format!("{}{}", PLUGIN_PREFIX, name);
+    let snapshot = SnapshotJson::new(service, clock)?;
+    // new file with a random name only readable by the user, removed when dropped
+    let snapshot_file = tempfile::Builder::new()
+        .prefix("rtw-snapshot-")
+        .suffix(".json")
+        .tempfile()?;
+    let mut writer = BufWriter::new(snapshot_file.as_file());
+    serde_json::to_writer(&mut writer, &snapshot)?;
+    // flush explicitly: errors are ignored when dropping the writer
+    writer.flush()?;
+    drop(writer);
+    let mut command = Command::new(&executable);
+    command
+        .args(args)
+        .env("RTW_STORAGE_DIR", context.storage_dir)
+        .env("RTW_SNAPSHOT", snapshot_file.path());
+    match context.config_path {
+        None => command.env_remove("RTW_CONFIG"),
+        Some(config_path) => command.env("RTW_CONFIG", config_path),
+    };
+    let status = command.status();
+    drop(snapshot_file);
+    match status {
+        Ok(status) if status.success() => Ok(()),
+        Ok(status) => Err(anyhow!("{} failed ({})", executable, status)),
+        Err(e) if e.kind() == ErrorKind::NotFound => Err(anyhow!(
+            "unknown subcommand: {} ({} not found in PATH){}",
+            name,
+            executable,
+            not_found_hint(name)
+        )),
+        Err(e) => Err(anyhow!("{}: {}", executable, e)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::chrono_clock::ChronoClock;
+    use crate::json_storage::JsonStorage;
+    use crate::plugins::{run_plugin, PluginContext, SnapshotJson};
+    use crate::rtw_core::activity::OngoingActivity;
+    use crate::rtw_core::clock::Clock;
+    use crate::rtw_core::service::ActivityService;
+    use crate::service::Service;
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_run_plugin_not_found() {
+        let clock = ChronoClock {};
+        let test_dir = tempdir().expect("error while creating tempdir");
+        let mut service = Service::new(JsonStorage::new(
+            test_dir.path().join(".rtwc.json"),
+            test_dir.path().join(".rtwh.json"),
+        ));
+        service
+            .start_activity(
+                OngoingActivity::new(clock.get_time(), vec![String::from("foo")], None),
+                true,
+            )
+            .unwrap();
+        let snapshot = SnapshotJson::new(&service, &clock).unwrap();
+        assert_eq!(snapshot.ongoing.len(), 1);
+        assert!(snapshot.finished.is_empty());
+        let context = PluginContext {
+            storage_dir: test_dir.path(),
+            config_path: None,
+        };
+        let error = run_plugin("does-not-exist", &[], &service, &clock, &context).unwrap_err();
+        assert!(error.to_string().contains("rtw-does-not-exist not found"));
+        assert!(!error.to_string().contains("Did you mean"));
+        let error = run_plugin("sumary", &[], &service, &clock, &context).unwrap_err();
+        assert!(error.to_string().ends_with("Did you mean summary?"));
+    }
+}
@@ -0,0 +1,534 @@
+//! Local REST/JSON API (`rtw serve`).
+//!
+//! Requests are translated to `RtwAction`s, then planned with `dry_run_action`
+//! and applied with `run_mutation` (unless `?dry=true` or `rtw --dry serve`), like CLI commands.
+//!
+//! Mutating requests must be sent as `application/json` and without an `Origin` header,
+//! so that web pages open in a browser cannot drive the API (no CORS preflight for simple requests).
+use crate::ics_feed::parse_feed_query;
+use crate::journal::Journal;
+use crate::json_export::{timestamp, ActivityJson};
+use crate::rtw_cli::{dry_run_action, run_mutation, RtwAction, RtwMutation};
+use crate::rtw_config::RtwConfig;
+use crate::rtw_core::clock::Clock;
+use crate::rtw_core::datetimew::DateTimeW;
+use crate::rtw_core::service::ActivityService;
+use crate::rtw_core::storage::Storage;
+use crate::rtw_core::{ActivitySelector, Description, Metadata, Tags};
+use crate::service::Service;
+use crate::time_tools::TimeTools;
+use anyhow::anyhow;
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use std::io::Cursor;
+use std::path::Path;
+use std::str::FromStr;
+use tiny_http::{Header, Method, Request, Response, Server};
+
+/// `POST /activities/start` body
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct StartRequest {
+    tags: Tags,
+    description: Option<Description>,
+    metadata: Metadata,
+    /// time clue, now when omitted
+    start: Option<String>,
+}
+
+/// `POST /activities/track` body
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct TrackRequest {
+    start: String,
+    stop: String,
+    #[serde(default)]
+    tags: Tags,
+    #[serde(default)]
+    description: Option<Description>,
+    #[serde(default)]
+    metadata: Metadata,
+}
+
+/// `POST /activities/stop` and `POST /activities/cancel` body
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct StopRequest {
+    /// activity id or uid, the ongoing activity when omitted
+    id: Option<String>,
+    /// time clue, now when omitted (ignored by cancel)
+    time: Option<String>,
+}
+
+/// Planned (`dry_run: true`) or applied mutation
+#[derive(Debug, Serialize)]
+pub(crate) struct MutationResponse {
+    pub dry_run: bool,
+    pub mutation: MutationJson,
+}
+
+/// JSON description of a `RtwMutation` (API and RPC responses)
+#[derive(Debug, Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub(crate) enum MutationJson {
+    Start {
+        activity: ActivityJson,
+    },
+    Track {
+        activity: ActivityJson,
+    },
+    Stop {
+        uid: String,
+        stop: String,
+    },
+    Delete {
+        uid: String,
+    },
+    Cancel {
+        uid: String,
+    },
+    /// nothing to do e.g. no ongoing activity or no matching activity
+    None,
+}
+
+impl MutationJson {
+    pub fn new(mutation: &RtwMutation, now: DateTimeW) -> Self {
+        match mutation {
+            RtwMutation::Start(started) => MutationJson::Start {
+                activity: ActivityJson::from_ongoing(0, started, now).without_id(),
+            },
+            RtwMutation::Track(tracked) => MutationJson::Track {
+                activity: ActivityJson::from_finished(0, tracked).without_id(),
+            },
+            RtwMutation::Stop(stop_time, uid) => MutationJson::Stop {
+                uid: uid.to_string(),
+                stop: timestamp(*stop_time),
+            },
+            RtwMutation::Delete(uid) => MutationJson::Delete {
+                uid: uid.to_string(),
+            },
+            RtwMutation::Cancel(uid) => MutationJson::Cancel {
+                uid: uid.to_string(),
+            },
+            // other mutations are not reachable from the API
+            _ => MutationJson::None,
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+struct ErrorResponse {
+    error: String,
+}
+
+type JsonResponse = Response<Cursor<Vec<u8>>>;
+
+fn json_response<T: Serialize>(status_code: u16, body: &T) -> JsonResponse {
+    let content_type =
+        Header::from_bytes("Content-Type", "application/json").expect("valid header");
+    let body = serde_json::to_string(body).expect("serializable response");
+    Response::from_string(format!("{}\n", body))
+        .with_status_code(status_code)
+        .with_header(content_type)
+}
+
+fn error_response(status_code: u16, error: anyhow::Error) -> JsonResponse {
+    json_response(
+        status_code,
+        &ErrorResponse {
+            error: error.to_string(),
+        },
+    )
+}
+
+fn parse_body<T: DeserializeOwned + Default>(body: &str) -> anyhow::Result<T> {
+    if body.trim().is_empty() {
+        Ok(T::default())
+    } else {
+        Ok(serde_json::from_str(body)?)
+    }
+}
+
+fn parse_time(time_maybe: Option<&str>, clock: &dyn Clock) -> anyhow::Result<DateTimeW> {
+    match time_maybe {
+        None => Ok(clock.get_time()),
+        Some(time_str) => {
+            TimeTools::time_from_str(time_str, clock).map(|time| clock.date_time(time))
+        }
+    }
+}
+
+fn parse_selector(id_maybe: Option<&str>) -> anyhow::Result<Option<ActivitySelector>> {
+    id_maybe.map(ActivitySelector::from_str).transpose()
+}
+
+/// Translate a mutating request to an action
+fn parse_action(
+    method: &Method,
+    path: &str,
+    body: &str,
+    clock: &dyn Clock,
+) -> anyhow::Result<Option<RtwAction>> {
+    let action = match (method, path) {
+        (Method::Post, "/activities/start") => {
+            let request: StartRequest = parse_body(body)?;
+            RtwAction::Start(
+                parse_time(request.start.as_deref(), clock)?,
+                request.tags,
+                request.description,
+                request.metadata,
+            )
+        }
+        (Method::Post, "/activities/track") => {
+            let request: TrackRequest = serde_json::from_str(body)?;
+            RtwAction::Track(
+                (
+                    parse_time(Some(&request.start), clock)?,
+                    parse_time(Some(&request.stop), clock)?,
+                ),
+                request.tags,
+                request.description,
+                request.metadata,
+            )
+        }
+        (Method::Post, "/activities/stop") => {
+            let request: StopRequest = parse_body(body)?;
+            RtwAction::Stop(
+                parse_time(request.time.as_deref(), clock)?,
+                parse_selector(request.id.as_deref())?,
+            )
+        }
+        (Method::Post, "/activities/cancel") => {
+            let request: StopRequest = parse_body(body)?;
+            RtwAction::Cancel(parse_selector(request.id.as_deref())?)
+        }
+        (Method::Delete, path) => match path.strip_prefix("/activities/") {
+            Some(id) => RtwAction::Delete(ActivitySelector::from_str(id)?),
+            None => return Ok(None),
+        },
+        _ => return Ok(None),
+    };
+    Ok(Some(action))
+}
+
+fn list_activities<S: Storage>(
+    query: &str,
+    service: &Service<S>,
+    clock: &dyn Clock,
+) -> anyhow::Result<Vec<ActivityJson>> {
+    let feed_query = parse_feed_query(query, clock)?;
+    let finished = match feed_query.range {
+        None => service.get_finished_activities()?,
+        Some((range_start, range_end)) => {
+            service.filter_activities_by_range(range_start, range_end)?
+        }
+    };
+    let mut activities: Vec<ActivityJson> = finished
+        .iter()
+        .map(|(id, a)| ActivityJson::from_finished(*id, a))
+        .collect();
+    if feed_query.ongoing {
+        let now = clock.get_time();
+        activities.extend(
+            service
+                .get_ongoing_activities()?
+                .iter()
+                .map(|(id, a)| ActivityJson::from_ongoing(*id, a, now)),
+        );
+    }
+    activities.retain(|a| {
+        feed_query.tags.is_empty() || a.tags.iter().any(|t| feed_query.tags.contains(t))
+    });
+    Ok(activities)
+}
+
+fn header<'a>(headers: &'a [Header], name: &'static str) -> Option<&'a str> {
+    headers
+        .iter()
+        .find(|h| h.field.equiv(name))
+        .map(|h| h.value.as_str())
+}
+
+// Reject mutating requests which could come from a web page (see module documentation)
+fn check_mutating_request(method: &Method, headers: &[Header]) -> Result<(), JsonResponse> {
+    if header(headers, "Origin").is_some() {
+        return Err(error_response(
+            403,
+            anyhow!("cross-origin requests are not allowed"),
+        ));
+    }
+    let json_content_type = header(headers, "Content-Type")
+        .and_then(|content_type| content_type.split(';').next())
+        .is_some_and(|media_type| media_type.trim().eq_ignore_ascii_case("application/json"));
+    if *method == Method::Post && !json_content_type {
+        return Err(error_response(
+            415,
+            anyhow!("Content-Type must be application/json"),
+        ));
+    }
+    Ok(())
+}
+
+/// Parts of an HTTP request needed to respond
+struct ApiRequest<'a> {
+    method: Method,
+    url: &'a str,
+    headers: &'a [Header],
+    body: &'a str,
+}
+
+/// `dry_run`: only plan mutations, whatever the request asks
+fn handle<S, Cl>(
+    request: &ApiRequest,
+    service: &mut Service<S>,
+    clock: &Cl,
+    config: &RtwConfig,
+    journal_path: &Path,
+    dry_run: bool,
+) -> JsonResponse
+where
+    S: Storage,
+    Cl: Clock,
+{
+    let method = &request.method;
+    let (path, query) = request.url.split_once('?').unwrap_or((request.url, ""));
+    match (method, path) {
+        (Method::Get, "/activities") => {
+            return match list_activities(query, service, clock) {
+                Ok(activities) => json_response(200, &activities),
+                Err(e) => error_response(400, e),
+            }
+        }
+        (Method::Get, "/activities/ongoing") => {
+            let now = clock.get_time();
+            return match service.get_ongoing_activities() {
+                Ok(ongoing) => {
+                    let ongoing: Vec<ActivityJson> = ongoing
+                        .iter()
+                        .map(|(id, a)| ActivityJson::from_ongoing(*id, a, now))
+                        .collect();
+                    json_response(200, &ongoing)
+                }
+                Err(e) => error_response(500, e),
+            };
+        }
+        _ => {}
+    }
+    if let Err(response) = check_mutating_request(method, request.headers) {
+        return response;
+    }
+    let dry_run = dry_run
+        || form_urlencoded::parse(query.as_bytes())
+            .any(|(key, value)| key == "dry" && (value.is_empty() || value == "true"));
+    let action = match parse_action(method, path, request.body, clock) {
+        Ok(Some(action)) => action,
+        Ok(None) => return error_response(404, anyhow!("not found: {} {}", method, path)),
+        Err(e) => return error_response(400, e),
+    };
+    let mut journal = match Journal::load(journal_path.to_path_buf()) {
+        Ok(journal) => journal,
+        Err(e) => return error_response(500, e),
+    };
+    // the planned change is returned in the response, not printed on the server output
+    let mutation = match dry_run_action(
+        action,
+        service,
+        clock,
+        config,
+        &journal,
+        &mut std::io::sink(),
+    ) {
+        Ok(mutation) => mutation,
+        Err(e) => return error_response(400, e),
+    };
+    let response = MutationResponse {
+        dry_run,
+        mutation: MutationJson::new(&mutation, clock.get_time()),
+    };
+    if dry_run {
+        return json_response(200, &response);
+    }
+    match run_mutation(mutation, service, clock, config, &mut journal) {
+        Ok(()) => json_response(200, &response),
+        // e.g. overlapping activities
+        Err(e) => error_response(409, e),
+    }
+}
+
+fn respond<S, Cl>(
+    request: &mut Request,
+    service: &mut Service<S>,
+    clock: &Cl,
+    config: &RtwConfig,
+    journal_path: &Path,
+    dry_run: bool,
+) -> JsonResponse
+where
+    S: Storage,
+    Cl: Clock,
+{
+    let mut body = String::new();
+    if let Err(e) = request.as_reader().read_to_string(&mut body) {
+        return error_response(400, anyhow!(e));
+    }
+    let api_request = ApiRequest {
+        method: request.method().clone(),
+        url: request.url(),
+        headers: request.headers(),
+        body: &body,
+    };
+    handle(&api_request, service, clock, config, journal_path, dry_run)
+}
+
+/// Serve the API on `host:port` until the process is killed
+///
+/// Port 0 picks a free port, the actual address is printed.
+///
+/// With `dry_run` (`rtw --dry serve`) mutations are only planned.
+pub(crate) fn serve_api<S, Cl>(
+    service: &mut Service<S>,
+    clock: &Cl,
+    config: &RtwConfig,
+    journal_path: &Path,
+    host: &str,
+    port: u16,
+    dry_run: bool,
+) -> anyhow::Result<()>
+where
+    S: Storage,
+    Cl: Clock,
+{
+    let server = Server::http((host, port)).map_err(|e| anyhow!(e))?;
+    match server.server_addr().to_ip() {
+        Some(address) => println!("Serving API on http://{}/", address),
+        None => println!("Serving API"),
+    }
+    if dry_run {
+        println!("(dry-run) mutations are planned, nothing is done");
+    }
+    for mut request in server.incoming_requests() {
+        let response = respond(&mut request, service, clock, config, journal_path, dry_run);
+        if let Err(e) = request.respond(response) {
+            eprintln!("could not send response: {}", e);
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::chrono_clock::ChronoClock;
+    use crate::json_storage::JsonStorage;
+    use crate::rest_api::{handle, parse_action, ApiRequest};
+    use crate::rtw_cli::RtwAction;
+    use crate::rtw_config::RtwConfig;
+    use crate::rtw_core::service::ActivityService;
+    use crate::service::Service;
+    use tempfile::tempdir;
+    use tiny_http::{Header, Method};
+
+    #[test]
+    fn test_parse_action() {
+        let clock = ChronoClock {};
+        let action = parse_action(
+            &Method::Post,
+            "/activities/start",
+            "{\"tags\": [\"foo\"], \"start\": \"09:00\"}",
+            &clock,
+        )
+        .unwrap();
+        assert!(matches!(action, Some(RtwAction::Start(_, tags, None, _)) if tags == vec!["foo"]));
+        let action = parse_action(&Method::Post, "/activities/stop", "", &clock).unwrap();
+        assert!(matches!(action, Some(RtwAction::Stop(_, None))));
+        let action = parse_action(&Method::Delete, "/activities/0", "", &clock).unwrap();
+        assert!(matches!(action, Some(RtwAction::Delete(_))));
+        assert!(parse_action(&Method::Post, "/activities/track", "{}", &clock).is_err());
+        assert!(parse_action(&Method::Post, "/activities/start", "{\"foo\": 1}", &clock).is_err());
+        assert!(parse_action(&Method::Get, "/foo", "", &clock)
+            .unwrap()
+            .is_none());
+    }
+
+    #[test]
+    fn test_handle() {
+        let clock = ChronoClock {};
+        let config = RtwConfig::default();
+        let test_dir = tempdir().expect("error while creating tempdir");
+        let journal_path = test_dir.path().join(".rtw.journal.json");
+        let mut service = Service::new(JsonStorage::new(
+            test_dir.path().join(".rtwc.json"),
+            test_dir.path().join(".rtwh.json"),
+        ));
+        let json = Header::from_bytes("Content-Type", "application/json; charset=utf-8").unwrap();
+        let origin = Header::from_bytes("Origin", "https://example.com").unwrap();
+        let mut send = |method: Method, url: &str, headers: &[Header], body: &str| {
+            let request = ApiRequest {
+                method,
+                url,
+                headers,
+                body,
+            };
+            let response = handle(
+                &request,
+                &mut service,
+                &clock,
+                &config,
+                &journal_path,
+                false,
+            );
+            let status_code = response.status_code().0;
+            let body = String::from_utf8(response.into_reader().into_inner()).unwrap();
+            (status_code, body)
+        };
+        let start = "{\"tags\": [\"foo\"]}";
+        let (status_code, _) = send(Method::Post, "/activities/start", &[], start);
+        assert_eq!(status_code, 415);
+        let headers = [json.clone(), origin.clone()];
+        let (status_code, _) = send(Method::Post, "/activities/start", &headers, start);
+        assert_eq!(status_code, 403);
+        let (status_code, _) = send(Method::Delete, "/activities/0", &[origin], "");
+        assert_eq!(status_code, 403);
+        let headers = [json];
+        let (status_code, planned) = send(Method::Post, "/activities/start?dry", &headers, start);
+        assert_eq!(status_code, 200);
+        assert!(planned.contains("\"dry_run\":true"));
+        let (_, ongoing) = send(Method::Get, "/activities/ongoing", &[], "");
+        assert_eq!(ongoing, "[]\n");
+        let (status_code, started) = send(Method::Post, "/activities/start", &headers, start);
+        assert_eq!(status_code, 200);
+        assert!(started.contains("\"type\":\"start\""));
+        // relative ids are meaningless for changes, activities are identified by uid
+        assert!(started.contains("\"uid\":"));
+        assert!(!started.contains("\"id\":"));
+        let (_, ongoing) = send(Method::Get, "/activities/ongoing", &[], "");
+        assert!(ongoing.contains("\"tags\":[\"foo\"]"));
+        let (status_code, _) = send(Method::Post, "/activities/track", &headers, "{}");
+        assert_eq!(status_code, 400);
+        let (status_code, _) = send(Method::Post, "/foo", &headers, "");
+        assert_eq!(status_code, 404);
+    }
+
+    #[test]
+    fn test_handle_dry_run() {
+        let clock = ChronoClock {};
+        let config = RtwConfig::default();
+        let test_dir = tempdir().expect("error while creating tempdir");
+        let journal_path = test_dir.path().join(".rtw.journal.json");
+        let mut service = Service::new(JsonStorage::new(
+            test_dir.path().join(".rtwc.json"),
+            test_dir.path().join(".rtwh.json"),
+        ));
+        let json = Header::from_bytes("Content-Type", "application/json").unwrap();
+        let request = ApiRequest {
+            method: Method::Post,
+            url: "/activities/start",
+            headers: &[json],
+            body: "{\"tags\": [\"foo\"]}",
+        };
+        let response = handle(&request, &mut service, &clock, &config, &journal_path, true);
+        assert_eq!(response.status_code().0, 200);
+        let body = String::from_utf8(response.into_reader().into_inner()).unwrap();
+        assert!(body.contains("\"dry_run\":true"));
+        assert!(service.get_ongoing_activities().unwrap().is_empty());
+    }
+}
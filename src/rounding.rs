@@ -0,0 +1,88 @@
+//! Duration rounding (e.g. billing in 15 minutes increments).
+//!
+//! The rounded duration of a group of activities (report entry, invoice item...) is either the sum
+//! of its rounded activities (`RoundingScope::Activity`) or its rounded sum (`RoundingScope::Group`).
+use crate::json_export::seconds;
+use crate::rtw_config::{Rounding, RoundingMode, RoundingScope};
+use crate::rtw_core::durationw::DurationW;
+use chrono::Duration;
+
+impl Rounding {
+    /// Round `duration` to a multiple of the increment (unchanged if the increment is 0)
+    pub fn round(&self, duration: DurationW) -> DurationW {
+        let increment = i64::from(self.increment_minutes) * 60;
+        if increment == 0 {
+            return duration;
+        }
+        let raw = seconds(duration);
+        let rounded = match self.mode {
+            RoundingMode::Up => (raw + increment - 1).div_euclid(increment),
+            RoundingMode::Down => raw.div_euclid(increment),
+            RoundingMode::Nearest => (raw + increment / 2).div_euclid(increment),
+        } * increment;
+        Duration::seconds(rounded).into()
+    }
+
+    /// Rounded duration of a group of activities given their (net) `durations`
+    pub fn round_group(&self, durations: &[DurationW]) -> DurationW {
+        match self.scope {
+            RoundingScope::Activity => durations.iter().map(|d| self.round(*d)).sum(),
+            RoundingScope::Group => self.round(durations.iter().copied().sum()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::rtw_config::{Rounding, RoundingMode, RoundingScope};
+    use crate::rtw_core::durationw::DurationW;
+    use chrono::Duration;
+
+    fn minutes(m: i64) -> DurationW {
+        Duration::minutes(m).into()
+    }
+
+    #[test]
+    fn test_round() {
+        let rounding = |mode| Rounding {
+            increment_minutes: 15,
+            mode,
+            scope: RoundingScope::Group,
+        };
+        assert_eq!(rounding(RoundingMode::Up).round(minutes(16)), minutes(30));
+        assert_eq!(rounding(RoundingMode::Up).round(minutes(15)), minutes(15));
+        assert_eq!(rounding(RoundingMode::Down).round(minutes(29)), minutes(15));
+        assert_eq!(
+            rounding(RoundingMode::Nearest).round(minutes(22)),
+            minutes(15)
+        );
+        assert_eq!(
+            rounding(RoundingMode::Nearest).round(minutes(23)),
+            minutes(30)
+        );
+        assert_eq!(rounding(RoundingMode::Up).round(minutes(0)), minutes(0));
+        let none = Rounding {
+            increment_minutes: 0,
+            mode: RoundingMode::Up,
+            scope: RoundingScope::Group,
+        };
+        assert_eq!(none.round(minutes(7)), minutes(7));
+    }
+
+    #[test]
+    fn test_round_group() {
+        let durations = [minutes(10), minutes(10), minutes(10)];
+        let group = Rounding {
+            increment_minutes: 15,
+            mode: RoundingMode::Up,
+            scope: RoundingScope::Group,
+        };
+        assert_eq!(group.round_group(&durations), minutes(30));
+        let activity = Rounding {
+            scope: RoundingScope::Activity,
+            ..group
+        };
+        assert_eq!(activity.round_group(&durations), minutes(45));
+        assert_eq!(activity.round_group(&[]), minutes(0));
+    }
+}
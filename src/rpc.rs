@@ -0,0 +1,455 @@
+//! JSON-RPC 2.0 over stdio (`rtw rpc`) for editor integrations.
+//!
+//! One request per line on stdin, one response per line on stdout.
+//! An `ongoing_changed` notification is sent whenever the set of ongoing activities changes
+//! (through RPC calls or any other rtw process).
+use crate::journal::Journal;
+use crate::json_export::ActivityJson;
+use crate::rest_api::{MutationJson, MutationResponse};
+use crate::rtw_cli::{dry_run_action, run_mutation, RtwAction, RtwMutation};
+use crate::rtw_config::RtwConfig;
+use crate::rtw_core::activity::OngoingActivity;
+use crate::rtw_core::clock::Clock;
+use crate::rtw_core::datetimew::DateTimeW;
+use crate::rtw_core::service::ActivityService;
+use crate::rtw_core::storage::Storage;
+use crate::rtw_core::{ActivitySelector, Description, Metadata, Tags};
+use crate::service::Service;
+use crate::time_tools::TimeTools;
+use chrono::{Local, TimeZone};
+use serde::de::DeserializeOwned;
+use serde::Deserialize;
+use serde_json::{json, Value};
+use std::io::{BufRead, Write};
+use std::path::Path;
+use std::str::FromStr;
+use std::sync::mpsc::{channel, RecvTimeoutError};
+use std::time::Duration;
+
+const PARSE_ERROR: i64 = -32700;
+const INVALID_REQUEST: i64 = -32600;
+const METHOD_NOT_FOUND: i64 = -32601;
+const INVALID_PARAMS: i64 = -32602;
+/// Application error e.g. no ongoing activity or overlapping activities
+const SERVER_ERROR: i64 = -32000;
+
+#[derive(Debug, Deserialize)]
+struct RpcRequest {
+    jsonrpc: String,
+    /// absent for notifications (no response)
+    id: Option<Value>,
+    method: String,
+    #[serde(default)]
+    params: Value,
+}
+
+#[derive(Debug)]
+struct RpcError {
+    code: i64,
+    message: String,
+}
+
+impl RpcError {
+    fn new(code: i64, message: impl ToString) -> Self {
+        RpcError {
+            code,
+            message: message.to_string(),
+        }
+    }
+
+    fn server(error: anyhow::Error) -> Self {
+        RpcError::new(SERVER_ERROR, error)
+    }
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct StartParams {
+    tags: Tags,
+    description: Option<Description>,
+    metadata: Metadata,
+    /// time clue, now when omitted
+    start: Option<String>,
+    dry_run: bool,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct TrackParams {
+    start: String,
+    stop: String,
+    #[serde(default)]
+    tags: Tags,
+    #[serde(default)]
+    description: Option<Description>,
+    #[serde(default)]
+    metadata: Metadata,
+    #[serde(default)]
+    dry_run: bool,
+}
+
+/// `stop_ongoing_activity`, `cancel_ongoing_activity` and `delete_activity` params
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct SelectParams {
+    /// activity id or uid (required by `delete_activity`)
+    id: Option<String>,
+    /// time clue, now when omitted (`stop_ongoing_activity` only)
+    time: Option<String>,
+    dry_run: bool,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct FilterParams {
+    /// time clue
+    from: Option<String>,
+    /// time clue, now when omitted
+    to: Option<String>,
+    /// activities with at least one of these tags (all if empty)
+    tags: Tags,
+}
+
+// Omitted params are parsed as `{}`
+fn parse_params<T: DeserializeOwned>(params: Value) -> Result<T, RpcError> {
+    let params = if params.is_null() { json!({}) } else { params };
+    serde_json::from_value(params).map_err(|e| RpcError::new(INVALID_PARAMS, e))
+}
+
+fn parse_time(time_maybe: Option<&str>, clock: &dyn Clock) -> Result<DateTimeW, RpcError> {
+    match time_maybe {
+        None => Ok(clock.get_time()),
+        Some(time_str) => TimeTools::time_from_str(time_str, clock)
+            .map(|time| clock.date_time(time))
+            .map_err(|e| RpcError::new(INVALID_PARAMS, e)),
+    }
+}
+
+fn parse_selector(id_maybe: Option<&str>) -> Result<Option<ActivitySelector>, RpcError> {
+    id_maybe
+        .map(ActivitySelector::from_str)
+        .transpose()
+        .map_err(|e| RpcError::new(INVALID_PARAMS, e))
+}
+
+fn ongoing_json<S: Storage>(
+    service: &Service<S>,
+    clock: &dyn Clock,
+) -> anyhow::Result<Vec<ActivityJson>> {
+    let now = clock.get_time();
+    Ok(service
+        .get_ongoing_activities()?
+        .iter()
+        .map(|(id, a)| ActivityJson::from_ongoing(*id, a, now))
+        .collect())
+}
+
+fn filter_activities<S: Storage>(
+    params: FilterParams,
+    service: &Service<S>,
+    clock: &dyn Clock,
+) -> Result<Vec<ActivityJson>, RpcError> {
+    let finished = match (&params.from, &params.to) {
+        (None, None) => service.get_finished_activities(),
+        (from, to) => {
+            let range_start = match from {
+                None => Local.timestamp(0, 0).into(),
+                Some(from) => parse_time(Some(from), clock)?,
+            };
+            let range_end = parse_time(to.as_deref(), clock)?;
+            service.filter_activities_by_range(range_start, range_end)
+        }
+    }
+    .map_err(RpcError::server)?;
+    Ok(finished
+        .iter()
+        .filter(|(_id, a)| {
+            params.tags.is_empty() || a.get_tags().iter().any(|t| params.tags.contains(t))
+        })
+        .map(|(id, a)| ActivityJson::from_finished(*id, a))
+        .collect())
+}
+
+// Translate a mutating method to an action (and whether it is a dry run), `None` for read-only methods
+fn parse_action(
+    method: &str,
+    params: Value,
+    clock: &dyn Clock,
+) -> Result<Option<(RtwAction, bool)>, RpcError> {
+    let parsed = match method {
+        "start_activity" => {
+            let params: StartParams = parse_params(params)?;
+            let start_time = parse_time(params.start.as_deref(), clock)?;
+            let action =
+                RtwAction::Start(start_time, params.tags, params.description, params.metadata);
+            (action, params.dry_run)
+        }
+        "track_activity" => {
+            let params: TrackParams = parse_params(params)?;
+            let start_time = parse_time(Some(&params.start), clock)?;
+            let stop_time = parse_time(Some(&params.stop), clock)?;
+            let action = RtwAction::Track(
+                (start_time, stop_time),
+                params.tags,
+                params.description,
+                params.metadata,
+            );
+            (action, params.dry_run)
+        }
+        "stop_ongoing_activity" => {
+            let params: SelectParams = parse_params(params)?;
+            let stop_time = parse_time(params.time.as_deref(), clock)?;
+            let selector = parse_selector(params.id.as_deref())?;
+            (RtwAction::Stop(stop_time, selector), params.dry_run)
+        }
+        "cancel_ongoing_activity" => {
+            let params: SelectParams = parse_params(params)?;
+            let selector = parse_selector(params.id.as_deref())?;
+            (RtwAction::Cancel(selector), params.dry_run)
+        }
+        "delete_activity" => {
+            let params: SelectParams = parse_params(params)?;
+            let selector = parse_selector(params.id.as_deref())?
+                .ok_or_else(|| RpcError::new(INVALID_PARAMS, "missing id"))?;
+            (RtwAction::Delete(selector), params.dry_run)
+        }
+        _ => return Ok(None),
+    };
+    Ok(Some(parsed))
+}
+
+// `dry_run`: only plan mutations, whatever the params ask
+fn call<S: Storage, Cl: Clock>(
+    method: &str,
+    params: Value,
+    service: &mut Service<S>,
+    clock: &Cl,
+    config: &RtwConfig,
+    journal_path: &Path,
+    dry_run: bool,
+) -> Result<Value, RpcError> {
+    match method {
+        "get_ongoing_activities" => {
+            return ongoing_json(service, clock)
+                .map(|ongoing| json!(ongoing))
+                .map_err(RpcError::server);
+        }
+        "filter_activities" => {
+            let params: FilterParams = parse_params(params)?;
+            return filter_activities(params, service, clock).map(|finished| json!(finished));
+        }
+        _ => {}
+    }
+    let (action, dry_run_param) = parse_action(method, params, clock)?
+        .ok_or_else(|| RpcError::new(METHOD_NOT_FOUND, format!("unknown method: {}", method)))?;
+    let dry_run = dry_run || dry_run_param;
+    let mut journal = Journal::load(journal_path.to_path_buf()).map_err(RpcError::server)?;
+    // stdout is reserved to JSON-RPC messages, the planned change is kept for error messages
+    let mut planned = vec![];
+    let mutation = dry_run_action(action, service, clock, config, &journal, &mut planned)
+        .map_err(RpcError::server)?;
+    if matches!(mutation, RtwMutation::Pure) {
+        // nothing to do e.g. no ongoing activity
+        let reason = String::from_utf8_lossy(&planned);
+        return Err(RpcError::new(SERVER_ERROR, reason.trim()));
+    }
+    let response = MutationResponse {
+        dry_run,
+        mutation: MutationJson::new(&mutation, clock.get_time()),
+    };
+    if !dry_run {
+        run_mutation(mutation, service, clock, config, &mut journal).map_err(RpcError::server)?;
+    }
+    Ok(json!(response))
+}
+
+// Handle one line, returns the response if any (notifications have none)
+fn handle_line<S: Storage, Cl: Clock>(
+    line: &str,
+    service: &mut Service<S>,
+    clock: &Cl,
+    config: &RtwConfig,
+    journal_path: &Path,
+    dry_run: bool,
+) -> Option<Value> {
+    let error = |id: Value, error: RpcError| {
+        json!({
+            "jsonrpc": "2.0",
+            "id": id,
+            "error": {"code": error.code, "message": error.message},
+        })
+    };
+    let value: Value = match serde_json::from_str(line) {
+        Ok(value) => value,
+        Err(e) => return Some(error(Value::Null, RpcError::new(PARSE_ERROR, e))),
+    };
+    let id_maybe = value.get("id").cloned();
+    let request: RpcRequest = match serde_json::from_value::<RpcRequest>(value) {
+        Ok(request) if request.jsonrpc == "2.0" => request,
+        Ok(_) => {
+            let e = RpcError::new(INVALID_REQUEST, "jsonrpc must be \"2.0\"");
+            return Some(error(id_maybe.unwrap_or(Value::Null), e));
+        }
+        Err(e) => {
+            let e = RpcError::new(INVALID_REQUEST, e);
+            return Some(error(id_maybe.unwrap_or(Value::Null), e));
+        }
+    };
+    let result = call(
+        &request.method,
+        request.params,
+        service,
+        clock,
+        config,
+        journal_path,
+        dry_run,
+    );
+    let id = request.id?;
+    Some(match result {
+        Ok(result) => json!({"jsonrpc": "2.0", "id": id, "result": result}),
+        Err(e) => error(id, e),
+    })
+}
+
+fn send(value: &Value) -> anyhow::Result<()> {
+    let stdout = std::io::stdout();
+    let mut stdout = stdout.lock();
+    writeln!(stdout, "{}", value)?;
+    stdout.flush()?;
+    Ok(())
+}
+
+/// Serve JSON-RPC requests from stdin until stdin is closed
+///
+/// Ongoing activities are checked after each request and every `poll_interval`.
+///
+/// With `dry_run` (`rtw --dry rpc`) mutations are only planned.
+pub(crate) fn serve_rpc<S, Cl>(
+    service: &mut Service<S>,
+    clock: &Cl,
+    config: &RtwConfig,
+    journal_path: &Path,
+    poll_interval: Duration,
+    dry_run: bool,
+) -> anyhow::Result<()>
+where
+    S: Storage,
+    Cl: Clock,
+{
+    let (sender, receiver) = channel();
+    std::thread::spawn(move || {
+        for line in std::io::stdin().lock().lines() {
+            if line.map(|line| sender.send(line)).is_err() {
+                break;
+            }
+        }
+    });
+    let ongoing_activities = |service: &Service<S>| -> anyhow::Result<Vec<OngoingActivity>> {
+        Ok(service
+            .get_ongoing_activities()?
+            .into_iter()
+            .map(|(_id, a)| a)
+            .collect())
+    };
+    let mut ongoing = ongoing_activities(service)?;
+    loop {
+        match receiver.recv_timeout(poll_interval) {
+            Ok(line) if line.trim().is_empty() => {}
+            Ok(line) => {
+                let response = handle_line(&line, service, clock, config, journal_path, dry_run);
+                if let Some(response) = response {
+                    send(&response)?;
+                }
+            }
+            Err(RecvTimeoutError::Timeout) => {}
+            Err(RecvTimeoutError::Disconnected) => return Ok(()),
+        }
+        let current = ongoing_activities(service)?;
+        if current != ongoing {
+            ongoing = current;
+            send(&json!({
+                "jsonrpc": "2.0",
+                "method": "ongoing_changed",
+                "params": {"ongoing": ongoing_json(service, clock)?},
+            }))?;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::chrono_clock::ChronoClock;
+    use crate::json_storage::JsonStorage;
+    use crate::rpc::handle_line;
+    use crate::rtw_config::RtwConfig;
+    use crate::rtw_core::service::ActivityService;
+    use crate::service::Service;
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_handle_line() {
+        let clock = ChronoClock {};
+        let config = RtwConfig::default();
+        let test_dir = tempdir().expect("error while creating tempdir");
+        let journal_path = test_dir.path().join(".rtw.journal.json");
+        let mut service = Service::new(JsonStorage::new(
+            test_dir.path().join(".rtwc.json"),
+            test_dir.path().join(".rtwh.json"),
+        ));
+        let mut handle = |line: &str| {
+            handle_line(line, &mut service, &clock, &config, &journal_path, false)
+                .map(|response| response.to_string())
+        };
+        let started = handle(
+            r#"{"jsonrpc": "2.0", "id": 1, "method": "start_activity", "params": {"tags": ["foo"]}}"#,
+        )
+        .unwrap();
+        assert!(started.contains(r#""dry_run":false"#));
+        assert!(started.contains(r#""type":"start""#));
+        // no relative id in the started activity, only its uid
+        assert!(started.contains(r#""uid":"#) && !started.contains(r#""id":0"#));
+        // notifications have no response
+        assert_eq!(
+            handle(r#"{"jsonrpc": "2.0", "method": "get_ongoing_activities"}"#),
+            None
+        );
+        let ongoing =
+            handle(r#"{"jsonrpc": "2.0", "id": 2, "method": "get_ongoing_activities"}"#).unwrap();
+        assert!(ongoing.contains(r#""tags":["foo"]"#));
+        let stopped = handle(
+            r#"{"jsonrpc": "2.0", "id": 3, "method": "stop_ongoing_activity", "params": {"dry_run": true}}"#,
+        )
+        .unwrap();
+        assert!(stopped.contains(r#""dry_run":true"#));
+        let cancelled = handle(
+            r#"{"jsonrpc": "2.0", "id": 7, "method": "cancel_ongoing_activity", "params": {"id": "1"}}"#,
+        )
+        .unwrap();
+        assert!(cancelled.contains("-32000"));
+        assert!(cancelled.contains("No ongoing activity with id 1."));
+        let unknown = handle(r#"{"jsonrpc": "2.0", "id": 4, "method": "foo"}"#).unwrap();
+        assert!(unknown.contains("-32601"));
+        let invalid = handle(r#"{"jsonrpc": "2.0", "id": 5, "method": "track_activity"}"#).unwrap();
+        assert!(invalid.contains("-32602"));
+        assert!(handle("{").unwrap().contains("-32700"));
+        assert!(handle(r#"{"id": 6}"#).unwrap().contains("-32600"));
+    }
+
+    #[test]
+    fn test_handle_line_dry_run() {
+        let clock = ChronoClock {};
+        let config = RtwConfig::default();
+        let test_dir = tempdir().expect("error while creating tempdir");
+        let journal_path = test_dir.path().join(".rtw.journal.json");
+        let mut service = Service::new(JsonStorage::new(
+            test_dir.path().join(".rtwc.json"),
+            test_dir.path().join(".rtwh.json"),
+        ));
+        let line = r#"{"jsonrpc": "2.0", "id": 1, "method": "start_activity", "params": {"tags": ["foo"], "dry_run": false}}"#;
+        let started = handle_line(line, &mut service, &clock, &config, &journal_path, true)
+            .unwrap()
+            .to_string();
+        assert!(started.contains(r#""dry_run":true"#));
+        assert!(service.get_ongoing_activities().unwrap().is_empty());
+    }
+}
@@ -1,58 +1,293 @@
 //! Translate CLI args to calls to activity Service.
 use crate::cli_helper;
+use crate::cli_helper::{
+    ActivityEdit, DumpFormat, ImportSource, MergeSelection, OutputFormat, SummaryOptions,
+};
+use crate::csv::{export_activities_to_csv, read_csv_activities};
+use crate::hooks::HookEvent;
 use crate::ical_export::export_activities_to_ical;
+use crate::ical_import::read_ics_activities;
+use crate::invoice::{build_invoice, render_html, render_markdown, InvoiceFormat};
+use crate::journal::{Change, Journal, JournalEntry};
+use crate::json_export::{seconds, to_json_output, ActivityJson, ReportJson, TagNodeJson};
 use crate::rtw_cli::OptionalOrAmbiguousOrNotFound::Optional;
-use crate::rtw_config::RtwConfig;
-use crate::rtw_core::activity::{Activity, OngoingActivity};
+use crate::rtw_config::{Hooks, Rounding, RoundingScope, RtwConfig};
+use crate::rtw_core::activity::{intersect, overlap, Activity, OngoingActivity};
 use crate::rtw_core::clock::Clock;
 use crate::rtw_core::datetimew::DateTimeW;
 use crate::rtw_core::durationw::DurationW;
 use crate::rtw_core::service::ActivityService;
 use crate::rtw_core::storage::Storage;
-use crate::rtw_core::ActivityId;
-use crate::rtw_core::{Description, Tags};
+use crate::rtw_core::{ActivityId, ActivitySelector, ActivityUid};
+use crate::rtw_core::{Description, Metadata, Tag, Tags};
 use crate::service::Service;
 use crate::status::{format_status, FormatString};
+use crate::tag_tree::{build_tag_tree, percent, TagNode};
 use crate::timeline::render_days;
+use crate::timewarrior::{export_activities_to_timewarrior, read_timewarrior_data};
+use chrono::Duration;
 use clap::ArgMatches;
 use itertools::Itertools;
+use std::io::Write;
+use std::path::PathBuf;
 
 type ActivityWithId = (ActivityId, Activity);
 
+/// Label of the raw and rounded totals line (see `rounding` config)
+const TOTAL_LABEL: &str = "Total";
+
 /// Describe the action to be made
 ///
 /// see `run`
 pub enum RtwAction {
-    Cancel(Option<ActivityId>),
-    Start(DateTimeW, Tags, Option<Description>),
-    Track((DateTimeW, DateTimeW), Tags, Option<Description>),
-    Stop(DateTimeW, Option<ActivityId>),
-    Summary((DateTimeW, DateTimeW), bool, bool, bool),
-    DumpICal((DateTimeW, DateTimeW)),
-    Continue(Option<ActivityId>),
-    Delete(ActivityId),
-    DisplayCurrent,
+    Cancel(Option<ActivitySelector>),
+    Start(DateTimeW, Tags, Option<Description>, Metadata),
+    Track((DateTimeW, DateTimeW), Tags, Option<Description>, Metadata),
+    Stop(DateTimeW, Option<ActivitySelector>),
+    Summary((DateTimeW, DateTimeW), SummaryOptions),
+    Dump((DateTimeW, DateTimeW), DumpFormat, Option<PathBuf>),
+    Continue(Option<ActivitySelector>),
+    Delete(ActivitySelector),
+    Edit(ActivitySelector, ActivityEdit),
+    Modify(Option<ActivitySelector>, ActivityEdit),
+    Split(ActivitySelector, DateTimeW, Option<Tags>),
+    Merge(MergeSelection),
+    Import(ImportSource, bool),
+    Pause(DateTimeW, Option<ActivitySelector>),
+    Resume(DateTimeW, Option<ActivitySelector>),
+    DisplayCurrent(OutputFormat),
     Timeline((DateTimeW, DateTimeW)),
     Completion(clap::Shell),
     Status(Option<FormatString>),
+    /// Serve iCalendar feed on (host, port), the server itself is run from `main`
+    ServeIcs(String, u16),
+    /// Invoice of a client
+    Invoice((DateTimeW, DateTimeW), String, InvoiceFormat),
+    Undo,
+    Redo,
 }
 
 pub enum RtwMutation {
     Start(OngoingActivity),
     Track(Activity),
-    Stop(DateTimeW, ActivityId),
-    Delete(ActivityId),
-    Cancel(ActivityId),
+    Stop(DateTimeW, ActivityUid),
+    Delete(ActivityUid),
+    Cancel(ActivityUid),
+    Edit(ActivityUid, Activity),
+    Modify(ActivityUid, OngoingActivity),
+    Split(JournalEntry),
+    Merge(JournalEntry),
+    Import(JournalEntry),
+    /// Write (path, content) files
+    WriteFiles(Vec<(PathBuf, String)>),
+    Pause(ActivityUid, OngoingActivity),
+    Resume(ActivityUid, OngoingActivity),
+    Undo(JournalEntry),
+    Redo(JournalEntry),
     Pure,
 }
 
 enum OptionalOrAmbiguousOrNotFound {
     Optional(Option<(ActivityId, OngoingActivity)>),
     Ambiguous,
-    NotFound(ActivityId),
+    NotFound(ActivitySelector),
+}
+
+/// Uids are displayed shortened, any prefix of `UID_PREFIX_MIN_LEN` characters is accepted as input
+///
+/// Digits only prefixes are extended up to the first non-digit (they would be read as an id).
+fn short_uid(uid: &ActivityUid) -> String {
+    let uid = uid.to_string();
+    let first_non_digit = uid.find(|c: char| !c.is_ascii_digit()).unwrap_or(uid.len());
+    let len = std::cmp::max(crate::rtw_core::UID_PREFIX_MIN_LEN, first_non_digit + 1);
+    uid[..std::cmp::min(len, uid.len())].to_string()
+}
+
+fn print_change(change: &Change, out: &mut dyn Write) -> anyhow::Result<()> {
+    match change {
+        Change::AddFinished(a) => {
+            writeln!(out, "Recorded {}", a.get_title())?;
+            writeln!(out, "Started {:>20}", a.get_start_time())?;
+            writeln!(out, "Ended   {:>20}", a.get_stop_time())?;
+            writeln!(out, "Total   {:>20}", a.get_duration())?;
+        }
+        Change::RemoveFinished(a) => {
+            writeln!(out, "Deleted {}", a.get_title())?;
+            writeln!(out, "Started {:>20}", a.get_start_time())?;
+            writeln!(out, "Ended   {:>20}", a.get_stop_time())?;
+            writeln!(out, "Total   {:>20}", a.get_duration())?;
+        }
+        Change::AddOngoing(a) => {
+            writeln!(out, "Tracking {}", a.get_title())?;
+            writeln!(out, "Started  {}", a.get_start_time())?;
+        }
+        Change::RemoveOngoing(a) => {
+            writeln!(out, "Cancelled {}", a.get_title())?;
+            writeln!(out, "Started   {:>20}", a.get_start_time())?;
+        }
+    }
+    Ok(())
+}
+
+/// Ongoing activity with `uid` before it gets stopped
+fn find_ongoing_activity<S: Storage>(
+    uid: ActivityUid,
+    service: &Service<S>,
+) -> anyhow::Result<Option<OngoingActivity>> {
+    Ok(service
+        .get_ongoing_activities()?
+        .into_iter()
+        .map(|(_id, a)| a)
+        .find(|a| a.uid == uid))
+}
+
+/// Group consecutive activities with same tags separated by at most `gap`
+///
+/// The gap is measured from the latest stop time of the group.
+/// Only groups of at least 2 activities are returned.
+fn group_by_gap(activities: &[ActivityWithId], gap: DurationW) -> Vec<Vec<Activity>> {
+    let sorted: Vec<Activity> = activities
+        .iter()
+        .map(|(_i, a)| a.clone())
+        .sorted()
+        .collect();
+    let mut groups: Vec<Vec<Activity>> = vec![];
+    for activity in sorted {
+        match groups.last_mut() {
+            Some(group)
+                if group.iter().all(|a| a.get_tags() == activity.get_tags())
+                    && group
+                        .iter()
+                        .map(Activity::get_stop_time)
+                        .max()
+                        .is_some_and(|stop| activity.get_start_time() - stop <= gap) =>
+            {
+                group.push(activity)
+            }
+            _ => groups.push(vec![activity]),
+        }
+    }
+    groups.into_iter().filter(|group| group.len() > 1).collect()
 }
 
-fn merge_same_tags(activities: &[ActivityWithId]) -> Vec<(ActivityId, Activity, DurationW, usize)> {
+/// Select imported activities that can be added, print what would be imported
+///
+/// Activities already present (same uid) are ignored,
+/// overlapping activities are either skipped or make the import fail.
+fn prepare_import<S: Storage>(
+    finished: Vec<Activity>,
+    ongoing: Vec<OngoingActivity>,
+    skip_overlapping: bool,
+    service: &Service<S>,
+    config: &RtwConfig,
+    out: &mut dyn Write,
+) -> anyhow::Result<RtwMutation> {
+    let existing: Vec<Activity> = service
+        .get_finished_activities()?
+        .into_iter()
+        .map(|(_i, a)| a)
+        .collect();
+    let existing_ongoing = service.get_ongoing_activities()?;
+    let mut accepted: Vec<Activity> = vec![];
+    let mut accepted_ongoing: Vec<OngoingActivity> = vec![];
+    let mut already_imported = 0;
+    let mut skipped = 0;
+    for activity in finished.into_iter().sorted() {
+        let uid = activity.get_uid();
+        if existing
+            .iter()
+            .chain(accepted.iter())
+            .any(|a| a.get_uid() == uid)
+        {
+            already_imported += 1;
+            continue;
+        }
+        let intersections: Vec<&Activity> = existing
+            .iter()
+            .chain(accepted.iter())
+            .filter(|a| overlap(a, &activity).is_some())
+            .collect();
+        if config.deny_overlapping && !intersections.is_empty() {
+            if skip_overlapping {
+                skipped += 1;
+                continue;
+            }
+            return Err(anyhow::anyhow!(
+                "{:?} would overlap {:?}\n\
+                Tip: you can skip overlapping activities using `--skip-overlapping` \
+                or enable overlapping using `rtw --overlap import ...`",
+                activity,
+                intersections
+            ));
+        }
+        accepted.push(activity);
+    }
+    for activity in ongoing {
+        if existing_ongoing.iter().any(|(_i, a)| a.uid == activity.uid) {
+            already_imported += 1;
+        } else if config.deny_overlapping
+            && (!existing_ongoing.is_empty()
+                || !accepted_ongoing.is_empty()
+                || existing
+                    .iter()
+                    .chain(accepted.iter())
+                    .any(|a| intersect(a, &activity.start_time).is_some()))
+        {
+            if skip_overlapping {
+                skipped += 1;
+            } else {
+                return Err(anyhow::anyhow!(
+                    "ongoing {:?} would overlap existing activities\n\
+                    Tip: you can skip overlapping activities using `--skip-overlapping` \
+                    or enable overlapping using `rtw --overlap import ...`",
+                    activity
+                ));
+            }
+        } else {
+            accepted_ongoing.push(activity);
+        }
+    }
+    if already_imported > 0 {
+        writeln!(
+            out,
+            "Ignored  {} already imported activities",
+            already_imported
+        )?;
+    }
+    if skipped > 0 {
+        writeln!(out, "Skipped  {} overlapping activities", skipped)?;
+    }
+    if accepted.is_empty() && accepted_ongoing.is_empty() {
+        writeln!(out, "Nothing to import.")?;
+        return Ok(RtwMutation::Pure);
+    }
+    writeln!(out, "Imported {} finished activities", accepted.len())?;
+    if !accepted_ongoing.is_empty() {
+        writeln!(
+            out,
+            "Imported {} ongoing activities",
+            accepted_ongoing.len()
+        )?;
+    }
+    let changes = accepted
+        .into_iter()
+        .map(Change::AddFinished)
+        .chain(accepted_ongoing.into_iter().map(Change::AddOngoing))
+        .collect();
+    Ok(RtwMutation::Import(JournalEntry::new("import", changes)))
+}
+
+/// Activities with same tags summed up (see `summary --report`)
+struct ReportEntry {
+    activity: Activity,
+    duration: DurationW,
+    net_duration: DurationW,
+    has_breaks: bool,
+    segments: usize,
+    net_durations: Vec<DurationW>,
+}
+
+fn merge_same_tags(activities: &[ActivityWithId]) -> Vec<ReportEntry> {
     let uniques: Vec<ActivityWithId> = activities
         .iter()
         .cloned()
@@ -61,24 +296,230 @@ fn merge_same_tags(activities: &[ActivityWithId]) -> Vec<(ActivityId, Activity,
     uniques
         .iter()
         .cloned()
-        .map(|(i, activity)| {
-            let same_tag = activities
+        .map(|(_i, activity)| {
+            let same_tag: Vec<&Activity> = activities
                 .iter()
-                .filter(|(_i, other)| activity.get_title() == other.get_title());
-            let durations: Vec<DurationW> = same_tag.map(|(_i, a)| a.get_duration()).collect();
-            let segments = durations.len();
-            let duration = durations.into_iter().sum();
-            (i, activity, duration, segments)
+                .filter(|(_i, other)| activity.get_title() == other.get_title())
+                .map(|(_i, other)| other)
+                .collect();
+            ReportEntry {
+                duration: same_tag.iter().map(|a| a.get_duration()).sum(),
+                net_duration: same_tag.iter().map(|a| a.get_net_duration()).sum(),
+                has_breaks: same_tag.iter().any(|a| !a.get_breaks().is_empty()),
+                segments: same_tag.len(),
+                net_durations: same_tag.iter().map(|a| a.get_net_duration()).collect(),
+                activity,
+            }
         })
         .collect()
 }
 
+/// Activities carrying the same tag summed up (see `summary --by-tag`)
+struct TagReportEntry {
+    tag: Tag,
+    duration: DurationW,
+    net_duration: DurationW,
+    has_breaks: bool,
+    segments: usize,
+    net_durations: Vec<DurationW>,
+}
+
+/// Attribute each activity to every tag it carries, in order of first appearance
+///
+/// With `split_evenly`, an activity with n tags counts for 1/n of its duration per tag.
+fn merge_by_tag(activities: &[ActivityWithId], split_evenly: bool) -> Vec<TagReportEntry> {
+    let mut entries: Vec<TagReportEntry> = vec![];
+    for (_id, activity) in activities {
+        let tags: Vec<Tag> = activity.get_tags().into_iter().unique().collect();
+        let share = |d: DurationW| -> DurationW {
+            if split_evenly {
+                (Duration::from(d) / tags.len() as i32).into()
+            } else {
+                d
+            }
+        };
+        for tag in tags.iter() {
+            let index = match entries.iter().position(|e| &e.tag == tag) {
+                Some(index) => index,
+                None => {
+                    entries.push(TagReportEntry {
+                        tag: tag.clone(),
+                        duration: DurationW::default(),
+                        net_duration: DurationW::default(),
+                        has_breaks: false,
+                        segments: 0,
+                        net_durations: vec![],
+                    });
+                    entries.len() - 1
+                }
+            };
+            let entry = &mut entries[index];
+            entry.duration = entry.duration + share(activity.get_duration());
+            entry.net_duration = entry.net_duration + share(activity.get_net_duration());
+            entry.has_breaks |= !activity.get_breaks().is_empty();
+            entry.segments += 1;
+            entry.net_durations.push(share(activity.get_net_duration()));
+        }
+    }
+    entries
+}
+
+/// Rounded net duration in seconds (`None` without rounding)
+fn rounded_seconds(net_durations: &[DurationW], rounding: Option<Rounding>) -> Option<i64> {
+    rounding.map(|rounding| seconds(rounding.round_group(net_durations)))
+}
+
+/// Append the rounded net duration to `duration` (unchanged without rounding)
+fn with_rounded(
+    duration: String,
+    net_durations: &[DurationW],
+    rounding: Option<Rounding>,
+) -> String {
+    match rounding {
+        None => duration,
+        Some(rounding) => format!(
+            "{} (rounded {})",
+            duration,
+            rounding.round_group(net_durations)
+        ),
+    }
+}
+
+/// Print the per tag report (`summary --by-tag`)
+fn print_tag_report(
+    activities: &[ActivityWithId],
+    split_evenly: bool,
+    rounding: Option<Rounding>,
+    format: OutputFormat,
+    out: &mut dyn Write,
+) -> anyhow::Result<()> {
+    let entries = merge_by_tag(activities, split_evenly);
+    if format != OutputFormat::Text {
+        let report: Vec<ReportJson> = entries
+            .into_iter()
+            .map(|entry| ReportJson {
+                tags: vec![entry.tag],
+                duration: seconds(entry.duration),
+                net_duration: seconds(entry.net_duration),
+                rounded_net_duration: rounded_seconds(&entry.net_durations, rounding),
+                segments: entry.segments,
+            })
+            .collect();
+        write!(
+            out,
+            "{}",
+            to_json_output(&report, format == OutputFormat::JsonLines)?
+        )?;
+        return Ok(());
+    }
+    if entries.is_empty() {
+        writeln!(out, "No filtered data found.")?;
+    }
+    let longest_tag = entries
+        .iter()
+        .map(|entry| entry.tag.len())
+        .max()
+        .unwrap_or_default();
+    for entry in entries {
+        let duration = if entry.has_breaks {
+            format!("{} (net {})", entry.duration, entry.net_duration)
+        } else {
+            entry.duration.to_string()
+        };
+        let duration = with_rounded(duration, &entry.net_durations, rounding);
+        let singular_or_plural = if entry.segments <= 1 {
+            "segment"
+        } else {
+            "segments"
+        };
+        writeln!(
+            out,
+            "{:width$} {} ({} {})",
+            entry.tag,
+            duration,
+            entry.segments,
+            singular_or_plural,
+            width = longest_tag
+        )?;
+    }
+    Ok(())
+}
+
+/// Print the hierarchical tags report (`summary --report --depth`)
+fn print_tag_tree(
+    activities: &[ActivityWithId],
+    separator: &str,
+    depth: usize,
+    rounding: Option<Rounding>,
+    format: OutputFormat,
+    out: &mut dyn Write,
+) -> anyhow::Result<()> {
+    let activities: Vec<Activity> = activities.iter().map(|(_id, a)| a.clone()).collect();
+    let roots = build_tag_tree(&activities, separator, depth);
+    let total: DurationW = roots.iter().map(|root| root.net_duration).sum();
+    let nodes: Vec<(Vec<String>, &TagNode)> = roots.iter().flat_map(|root| root.walk()).collect();
+    if format != OutputFormat::Text {
+        let nodes: Vec<TagNodeJson> = nodes
+            .into_iter()
+            .map(|(path, node)| TagNodeJson {
+                path,
+                duration: seconds(node.duration),
+                net_duration: seconds(node.net_duration),
+                rounded_net_duration: rounded_seconds(&node.net_durations, rounding),
+                segments: node.segments,
+                percent: percent(node.net_duration, total),
+            })
+            .collect();
+        write!(
+            out,
+            "{}",
+            to_json_output(&nodes, format == OutputFormat::JsonLines)?
+        )?;
+        return Ok(());
+    }
+    if nodes.is_empty() {
+        writeln!(out, "No filtered data found.")?;
+    }
+    let label =
+        |path: &[String], node: &TagNode| format!("{}{}", "  ".repeat(path.len() - 1), node.name);
+    let longest_label = nodes
+        .iter()
+        .map(|(path, node)| label(path, node).len())
+        .max()
+        .unwrap_or_default();
+    for (path, node) in nodes.iter() {
+        let duration = if node.has_breaks {
+            format!("{} (net {})", node.duration, node.net_duration)
+        } else {
+            node.duration.to_string()
+        };
+        let duration = with_rounded(duration, &node.net_durations, rounding);
+        let singular_or_plural = if node.segments <= 1 {
+            "segment"
+        } else {
+            "segments"
+        };
+        writeln!(
+            out,
+            "{:width$} {} {:>5.1}% ({} {})",
+            label(path, node),
+            duration,
+            percent(node.net_duration, total),
+            node.segments,
+            singular_or_plural,
+            width = longest_label
+        )?;
+    }
+    Ok(())
+}
+
 fn get_ongoing_activity<S: Storage>(
-    id_maybe: Option<ActivityId>,
+    id_maybe: Option<ActivitySelector>,
     service: &Service<S>,
 ) -> anyhow::Result<OptionalOrAmbiguousOrNotFound> {
+    let ongoing_activities = service.get_ongoing_activities()?;
     match id_maybe {
-        None => match service.get_ongoing_activities()?.as_slice() {
+        None => match ongoing_activities.as_slice() {
             [] => Ok(OptionalOrAmbiguousOrNotFound::Optional(None)),
             [(cancelled_id, cancelled)] => Ok(OptionalOrAmbiguousOrNotFound::Optional(Some((
                 *cancelled_id,
@@ -86,13 +527,20 @@ fn get_ongoing_activity<S: Storage>(
             )))),
             _ => Ok(OptionalOrAmbiguousOrNotFound::Ambiguous),
         },
-        Some(cancelled_id) => match service.get_ongoing_activity(cancelled_id)? {
-            None => Ok(OptionalOrAmbiguousOrNotFound::NotFound(cancelled_id)),
-            Some(cancelled) => Ok(OptionalOrAmbiguousOrNotFound::Optional(Some((
-                cancelled_id,
-                cancelled,
-            )))),
-        },
+        Some(selector) => {
+            let selected: Vec<(ActivityId, OngoingActivity)> = ongoing_activities
+                .into_iter()
+                .filter(|(id, a)| selector.matches(*id, &a.uid))
+                .collect();
+            match selected.as_slice() {
+                [] => Ok(OptionalOrAmbiguousOrNotFound::NotFound(selector)),
+                [(cancelled_id, cancelled)] => Ok(OptionalOrAmbiguousOrNotFound::Optional(Some((
+                    *cancelled_id,
+                    cancelled.clone(),
+                )))),
+                _ => Ok(OptionalOrAmbiguousOrNotFound::Ambiguous),
+            }
+        }
     }
 }
 
@@ -105,9 +553,15 @@ where
 {
     match matches.subcommand() {
         ("start", Some(sub_m)) => {
-            let (start_time, tags, description) = cli_helper::parse_start_args(sub_m, clock)?;
+            let (start_time, tags, description, metadata) =
+                cli_helper::parse_start_args(sub_m, clock)?;
             let abs_start_time = clock.date_time(start_time);
-            Ok(RtwAction::Start(abs_start_time, tags, description))
+            Ok(RtwAction::Start(
+                abs_start_time,
+                tags,
+                description,
+                metadata,
+            ))
         }
         ("stop", Some(sub_m)) => {
             let (stop_time, stopped_id_maybe) = cli_helper::parse_stop_args(sub_m, clock)?;
@@ -115,14 +569,8 @@ where
             Ok(RtwAction::Stop(abs_stop_time, stopped_id_maybe))
         }
         ("summary", Some(sub_m)) => {
-            let ((range_start, range_end), display_id, display_description, report) =
-                cli_helper::parse_summary_args(sub_m, clock)?;
-            Ok(RtwAction::Summary(
-                (range_start, range_end),
-                display_id,
-                display_description,
-                report,
-            ))
+            let ((range_start, range_end), options) = cli_helper::parse_summary_args(sub_m, clock)?;
+            Ok(RtwAction::Summary((range_start, range_end), options))
         }
         ("timeline", Some(sub_m)) => {
             let ((range_start, range_end), _display_id) =
@@ -138,11 +586,16 @@ where
             Ok(RtwAction::Delete(id))
         }
         ("track", Some(sub_m)) => {
-            let (start_time, stop_time, tags, description) =
+            let (start_time, stop_time, tags, description, metadata) =
                 cli_helper::parse_track_args(sub_m, clock)?;
             let start_time = clock.date_time(start_time);
             let stop_time = clock.date_time(stop_time);
-            Ok(RtwAction::Track((start_time, stop_time), tags, description))
+            Ok(RtwAction::Track(
+                (start_time, stop_time),
+                tags,
+                description,
+                metadata,
+            ))
         }
         ("day", Some(_sub_m)) => {
             let (range_start, range_end) = clock.today_range();
@@ -157,9 +610,8 @@ where
             Ok(RtwAction::Cancel(cancelled_id_maybe))
         }
         ("dump", Some(sub_m)) => {
-            let ((range_start, range_end), _display_id, _description, _report) =
-                cli_helper::parse_summary_args(sub_m, clock)?;
-            Ok(RtwAction::DumpICal((range_start, range_end)))
+            let (range, format, output_dir) = cli_helper::parse_dump_args(sub_m, clock)?;
+            Ok(RtwAction::Dump(range, format, output_dir))
         }
         ("completion", Some(sub_m)) => {
             let shell = cli_helper::parse_completion_args(sub_m)?;
@@ -169,98 +621,211 @@ where
             let format = cli_helper::parse_status_args(sub_m);
             Ok(RtwAction::Status(format))
         }
+        ("invoice", Some(sub_m)) => {
+            let (range, client, format) = cli_helper::parse_invoice_args(sub_m, clock)?;
+            Ok(RtwAction::Invoice(range, client, format))
+        }
+        ("serve-ics", Some(sub_m)) => {
+            let (host, port) = cli_helper::parse_serve_args(sub_m)?;
+            Ok(RtwAction::ServeIcs(host, port))
+        }
+        ("edit", Some(sub_m)) => {
+            let (id, edit) = cli_helper::parse_edit_args(sub_m, clock)?;
+            Ok(RtwAction::Edit(id, edit))
+        }
+        ("modify", Some(sub_m)) => {
+            let (id_maybe, edit) = cli_helper::parse_modify_args(sub_m, clock)?;
+            Ok(RtwAction::Modify(id_maybe, edit))
+        }
+        ("split", Some(sub_m)) => {
+            let (id, split_time, tags_maybe) = cli_helper::parse_split_args(sub_m, clock)?;
+            Ok(RtwAction::Split(
+                id,
+                clock.date_time(split_time),
+                tags_maybe,
+            ))
+        }
+        ("merge", Some(sub_m)) => {
+            let selection = cli_helper::parse_merge_args(sub_m)?;
+            Ok(RtwAction::Merge(selection))
+        }
+        ("import", Some(sub_m)) => {
+            let (source, skip_overlapping) = cli_helper::parse_import_args(sub_m, clock)?;
+            Ok(RtwAction::Import(source, skip_overlapping))
+        }
+        ("pause", Some(sub_m)) => {
+            let (pause_time, paused_id_maybe) = cli_helper::parse_stop_args(sub_m, clock)?;
+            Ok(RtwAction::Pause(
+                clock.date_time(pause_time),
+                paused_id_maybe,
+            ))
+        }
+        ("resume", Some(sub_m)) => {
+            let (resume_time, resumed_id_maybe) = cli_helper::parse_stop_args(sub_m, clock)?;
+            Ok(RtwAction::Resume(
+                clock.date_time(resume_time),
+                resumed_id_maybe,
+            ))
+        }
+        ("undo", Some(_sub_m)) => Ok(RtwAction::Undo),
+        ("redo", Some(_sub_m)) => Ok(RtwAction::Redo),
         // default case: display current activity
-        _ => Ok(RtwAction::DisplayCurrent),
+        _ => Ok(RtwAction::DisplayCurrent(cli_helper::parse_output_format(
+            matches,
+        ))),
     }
 }
 
 /// Dry run (side effect-free)
+///
+/// What would be done is written to `out`.
 pub fn dry_run_action<S, Cl>(
     action: RtwAction,
     service: &Service<S>,
     clock: &Cl,
     config: &RtwConfig,
+    journal: &Journal,
+    out: &mut dyn Write,
 ) -> anyhow::Result<RtwMutation>
 where
     S: Storage,
     Cl: Clock,
 {
     match action {
-        RtwAction::Start(start_time, tags, description) => {
-            let started = OngoingActivity::new(start_time, tags, description);
-            println!("Tracking {}", started.get_title());
-            println!("Started  {}", started.get_start_time());
+        RtwAction::Start(start_time, tags, description, metadata) => {
+            let started =
+                OngoingActivity::new(start_time, tags, description).with_metadata(metadata);
+            writeln!(out, "Tracking {}", started.get_title())?;
+            writeln!(out, "Started  {}", started.get_start_time())?;
             Ok(RtwMutation::Start(started))
         }
-        RtwAction::Track((start_time, stop_time), tags, description) => {
-            let tracked =
-                OngoingActivity::new(start_time, tags, description).into_activity(stop_time)?;
-            println!("Recorded {}", tracked.get_title());
-            println!("Started {:>20}", tracked.get_start_time());
-            println!("Ended   {:>20}", tracked.get_stop_time());
-            println!("Total   {:>20}", tracked.get_duration());
+        RtwAction::Track((start_time, stop_time), tags, description, metadata) => {
+            let tracked = OngoingActivity::new(start_time, tags, description)
+                .with_metadata(metadata)
+                .into_activity(stop_time)?;
+            writeln!(out, "Recorded {}", tracked.get_title())?;
+            writeln!(out, "Started {:>20}", tracked.get_start_time())?;
+            writeln!(out, "Ended   {:>20}", tracked.get_stop_time())?;
+            writeln!(out, "Total   {:>20}", tracked.get_duration())?;
             Ok(RtwMutation::Track(tracked))
         }
         RtwAction::Stop(stop_time, activity_id) => {
             match get_ongoing_activity(activity_id, service)? {
                 Optional(None) => {
-                    println!("There is no active time tracking.");
+                    writeln!(out, "There is no active time tracking.")?;
                     Ok(RtwMutation::Pure)
                 }
-                Optional(Some((stopped_id, stopped))) => {
-                    println!("Recorded {}", stopped.get_title());
-                    println!("Started {:>20}", stopped.get_start_time());
-                    println!("Ended   {:>20}", stop_time);
-                    println!("Total   {:>20}", stop_time - stopped.get_start_time());
-                    Ok(RtwMutation::Stop(stop_time, stopped_id))
+                Optional(Some((_stopped_id, stopped))) => {
+                    writeln!(out, "Recorded {}", stopped.get_title())?;
+                    writeln!(out, "Started {:>20}", stopped.get_start_time())?;
+                    writeln!(out, "Ended   {:>20}", stop_time)?;
+                    writeln!(out, "Total   {:>20}", stop_time - stopped.get_start_time())?;
+                    if !stopped.breaks.is_empty() {
+                        writeln!(out, "Net     {:>20}", stopped.get_net_duration(stop_time))?;
+                    }
+                    Ok(RtwMutation::Stop(stop_time, stopped.uid))
                 }
                 OptionalOrAmbiguousOrNotFound::Ambiguous => {
-                    println!("Multiple ongoing activities, please provide an id.");
+                    writeln!(out, "Multiple ongoing activities, please provide an id.")?;
                     Ok(RtwMutation::Pure)
                 }
                 OptionalOrAmbiguousOrNotFound::NotFound(stopped_id) => {
-                    println!("No ongoing activity with id {}.", stopped_id);
+                    writeln!(out, "No ongoing activity with id {}.", stopped_id)?;
                     Ok(RtwMutation::Pure)
                 }
             }
         }
-        RtwAction::Summary((range_start, range_end), display_id, display_description, report) => {
-            let activities = service.get_finished_activities()?;
-            let activities: Vec<(ActivityId, Activity)> = activities
-                .iter()
-                .filter(|(_i, a)| {
-                    range_start <= a.get_start_time() && a.get_start_time() <= range_end
-                })
-                .cloned()
-                .collect();
+        RtwAction::Summary((range_start, range_end), options) => {
+            let mut activities = service.filter_activities_by_range(range_start, range_end)?;
+            activities.retain(|(_id, a)| {
+                options.metadata_filter.matches(&a.get_metadata())
+                    && options
+                        .tag_filter
+                        .matches(&a.get_tags(), &config.tag_separator)
+            });
+            if options.by_tag {
+                print_tag_report(
+                    &activities,
+                    options.split_evenly,
+                    config.rounding,
+                    options.format,
+                    out,
+                )?;
+                return Ok(RtwMutation::Pure);
+            }
+            if let Some(depth) = options.depth {
+                print_tag_tree(
+                    &activities,
+                    &config.tag_separator,
+                    depth,
+                    config.rounding,
+                    options.format,
+                    out,
+                )?;
+                return Ok(RtwMutation::Pure);
+            }
+            if options.format != OutputFormat::Text {
+                let lines = options.format == OutputFormat::JsonLines;
+                let output = if options.report {
+                    let report: Vec<ReportJson> = merge_same_tags(activities.as_slice())
+                        .into_iter()
+                        .map(|entry| ReportJson {
+                            tags: entry.activity.get_tags(),
+                            duration: seconds(entry.duration),
+                            net_duration: seconds(entry.net_duration),
+                            rounded_net_duration: rounded_seconds(
+                                &entry.net_durations,
+                                config.rounding,
+                            ),
+                            segments: entry.segments,
+                        })
+                        .collect();
+                    to_json_output(&report, lines)?
+                } else {
+                    let activities: Vec<ActivityJson> = activities
+                        .iter()
+                        .map(|(id, a)| ActivityJson::from_finished_rounded(*id, a, config.rounding))
+                        .collect();
+                    to_json_output(&activities, lines)?
+                };
+                write!(out, "{}", output)?;
+                return Ok(RtwMutation::Pure);
+            }
             let longest_title = activities
                 .iter()
                 .map(|(_id, a)| a.get_title().len())
+                .chain(config.rounding.map(|_| TOTAL_LABEL.len()))
                 .max()
                 .unwrap_or_default();
             if activities.is_empty() {
-                println!("No filtered data found.");
-            } else if report {
+                writeln!(out, "No filtered data found.")?;
+            } else if options.report {
                 let activities_report = merge_same_tags(activities.as_slice());
-                for (_id, finished, duration, segments) in activities_report {
-                    let singular_or_plural = if segments <= 1 {
+                for entry in activities_report {
+                    let singular_or_plural = if entry.segments <= 1 {
                         String::from("segment")
                     } else {
                         // segments > 1
                         String::from("segments")
                     };
+                    let duration = if entry.has_breaks {
+                        format!("{} (net {})", entry.duration, entry.net_duration)
+                    } else {
+                        entry.duration.to_string()
+                    };
+                    let duration = with_rounded(duration, &entry.net_durations, config.rounding);
                     let output = format!(
                         "{:width$} {} ({} {})",
-                        finished.get_title(),
+                        entry.activity.get_title(),
                         duration,
-                        segments,
+                        entry.segments,
                         singular_or_plural,
                         width = longest_title
                     );
-                    println!("{}", output)
+                    writeln!(out, "{}", output)?
                 }
             } else {
-                for (id, finished) in activities {
+                for (id, finished) in activities.iter() {
                     let output = format!(
                         "{:width$} {} {} {}",
                         finished.get_title(),
@@ -269,83 +834,351 @@ where
                         finished.get_duration(),
                         width = longest_title
                     );
-                    let output = if display_id {
+                    let output = if finished.get_breaks().is_empty() {
+                        output
+                    } else {
+                        format!("{} (net {})", output, finished.get_net_duration())
+                    };
+                    let output =
+                        with_rounded(output, &[finished.get_net_duration()], config.rounding);
+                    let output = if options.display_uid {
+                        format!("{} {}", short_uid(&finished.get_uid()), output)
+                    } else {
+                        output
+                    };
+                    let output = if options.display_id {
                         format!("{:>1} {}", id, output)
                     } else {
                         output
                     };
-                    let output = match (display_description, finished.get_description()) {
+                    let output = match (options.display_description, finished.get_description()) {
                         (false, _) => output,
                         (true, None) => output,
                         (true, Some(description)) => format!("{}\n{}", output, description),
                     };
-                    println!("{}", output)
+                    writeln!(out, "{}", output)?
                 }
             }
+            if let (Some(rounding), false) = (config.rounding, activities.is_empty()) {
+                // rounded per activity or per title (report entries)
+                let rounded: DurationW = merge_same_tags(activities.as_slice())
+                    .iter()
+                    .map(|entry| rounding.round_group(&entry.net_durations))
+                    .sum();
+                let net_total: DurationW =
+                    activities.iter().map(|(_id, a)| a.get_net_duration()).sum();
+                // listed activities are rounded one by one, they may not add up to the total
+                let per_title = !options.report && rounding.scope == RoundingScope::Group;
+                writeln!(
+                    out,
+                    "{:width$} {} (rounded {}{})",
+                    TOTAL_LABEL,
+                    net_total,
+                    if per_title { "per title " } else { "" },
+                    rounded,
+                    width = longest_title
+                )?;
+            }
             Ok(RtwMutation::Pure)
         }
         RtwAction::Continue(activity_id) => {
-            let activities = service.get_finished_activities()?;
-            let activity_id = activity_id.unwrap_or(0); // id 0 == last finished activity
-            let continued_maybe = activities.iter().find(|(id, _a)| *id == activity_id);
-            match continued_maybe {
-                None => {
-                    println!("No activity to continue from.");
+            let activity_id = activity_id.unwrap_or(ActivitySelector::Id(0)); // id 0 == last finished activity
+            let continued: Vec<(ActivityId, Activity)> = service
+                .get_finished_activities()?
+                .into_iter()
+                .filter(|(id, a)| activity_id.matches(*id, &a.get_uid()))
+                .collect();
+            match continued.as_slice() {
+                [] => {
+                    writeln!(out, "No activity to continue from.")?;
+                    Ok(RtwMutation::Pure)
+                }
+                [_, _, ..] => {
+                    writeln!(out, "Multiple activities match {}.", activity_id)?;
                     Ok(RtwMutation::Pure)
                 }
-                Some((_id, finished)) => {
-                    println!("Tracking {}", finished.get_title());
+                [(_id, finished)] => {
+                    writeln!(out, "Tracking {}", finished.get_title())?;
                     let new_current = OngoingActivity::new(
                         clock.get_time(),
                         finished.get_tags(),
                         finished.get_description(),
-                    );
+                    )
+                    .with_metadata(finished.get_metadata());
                     Ok(RtwMutation::Start(new_current))
                 }
             }
         }
         RtwAction::Delete(activity_id) => {
-            let deleted = service.filter_activities(|(i, _)| *i == activity_id)?;
-            let deleted_maybe = deleted.first();
-            match deleted_maybe {
-                None => {
-                    println!("No activity found for id {}.", activity_id);
+            let deleted =
+                service.filter_activities(|(i, a)| activity_id.matches(*i, &a.get_uid()))?;
+            match deleted.as_slice() {
+                [] => {
+                    writeln!(out, "No activity found for id {}.", activity_id)?;
+                    Ok(RtwMutation::Pure)
+                }
+                [_, _, ..] => {
+                    writeln!(out, "Multiple activities match {}.", activity_id)?;
+                    Ok(RtwMutation::Pure)
+                }
+                [(_deleted_id, deleted)] => {
+                    writeln!(out, "Deleted {}", deleted.get_title())?;
+                    writeln!(out, "Started {:>20}", deleted.get_start_time())?;
+                    writeln!(out, "Ended   {:>20}", deleted.get_stop_time())?;
+                    writeln!(out, "Total   {:>20}", deleted.get_duration())?;
+                    Ok(RtwMutation::Delete(deleted.get_uid()))
+                }
+            }
+        }
+        RtwAction::Edit(activity_id, edit) => {
+            let edited =
+                service.filter_activities(|(i, a)| activity_id.matches(*i, &a.get_uid()))?;
+            match edited.as_slice() {
+                [] => {
+                    writeln!(out, "No activity found for id {}.", activity_id)?;
+                    Ok(RtwMutation::Pure)
+                }
+                [_, _, ..] => {
+                    writeln!(out, "Multiple activities match {}.", activity_id)?;
+                    Ok(RtwMutation::Pure)
+                }
+                [(_edited_id, original)] => {
+                    let stop_time = edit.stop_time.unwrap_or_else(|| original.get_stop_time());
+                    let edited = edit
+                        .apply(original.clone().into_ongoing_activity())
+                        .into_activity(stop_time)?;
+                    writeln!(out, "Edited  {}", edited.get_title())?;
+                    writeln!(out, "Started {:>20}", edited.get_start_time())?;
+                    writeln!(out, "Ended   {:>20}", edited.get_stop_time())?;
+                    writeln!(out, "Total   {:>20}", edited.get_duration())?;
+                    Ok(RtwMutation::Edit(original.get_uid(), edited))
+                }
+            }
+        }
+        RtwAction::Split(activity_id, split_time, tags_maybe) => {
+            let split =
+                service.filter_activities(|(i, a)| activity_id.matches(*i, &a.get_uid()))?;
+            match split.as_slice() {
+                [] => {
+                    writeln!(out, "No activity found for id {}.", activity_id)?;
+                    Ok(RtwMutation::Pure)
+                }
+                [_, _, ..] => {
+                    writeln!(out, "Multiple activities match {}.", activity_id)?;
+                    Ok(RtwMutation::Pure)
+                }
+                [(_split_id, original)] => {
+                    let (first, second) = original.clone().split_at(split_time, tags_maybe)?;
+                    for part in &[&first, &second] {
+                        writeln!(out, "Recorded {}", part.get_title())?;
+                        writeln!(out, "Started {:>20}", part.get_start_time())?;
+                        writeln!(out, "Ended   {:>20}", part.get_stop_time())?;
+                        writeln!(out, "Total   {:>20}", part.get_duration())?;
+                    }
+                    // both parts are within the original activity: no new overlap
+                    Ok(RtwMutation::Split(JournalEntry::new(
+                        "split",
+                        vec![
+                            Change::RemoveFinished(original.clone()),
+                            Change::AddFinished(first),
+                            Change::AddFinished(second),
+                        ],
+                    )))
+                }
+            }
+        }
+        RtwAction::Merge(selection) => {
+            let finished = service.get_finished_activities()?;
+            let groups = match selection {
+                MergeSelection::Activities(selectors) => {
+                    let mut selected: Vec<Activity> = vec![];
+                    for selector in selectors {
+                        let matching: Vec<&ActivityWithId> = finished
+                            .iter()
+                            .filter(|(i, a)| selector.matches(*i, &a.get_uid()))
+                            .collect();
+                        match matching.as_slice() {
+                            [] => {
+                                writeln!(out, "No activity found for id {}.", selector)?;
+                                return Ok(RtwMutation::Pure);
+                            }
+                            [(_i, activity)] => selected.push(activity.clone()),
+                            _ => {
+                                writeln!(out, "Multiple activities match {}.", selector)?;
+                                return Ok(RtwMutation::Pure);
+                            }
+                        }
+                    }
+                    selected.sort();
+                    selected.dedup();
+                    if selected.len() > 1 {
+                        vec![selected]
+                    } else {
+                        vec![]
+                    }
+                }
+                MergeSelection::Gap(gap) => group_by_gap(&finished, gap),
+            };
+            if groups.is_empty() {
+                writeln!(out, "Nothing to merge.")?;
+                return Ok(RtwMutation::Pure);
+            }
+            let mut changes: Vec<Change> = vec![];
+            for group in groups {
+                let merged = Activity::merge(group.clone())?;
+                let intersections: Vec<&Activity> = finished
+                    .iter()
+                    .map(|(_i, a)| a)
+                    .filter(|a| !group.contains(a) && overlap(a, &merged).is_some())
+                    .collect();
+                if config.deny_overlapping && !intersections.is_empty() {
+                    return Err(anyhow::anyhow!(
+                        "{:?} would overlap {:?}\n\
+                        Tip: you can enable overlapping using `rtw --overlap (start|stop|track|...)`",
+                        merged,
+                        intersections
+                    ));
+                }
+                writeln!(
+                    out,
+                    "Merged  {} ({} activities)",
+                    merged.get_title(),
+                    group.len()
+                )?;
+                writeln!(out, "Started {:>20}", merged.get_start_time())?;
+                writeln!(out, "Ended   {:>20}", merged.get_stop_time())?;
+                writeln!(out, "Total   {:>20}", merged.get_duration())?;
+                if !merged.get_breaks().is_empty() {
+                    writeln!(out, "Net     {:>20}", merged.get_net_duration())?;
+                }
+                changes.extend(group.into_iter().map(Change::RemoveFinished));
+                changes.push(Change::AddFinished(merged));
+            }
+            Ok(RtwMutation::Merge(JournalEntry::new("merge", changes)))
+        }
+        RtwAction::Import(source, skip_overlapping) => {
+            let (finished, ongoing) = match source {
+                ImportSource::Timewarrior(dir) => read_timewarrior_data(&dir)?,
+                ImportSource::Csv(path, options) => {
+                    let (finished, invalid_rows) = read_csv_activities(&path, &options)?;
+                    for invalid_row in invalid_rows {
+                        writeln!(out, "Invalid  {}", invalid_row)?;
+                    }
+                    (finished, vec![])
+                }
+                ImportSource::Ics(path, filter) => {
+                    let (finished, invalid_events) = read_ics_activities(&path, &filter)?;
+                    for invalid_event in invalid_events {
+                        writeln!(out, "Invalid  {}", invalid_event)?;
+                    }
+                    (finished, vec![])
+                }
+            };
+            prepare_import(finished, ongoing, skip_overlapping, service, config, out)
+        }
+        RtwAction::Modify(id_maybe, edit) => match get_ongoing_activity(id_maybe, service)? {
+            Optional(None) => {
+                writeln!(out, "There is no active time tracking.")?;
+                Ok(RtwMutation::Pure)
+            }
+            Optional(Some((_modified_id, original))) => {
+                let modified = edit.apply(original.clone());
+                writeln!(out, "Modified {}", modified.get_title())?;
+                writeln!(out, "Started  {}", modified.get_start_time())?;
+                Ok(RtwMutation::Modify(original.uid, modified))
+            }
+            OptionalOrAmbiguousOrNotFound::Ambiguous => {
+                writeln!(out, "Multiple ongoing activities, please provide an id.")?;
+                Ok(RtwMutation::Pure)
+            }
+            OptionalOrAmbiguousOrNotFound::NotFound(modified_id) => {
+                writeln!(out, "No ongoing activity with id {}.", modified_id)?;
+                Ok(RtwMutation::Pure)
+            }
+        },
+        RtwAction::Pause(pause_time, id_maybe) => match get_ongoing_activity(id_maybe, service)? {
+            Optional(None) => {
+                writeln!(out, "There is no active time tracking.")?;
+                Ok(RtwMutation::Pure)
+            }
+            Optional(Some((_paused_id, ongoing))) => {
+                let paused = ongoing.clone().pause(pause_time)?;
+                writeln!(out, "Paused  {}", paused.get_title())?;
+                writeln!(out, "Started {:>20}", paused.get_start_time())?;
+                writeln!(out, "Paused  {:>20}", pause_time)?;
+                Ok(RtwMutation::Pause(ongoing.uid, paused))
+            }
+            OptionalOrAmbiguousOrNotFound::Ambiguous => {
+                writeln!(out, "Multiple ongoing activities, please provide an id.")?;
+                Ok(RtwMutation::Pure)
+            }
+            OptionalOrAmbiguousOrNotFound::NotFound(paused_id) => {
+                writeln!(out, "No ongoing activity with id {}.", paused_id)?;
+                Ok(RtwMutation::Pure)
+            }
+        },
+        RtwAction::Resume(resume_time, id_maybe) => {
+            match get_ongoing_activity(id_maybe, service)? {
+                Optional(None) => {
+                    writeln!(out, "There is no active time tracking.")?;
                     Ok(RtwMutation::Pure)
                 }
-                Some((deleted_id, deleted)) => {
-                    println!("Deleted {}", deleted.get_title());
-                    println!("Started {:>20}", deleted.get_start_time());
-                    println!("Ended   {:>20}", deleted.get_stop_time());
-                    println!("Total   {:>20}", deleted.get_duration());
-                    Ok(RtwMutation::Delete(*deleted_id))
+                Optional(Some((_resumed_id, ongoing))) => {
+                    let resumed = ongoing.clone().resume(resume_time)?;
+                    writeln!(out, "Resumed {}", resumed.get_title())?;
+                    writeln!(out, "Started {:>20}", resumed.get_start_time())?;
+                    writeln!(out, "Resumed {:>20}", resume_time)?;
+                    writeln!(
+                        out,
+                        "Breaks  {:>20}",
+                        resumed.get_break_duration(resume_time)
+                    )?;
+                    Ok(RtwMutation::Resume(ongoing.uid, resumed))
+                }
+                OptionalOrAmbiguousOrNotFound::Ambiguous => {
+                    writeln!(out, "Multiple ongoing activities, please provide an id.")?;
+                    Ok(RtwMutation::Pure)
+                }
+                OptionalOrAmbiguousOrNotFound::NotFound(resumed_id) => {
+                    writeln!(out, "No ongoing activity with id {}.", resumed_id)?;
+                    Ok(RtwMutation::Pure)
                 }
             }
         }
-        RtwAction::DisplayCurrent => {
+        RtwAction::DisplayCurrent(format) => {
             let ongoing_activities = service.get_ongoing_activities()?;
-            if ongoing_activities.is_empty() {
-                println!("There is no active time tracking.");
+            if format != OutputFormat::Text {
+                let now = clock.get_time();
+                let activities: Vec<ActivityJson> = ongoing_activities
+                    .iter()
+                    .map(|(id, a)| ActivityJson::from_ongoing(*id, a, now))
+                    .collect();
+                write!(
+                    out,
+                    "{}",
+                    to_json_output(&activities, format == OutputFormat::JsonLines)?
+                )?;
+            } else if ongoing_activities.is_empty() {
+                writeln!(out, "There is no active time tracking.")?;
             } else {
+                let now = clock.get_time();
                 for (id, ongoing_activity) in ongoing_activities {
-                    println!("Tracking {}", ongoing_activity.get_title());
-                    println!(
-                        "Total    {}",
-                        clock.get_time() - ongoing_activity.get_start_time()
-                    );
-                    println!("Id       {}", id);
+                    writeln!(out, "Tracking {}", ongoing_activity.get_title())?;
+                    writeln!(out, "Total    {}", now - ongoing_activity.get_start_time())?;
+                    if !ongoing_activity.breaks.is_empty() {
+                        writeln!(out, "Net      {}", ongoing_activity.get_net_duration(now))?;
+                    }
+                    if ongoing_activity.is_paused() {
+                        writeln!(out, "Paused")?;
+                    }
+                    writeln!(out, "Id       {}", id)?;
+                    writeln!(out, "Uid      {}", short_uid(&ongoing_activity.uid))?;
                 }
             }
             Ok(RtwMutation::Pure)
         }
         RtwAction::Timeline((range_start, range_end)) => {
-            let activities = service.get_finished_activities()?;
-            let activities: Vec<ActivityWithId> = activities
-                .iter()
-                .filter(|(_i, a)| {
-                    range_start <= a.get_start_time() && a.get_start_time() <= range_end
-                })
-                .cloned()
-                .collect();
+            let activities = service.filter_activities_by_range(range_start, range_end)?;
             let now = clock.get_time();
             let ongoing_activities = service.get_ongoing_activities()?;
             let ongoing_activities: Vec<ActivityWithId> = ongoing_activities
@@ -365,91 +1198,438 @@ where
                 .collect();
             let rendered = render_days(timeline_activities.as_slice(), &config.timeline_colors)?;
             for line in rendered {
-                println!("{}", line);
+                writeln!(out, "{}", line)?;
             }
             Ok(RtwMutation::Pure)
         }
         RtwAction::Cancel(id_maybe) => match get_ongoing_activity(id_maybe, service)? {
             Optional(None) => {
-                println!("Nothing to cancel: there is no active time tracking.");
+                writeln!(out, "Nothing to cancel: there is no active time tracking.")?;
                 Ok(RtwMutation::Pure)
             }
-            Optional(Some((cancelled_id, cancelled))) => {
-                println!("Cancelled {}", cancelled.get_title());
-                println!("Started   {:>20}", cancelled.get_start_time());
-                println!(
+            Optional(Some((_cancelled_id, cancelled))) => {
+                writeln!(out, "Cancelled {}", cancelled.get_title())?;
+                writeln!(out, "Started   {:>20}", cancelled.get_start_time())?;
+                writeln!(
+                    out,
                     "Total     {:>20}",
                     clock.get_time() - cancelled.get_start_time()
-                );
-                Ok(RtwMutation::Cancel(cancelled_id))
+                )?;
+                Ok(RtwMutation::Cancel(cancelled.uid))
             }
             OptionalOrAmbiguousOrNotFound::Ambiguous => {
-                println!("Multiple ongoing activities, please provide an id.");
+                writeln!(out, "Multiple ongoing activities, please provide an id.")?;
                 Ok(RtwMutation::Pure)
             }
             OptionalOrAmbiguousOrNotFound::NotFound(cancelled_id) => {
-                println!("No ongoing activity with id {}.", cancelled_id);
+                writeln!(out, "No ongoing activity with id {}.", cancelled_id)?;
                 Ok(RtwMutation::Pure)
             }
         },
-        RtwAction::DumpICal((range_start, range_end)) => {
-            let activities = service.get_finished_activities()?;
-            let activities: Vec<Activity> = activities
-                .iter()
-                .map(|(_i, a)| a)
-                .filter(|a| range_start <= a.get_start_time() && a.get_start_time() <= range_end)
-                .cloned()
-                .collect();
-            let calendar = export_activities_to_ical(activities.as_slice());
-            println!("{}", calendar);
-            Ok(RtwMutation::Pure)
+        RtwAction::Dump((range_start, range_end), format, output_dir) => {
+            let activities_with_id = service.filter_activities_by_range(range_start, range_end)?;
+            let activities: Vec<Activity> =
+                activities_with_id.iter().map(|(_i, a)| a.clone()).collect();
+            match format {
+                DumpFormat::Json | DumpFormat::JsonLines => {
+                    let activities: Vec<ActivityJson> = activities_with_id
+                        .iter()
+                        .map(|(id, a)| ActivityJson::from_finished_rounded(*id, a, config.rounding))
+                        .collect();
+                    write!(
+                        out,
+                        "{}",
+                        to_json_output(&activities, format == DumpFormat::JsonLines)?
+                    )?;
+                    Ok(RtwMutation::Pure)
+                }
+                DumpFormat::Csv(options) => {
+                    write!(
+                        out,
+                        "{}",
+                        export_activities_to_csv(activities.as_slice(), &options, config.rounding)
+                    )?;
+                    Ok(RtwMutation::Pure)
+                }
+                DumpFormat::ICal(include_ongoing) => {
+                    let mut activities = activities;
+                    if include_ongoing {
+                        let now = clock.get_time();
+                        activities.extend(
+                            service
+                                .get_ongoing_activities()?
+                                .into_iter()
+                                .filter(|(_id, a)| {
+                                    range_start <= a.start_time && a.start_time <= range_end
+                                })
+                                .filter_map(|(_id, a)| a.into_activity(now).ok()),
+                        );
+                    }
+                    let calendar = export_activities_to_ical(activities.as_slice());
+                    writeln!(out, "{}", calendar)?;
+                    Ok(RtwMutation::Pure)
+                }
+                DumpFormat::Timewarrior => {
+                    let months = export_activities_to_timewarrior(activities.as_slice());
+                    match output_dir {
+                        None => {
+                            for (_month, lines) in months {
+                                for line in lines {
+                                    writeln!(out, "{}", line)?;
+                                }
+                            }
+                            Ok(RtwMutation::Pure)
+                        }
+                        Some(output_dir) => {
+                            let mut files = vec![];
+                            for (month, lines) in months {
+                                let path = output_dir.join(format!("{}.data", month));
+                                if path.exists() {
+                                    return Err(anyhow::anyhow!(
+                                        "{} already exists",
+                                        path.display()
+                                    ));
+                                }
+                                writeln!(
+                                    out,
+                                    "Writing {} ({} intervals)",
+                                    path.display(),
+                                    lines.len()
+                                )?;
+                                let content: String =
+                                    lines.iter().map(|line| format!("{}\n", line)).collect();
+                                files.push((path, content));
+                            }
+                            Ok(RtwMutation::WriteFiles(files))
+                        }
+                    }
+                }
+            }
         }
         RtwAction::Completion(shell) => {
             let mut app = cli_helper::get_app();
-            app.gen_completions_to(crate_name!(), shell, &mut std::io::stdout());
+            let mut completions = vec![];
+            app.gen_completions_to(crate_name!(), shell, &mut completions);
+            out.write_all(&completions)?;
+            Ok(RtwMutation::Pure)
+        }
+        RtwAction::ServeIcs(_host, _port) => Ok(RtwMutation::Pure),
+        RtwAction::Invoice((range_start, range_end), client, format) => {
+            let activities: Vec<Activity> = service
+                .filter_activities_by_range(range_start, range_end)?
+                .into_iter()
+                .map(|(_id, a)| a)
+                .collect();
+            let invoice = build_invoice(
+                &activities,
+                &client,
+                (range_start, range_end),
+                &config.billing,
+                &config.tag_separator,
+            )?;
+            match format {
+                InvoiceFormat::Markdown => write!(out, "{}", render_markdown(&invoice))?,
+                InvoiceFormat::Html => write!(out, "{}", render_html(&invoice))?,
+            }
             Ok(RtwMutation::Pure)
         }
         RtwAction::Status(format_maybe) => {
             let status_maybe = format_status(format_maybe, service, clock)?;
             if let Some(status) = status_maybe {
-                println!("{}", status);
+                writeln!(out, "{}", status)?;
             }
             Ok(RtwMutation::Pure)
         }
+        RtwAction::Undo => match journal.last_done() {
+            None => {
+                writeln!(out, "Nothing to undo.")?;
+                Ok(RtwMutation::Pure)
+            }
+            Some(entry) => {
+                writeln!(out, "Undo {}", entry.command)?;
+                for change in entry.inverted_changes() {
+                    print_change(&change, out)?;
+                }
+                Ok(RtwMutation::Undo(entry.clone()))
+            }
+        },
+        RtwAction::Redo => match journal.last_undone() {
+            None => {
+                writeln!(out, "Nothing to redo.")?;
+                Ok(RtwMutation::Pure)
+            }
+            Some(entry) => {
+                writeln!(out, "Redo {}", entry.command)?;
+                for change in entry.changes.iter() {
+                    print_change(change, out)?;
+                }
+                Ok(RtwMutation::Redo(entry.clone()))
+            }
+        },
     }
 }
 
+fn modify_ongoing_activity<S: Storage>(
+    command: &str,
+    activity_uid: ActivityUid,
+    modified: OngoingActivity,
+    service: &mut Service<S>,
+    config: &RtwConfig,
+    journal: &mut Journal,
+) -> anyhow::Result<()> {
+    let replaced_maybe =
+        service.modify_ongoing_activity(activity_uid, modified.clone(), config.deny_overlapping)?;
+    let changes = replaced_maybe
+        .into_iter()
+        .flat_map(|replaced| {
+            vec![
+                Change::RemoveOngoing(replaced),
+                Change::AddOngoing(modified.clone()),
+            ]
+        })
+        .collect();
+    journal.record(JournalEntry::new(command, changes))
+}
+
+// Affected activities of mutations with hooks (see `Hooks`)
+//
+// Starting an activity also stops the ongoing one when overlapping is denied (see `start_activity`).
+// Computed before taking the journal and storage locks (see `run_mutation`).
+fn hook_targets<S, Cl>(
+    mutation: &RtwMutation,
+    service: &Service<S>,
+    clock: &Cl,
+    config: &RtwConfig,
+) -> anyhow::Result<Vec<(HookEvent, ActivityJson)>>
+where
+    S: Storage,
+    Cl: Clock + ?Sized,
+{
+    let now = clock.get_time();
+    let target = match mutation {
+        RtwMutation::Start(started) => {
+            let mut targets = vec![];
+            if config.deny_overlapping {
+                if let [(_id, ongoing)] = service.get_ongoing_activities()?.as_slice() {
+                    // starting before the ongoing activity: `apply_mutation` reports the overlap
+                    if let Ok(stopped) = ongoing.clone().into_activity(started.start_time) {
+                        targets.push((HookEvent::Stop, ActivityJson::from_finished(0, &stopped)));
+                    }
+                }
+            }
+            targets.push((
+                HookEvent::Start,
+                ActivityJson::from_ongoing(0, started, now),
+            ));
+            return Ok(targets);
+        }
+        RtwMutation::Track(tracked) => {
+            Some((HookEvent::Track, ActivityJson::from_finished(0, tracked)))
+        }
+        RtwMutation::Stop(stop_time, uid) => find_ongoing_activity(*uid, service)?
+            .map(|ongoing| ongoing.into_activity(*stop_time))
+            .transpose()?
+            .map(|stopped| (HookEvent::Stop, ActivityJson::from_finished(0, &stopped))),
+        RtwMutation::Cancel(uid) => find_ongoing_activity(*uid, service)?.map(|cancelled| {
+            (
+                HookEvent::Cancel,
+                ActivityJson::from_ongoing(0, &cancelled, now),
+            )
+        }),
+        RtwMutation::Delete(uid) => service
+            .filter_activities(|(_id, a)| a.get_uid() == *uid)?
+            .first()
+            .map(|(_id, deleted)| (HookEvent::Delete, ActivityJson::from_finished(0, deleted))),
+        _ => None,
+    };
+    Ok(target.into_iter().collect())
+}
+
 /// Side effect
-pub fn run_mutation<S>(
+///
+/// Apply mutation, running configured hooks around start, stop, track, cancel and delete
+///
+/// A failing pre hook aborts the mutation.
+///
+/// Hooks run outside the journal and storage locks (so that they may call rtw themselves):
+/// the activities they receive are read before the mutation is applied and may be stale
+/// if another rtw process changes them in between, the mutation itself is checked under the locks.
+pub fn run_mutation<S, Cl>(
+    mutation: RtwMutation,
+    service: &mut Service<S>,
+    clock: &Cl,
+    config: &RtwConfig,
+    journal: &mut Journal,
+) -> anyhow::Result<()>
+where
+    S: Storage,
+    Cl: Clock + ?Sized,
+{
+    if config.hooks == Hooks::default() {
+        return apply_mutation(mutation, service, config, journal);
+    }
+    let targets: Vec<(HookEvent, ActivityJson)> = hook_targets(&mutation, service, clock, config)?
+        .into_iter()
+        .filter(|(event, _activity)| config.hooks.is_hooked(*event))
+        .collect();
+    for (event, activity) in targets.iter() {
+        config.hooks.run_pre(*event, activity)?;
+    }
+    apply_mutation(mutation, service, config, journal)?;
+    for (event, activity) in targets.iter() {
+        config.hooks.run_post(*event, activity);
+    }
+    Ok(())
+}
+
+fn apply_mutation<S>(
+    action: RtwMutation,
+    service: &mut Service<S>,
+    config: &RtwConfig,
+    journal: &mut Journal,
+) -> anyhow::Result<()>
+where
+    S: Storage,
+{
+    // journal lock then storage unit, in this order for every mutation
+    journal.atomically(|journal| {
+        service.atomically(|service| apply_changes(action, service, config, journal))
+    })
+}
+
+fn apply_changes<S>(
     action: RtwMutation,
     service: &mut Service<S>,
     config: &RtwConfig,
+    journal: &mut Journal,
 ) -> anyhow::Result<()>
 where
     S: Storage,
 {
     match action {
         RtwMutation::Start(activity) => {
-            let _started = service.start_activity(activity, config.deny_overlapping)?;
-            Ok(())
+            let ongoing_activities = service.get_ongoing_activities()?;
+            let (started, stopped_maybe) =
+                service.start_activity(activity, config.deny_overlapping)?;
+            let mut changes = vec![];
+            if let Some(stopped) = stopped_maybe {
+                if let Some((_id, ongoing)) = ongoing_activities
+                    .into_iter()
+                    .find(|(_id, a)| a.uid == stopped.get_uid())
+                {
+                    changes.push(Change::RemoveOngoing(ongoing));
+                }
+                changes.push(Change::AddFinished(stopped));
+            }
+            changes.push(Change::AddOngoing(started));
+            journal.record(JournalEntry::new("start", changes))
         }
         RtwMutation::Track(activity) => {
-            let _tracked = service.track_activity(activity, config.deny_overlapping)?;
-            Ok(())
+            let tracked = service.track_activity(activity, config.deny_overlapping)?;
+            journal.record(JournalEntry::new(
+                "track",
+                vec![Change::AddFinished(tracked)],
+            ))
         }
-        RtwMutation::Stop(stop_time, activity_id) => {
-            let _stopped =
-                service.stop_ongoing_activity(stop_time, activity_id, config.deny_overlapping)?;
-            Ok(())
+        RtwMutation::Stop(stop_time, activity_uid) => {
+            let ongoing_maybe = find_ongoing_activity(activity_uid, service)?;
+            let stopped_maybe =
+                service.stop_ongoing_activity(stop_time, activity_uid, config.deny_overlapping)?;
+            match (ongoing_maybe, stopped_maybe) {
+                (Some(ongoing), Some(stopped)) => journal.record(JournalEntry::new(
+                    "stop",
+                    vec![Change::RemoveOngoing(ongoing), Change::AddFinished(stopped)],
+                )),
+                _ => Ok(()),
+            }
         }
-        RtwMutation::Delete(activity_id) => {
-            let _deleted = service.delete_activity(activity_id)?;
-            Ok(())
+        RtwMutation::Delete(activity_uid) => {
+            let deleted_maybe = service.delete_activity(activity_uid)?;
+            let changes = deleted_maybe.into_iter().map(Change::RemoveFinished);
+            journal.record(JournalEntry::new("delete", changes.collect()))
         }
-        RtwMutation::Cancel(activity_id) => {
-            let _cancelled = service.cancel_ongoing_activity(activity_id)?;
+        RtwMutation::Cancel(activity_uid) => {
+            let cancelled_maybe = service.cancel_ongoing_activity(activity_uid)?;
+            let changes = cancelled_maybe.into_iter().map(Change::RemoveOngoing);
+            journal.record(JournalEntry::new("cancel", changes.collect()))
+        }
+        RtwMutation::Edit(activity_uid, edited) => {
+            let replaced_maybe =
+                service.edit_activity(activity_uid, edited.clone(), config.deny_overlapping)?;
+            let changes = replaced_maybe
+                .into_iter()
+                .flat_map(|replaced| {
+                    vec![
+                        Change::RemoveFinished(replaced),
+                        Change::AddFinished(edited.clone()),
+                    ]
+                })
+                .collect();
+            journal.record(JournalEntry::new("edit", changes))
+        }
+        RtwMutation::Import(entry) => {
+            // overlapping activities were already filtered out (see `prepare_import`)
+            for change in entry.changes.iter() {
+                match change {
+                    Change::AddFinished(activity) => {
+                        service.track_activity(activity.clone(), config.deny_overlapping)?;
+                    }
+                    Change::AddOngoing(activity) => {
+                        service.start_activity(activity.clone(), config.deny_overlapping)?;
+                    }
+                    other => service.apply_change(other)?,
+                }
+            }
+            journal.record(entry)
+        }
+        RtwMutation::WriteFiles(files) => {
+            for (path, content) in files {
+                std::fs::write(path, content)?;
+            }
             Ok(())
         }
+        RtwMutation::Split(entry) | RtwMutation::Merge(entry) => {
+            service.check_changes(&entry.changes)?;
+            for change in entry.changes.iter() {
+                service.apply_change(change)?;
+            }
+            journal.record(entry)
+        }
+        RtwMutation::Modify(activity_uid, modified) => {
+            modify_ongoing_activity("modify", activity_uid, modified, service, config, journal)
+        }
+        RtwMutation::Pause(activity_uid, paused) => {
+            modify_ongoing_activity("pause", activity_uid, paused, service, config, journal)
+        }
+        RtwMutation::Resume(activity_uid, resumed) => {
+            modify_ongoing_activity("resume", activity_uid, resumed, service, config, journal)
+        }
+        RtwMutation::Undo(entry) => {
+            // the journal was re-read under its lock (see `apply_mutation`)
+            if journal.last_done() != Some(&entry) {
+                return Err(anyhow::anyhow!(
+                    "journal changed by another rtw command, nothing undone"
+                ));
+            }
+            let changes = entry.inverted_changes();
+            service.check_changes(&changes)?;
+            for change in changes.iter() {
+                service.apply_change(change)?;
+            }
+            journal.mark_undone()
+        }
+        RtwMutation::Redo(entry) => {
+            if journal.last_undone() != Some(&entry) {
+                return Err(anyhow::anyhow!(
+                    "journal changed by another rtw command, nothing redone"
+                ));
+            }
+            service.check_changes(&entry.changes)?;
+            for change in entry.changes.iter() {
+                service.apply_change(change)?;
+            }
+            journal.mark_redone()
+        }
         RtwMutation::Pure => {
             // pure nothing to do
             Ok(())
@@ -4,22 +4,115 @@ extern crate config;
 use self::config::FileFormat;
 use serde::Deserialize;
 use serde::Serialize;
+use std::collections::BTreeMap;
 use std::path::{Path, PathBuf};
 
 const DEFAULT_CONFIG: &str = r#"
     {
         "timeline_colors": [[183,28,28], [26,35,126], [0,77,64], [38,50,56]],
-        "deny_overlapping": true
+        "deny_overlapping": true,
+        "storage_backend": "json"
     }
 "#;
 
 type Rgb = (u8, u8, u8);
 
+/// Where activities are persisted
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum StorageBackend {
+    /// `.rtw.json` (ongoing) and `.rtwh.json` (finished)
+    Json,
+    /// `.rtw.sqlite`, imported from the json files on first use
+    Sqlite,
+}
+
+/// Hook executables, one per mutation (see `hooks`)
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq)]
+#[serde(default)]
+pub struct HookSet {
+    pub start: Option<PathBuf>,
+    pub stop: Option<PathBuf>,
+    pub track: Option<PathBuf>,
+    pub cancel: Option<PathBuf>,
+    pub delete: Option<PathBuf>,
+}
+
+/// Executables run before (`pre`) and after (`post`) start, stop, track, cancel and delete
+///
+/// A failing pre hook aborts the change.
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq)]
+#[serde(default)]
+pub struct Hooks {
+    pub pre: HookSet,
+    pub post: HookSet,
+}
+
+/// How durations are rounded to an increment
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum RoundingMode {
+    Up,
+    Down,
+    Nearest,
+}
+
+/// What is rounded: each activity then summed up, or the sum of a group (report entry, invoice item...)
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Default, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum RoundingScope {
+    Activity,
+    #[default]
+    Group,
+}
+
+/// Round durations to a multiple of `increment_minutes` (see `rounding`)
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+pub struct Rounding {
+    pub increment_minutes: u32,
+    pub mode: RoundingMode,
+    #[serde(default)]
+    pub scope: RoundingScope,
+}
+
+/// Hourly rates used by `rtw invoice`
+///
+/// The rate of an activity is the rate of its most specific tag (`tag_rates`),
+/// else the rate of the invoiced client (`client_rates`), else `default_rate`.
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq)]
+#[serde(default)]
+pub struct Billing {
+    /// e.g. `EUR`, displayed next to amounts
+    pub currency: String,
+    pub default_rate: Option<f64>,
+    /// Rate per tag, a tag also sets the rate of its hierarchical sub-tags
+    pub tag_rates: BTreeMap<String, f64>,
+    /// Rate per client (see `rtw invoice --client`)
+    pub client_rates: BTreeMap<String, f64>,
+    /// Rounding of invoice line items (none if `None`), independent of `RtwConfig::rounding`
+    pub rounding: Option<Rounding>,
+}
+
 #[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
 pub struct RtwConfig {
     pub storage_dir_path: PathBuf,
     pub timeline_colors: Vec<Rgb>,
     pub deny_overlapping: bool,
+    pub storage_backend: StorageBackend,
+    #[serde(default)]
+    pub hooks: Hooks,
+    /// Separates levels of hierarchical tags e.g. `client:acme:backend`
+    #[serde(default = "default_tag_separator")]
+    pub tag_separator: String,
+    #[serde(default)]
+    pub billing: Billing,
+    /// Rounding of net durations in summaries, reports and exports (raw durations are also shown)
+    #[serde(default)]
+    pub rounding: Option<Rounding>,
+}
+
+fn default_tag_separator() -> String {
+    String::from(":")
 }
 
 impl RtwConfig {
@@ -29,25 +122,35 @@ impl RtwConfig {
             storage_dir_path: home_dir, // stores finished activities
             timeline_colors: vec![(183, 28, 28), (26, 35, 126), (0, 77, 64), (38, 50, 56)],
             deny_overlapping: true,
+            storage_backend: StorageBackend::Json,
+            hooks: Hooks::default(),
+            tag_separator: default_tag_separator(),
+            billing: Billing::default(),
+            rounding: None,
         }
     }
 
     pub fn deny_overlapping(self, deny: bool) -> Self {
         RtwConfig {
-            storage_dir_path: self.storage_dir_path,
-            timeline_colors: self.timeline_colors,
             deny_overlapping: deny,
+            ..self
         }
     }
 }
 
+fn config_paths(config_dir: &Path) -> [PathBuf; 2] {
+    [
+        config_dir.join("rtw").join("rtw_config.json"),
+        config_dir.join("rtw_config.json"),
+    ]
+}
+
 fn load_config_from_config_dir(
     config_dir: &Path,
     default_config: RtwConfig,
 ) -> anyhow::Result<RtwConfig> {
     let mut settings = config::Config::default();
-    let config_path = config_dir.join("rtw").join("rtw_config.json");
-    let config_path_fallback = config_dir.join("rtw_config.json");
+    let [config_path, config_path_fallback] = config_paths(config_dir);
     settings
         .set_default(
             "storage_dir_path",
@@ -67,9 +170,21 @@ pub fn load_config() -> anyhow::Result<RtwConfig> {
     }
 }
 
+/// First existing config file, if any
+pub fn find_config_path() -> Option<PathBuf> {
+    let config_dir = dirs_next::config_dir()?;
+    config_paths(&config_dir)
+        .iter()
+        .find(|path| path.is_file())
+        .cloned()
+}
+
 #[cfg(test)]
 mod tests {
-    use crate::rtw_config::{load_config_from_config_dir, RtwConfig};
+    use crate::rtw_config::{
+        load_config_from_config_dir, HookSet, Rounding, RoundingMode, RoundingScope, RtwConfig,
+        StorageBackend,
+    };
     use std::fs;
     use std::fs::File;
     use std::io::Write;
@@ -101,10 +216,7 @@ mod tests {
         let test_config_dir = tempdir().expect("could not create temp directory");
         let mut tmp_config = File::create(test_config_dir.path().join("rtw_config.json"))?;
         writeln!(tmp_config, "{{\n\"storage_dir_path\": \"/expected\"\n}}")?;
-        let config = load_config_from_config_dir(
-            &test_config_dir.path().to_path_buf(),
-            RtwConfig::default(),
-        );
+        let config = load_config_from_config_dir(test_config_dir.path(), RtwConfig::default());
         assert_eq!(config.unwrap().storage_dir_path, expected);
         Ok(())
     }
@@ -118,11 +230,77 @@ mod tests {
         fs::create_dir(test_config_sub_dir.clone()).expect("could not create temp/rtw directory");
         let mut tmp_config = File::create(test_config_sub_dir.join("rtw_config.json"))?;
         writeln!(tmp_config, "{{\n\"storage_dir_path\": \"/expected\"\n}}")?;
-        let config = load_config_from_config_dir(
-            &test_config_dir.path().to_path_buf(),
-            RtwConfig::default(),
-        );
+        let config = load_config_from_config_dir(test_config_dir.path(), RtwConfig::default());
         assert_eq!(config.unwrap().storage_dir_path, expected);
         Ok(())
     }
+
+    #[test]
+    fn test_config_hooks() -> anyhow::Result<()> {
+        let test_config_dir = tempdir().expect("could not create temp directory");
+        let mut tmp_config = File::create(test_config_dir.path().join("rtw_config.json"))?;
+        writeln!(
+            tmp_config,
+            "{{\n\"hooks\": {{\"pre\": {{\"start\": \"/bin/true\"}}}}\n}}"
+        )?;
+        let config = load_config_from_config_dir(test_config_dir.path(), RtwConfig::default())?;
+        assert_eq!(config.hooks.pre.start, Some(PathBuf::from("/bin/true")));
+        assert_eq!(config.hooks.pre.stop, None);
+        assert_eq!(config.hooks.post, HookSet::default());
+        Ok(())
+    }
+
+    #[test]
+    fn test_config_billing() -> anyhow::Result<()> {
+        let test_config_dir = tempdir().expect("could not create temp directory");
+        let mut tmp_config = File::create(test_config_dir.path().join("rtw_config.json"))?;
+        writeln!(
+            tmp_config,
+            r#"{{"billing": {{"currency": "EUR", "tag_rates": {{"client:acme": 100.0}}, "rounding": {{"increment_minutes": 15, "mode": "up"}}}}}}"#
+        )?;
+        let config = load_config_from_config_dir(test_config_dir.path(), RtwConfig::default())?;
+        assert_eq!(config.billing.currency, "EUR");
+        assert_eq!(config.billing.tag_rates.get("client:acme"), Some(&100.0));
+        assert!(config.billing.client_rates.is_empty());
+        assert_eq!(
+            config.billing.rounding,
+            Some(Rounding {
+                increment_minutes: 15,
+                mode: RoundingMode::Up,
+                scope: RoundingScope::Group
+            })
+        );
+        assert_eq!(config.rounding, None);
+        Ok(())
+    }
+
+    #[test]
+    fn test_config_rounding() -> anyhow::Result<()> {
+        let test_config_dir = tempdir().expect("could not create temp directory");
+        let mut tmp_config = File::create(test_config_dir.path().join("rtw_config.json"))?;
+        writeln!(
+            tmp_config,
+            r#"{{"rounding": {{"increment_minutes": 6, "mode": "nearest", "scope": "activity"}}}}"#
+        )?;
+        let config = load_config_from_config_dir(test_config_dir.path(), RtwConfig::default())?;
+        assert_eq!(
+            config.rounding,
+            Some(Rounding {
+                increment_minutes: 6,
+                mode: RoundingMode::Nearest,
+                scope: RoundingScope::Activity
+            })
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_config_storage_backend() -> anyhow::Result<()> {
+        let test_config_dir = tempdir().expect("could not create temp directory");
+        let mut tmp_config = File::create(test_config_dir.path().join("rtw_config.json"))?;
+        writeln!(tmp_config, "{{\n\"storage_backend\": \"sqlite\"\n}}")?;
+        let config = load_config_from_config_dir(test_config_dir.path(), RtwConfig::default());
+        assert_eq!(config.unwrap().storage_backend, StorageBackend::Sqlite);
+        Ok(())
+    }
 }
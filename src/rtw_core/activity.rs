@@ -2,14 +2,49 @@
 
 use crate::rtw_core::datetimew::DateTimeW;
 use crate::rtw_core::durationw::DurationW;
-use crate::rtw_core::{Description, Tags};
+use crate::rtw_core::{ActivityUid, Description, Metadata, Tags};
 use anyhow::anyhow;
 use serde::{Deserialize, Serialize};
-use std::cmp::Ordering;
+use std::cmp::{max, min, Ordering};
+use uuid::Uuid;
+
+/// Namespace used to derive uids of activities stored before uids existed
+const LEGACY_UID_NAMESPACE: Uuid = Uuid::from_u128(0x3f0e_5a8b_7d2c_4e91_b6a4_1c9d_8e7f_2a50);
+
+// Activities stored by previous versions have no uid (deserialized as nil).
+// A uid is derived from their content so that it stays the same until they are rewritten.
+fn legacy_uid<T: Serialize>(activity: &T) -> ActivityUid {
+    let content = serde_json::to_vec(activity).unwrap_or_default();
+    Uuid::new_v5(&LEGACY_UID_NAMESPACE, &content)
+}
+
+/// A break (pause) during an activity
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Break {
+    /// Break start time
+    pub start_time: DateTimeW,
+    /// Break stop time, `None` while the activity is paused
+    #[serde(default)]
+    pub stop_time: Option<DateTimeW>,
+}
+
+impl Break {
+    /// Break duration, open break ends at `now`
+    pub fn get_duration(&self, now: DateTimeW) -> DurationW {
+        self.stop_time.unwrap_or(now) - self.start_time
+    }
+}
+
+fn total_break_duration(breaks: &[Break], now: DateTimeW) -> DurationW {
+    breaks.iter().map(|b| b.get_duration(now)).sum()
+}
 
 /// A finished activity (with a stop time)
 #[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
 pub struct Activity {
+    /// Stable identifier
+    #[serde(default)]
+    uid: ActivityUid,
     /// Activity start time
     start_time: DateTimeW,
     /// Activity `stop time` >= `start time`
@@ -18,9 +53,30 @@ pub struct Activity {
     tags: Tags,
     #[serde(default)]
     description: Option<Description>,
+    /// Breaks within `start time` and `stop time` (not serialized when empty)
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    breaks: Vec<Break>,
+    /// Key-value fields (not serialized when empty)
+    #[serde(default, skip_serializing_if = "Metadata::is_empty")]
+    metadata: Metadata,
 }
 
 impl Activity {
+    /// uid getter
+    pub fn get_uid(&self) -> ActivityUid {
+        self.uid
+    }
+    /// Give a uid to activities stored without one
+    pub fn with_legacy_uid(self) -> Self {
+        if self.uid.is_nil() {
+            Activity {
+                uid: legacy_uid(&self),
+                ..self
+            }
+        } else {
+            self
+        }
+    }
     /// start time getter
     pub fn get_start_time(&self) -> DateTimeW {
         self.start_time
@@ -29,10 +85,50 @@ impl Activity {
     pub fn get_stop_time(&self) -> DateTimeW {
         self.stop_time
     }
-    /// Return activity duration
+    /// Return activity duration (gross: breaks included)
     pub fn get_duration(&self) -> DurationW {
         self.stop_time - self.start_time
     }
+    /// Return breaks total duration
+    pub fn get_break_duration(&self) -> DurationW {
+        total_break_duration(&self.breaks, self.stop_time)
+    }
+    /// Return activity duration excluding breaks
+    pub fn get_net_duration(&self) -> DurationW {
+        self.get_duration() - self.get_break_duration()
+    }
+    /// Return breaks
+    pub fn get_breaks(&self) -> Vec<Break> {
+        self.breaks.clone()
+    }
+    /// Split activity into the worked intervals between breaks
+    ///
+    /// Returned activities have no breaks and keep uid, tags and description.
+    pub fn split_at_breaks(&self) -> Vec<Activity> {
+        let mut intervals = vec![];
+        let mut start_time = self.start_time;
+        for b in self.breaks.iter() {
+            let break_stop = b.stop_time.unwrap_or(self.stop_time);
+            if start_time < b.start_time {
+                intervals.push(Activity {
+                    start_time,
+                    stop_time: b.start_time,
+                    breaks: vec![],
+                    ..self.clone()
+                });
+            }
+            start_time = break_stop;
+        }
+        if start_time < self.stop_time || intervals.is_empty() {
+            intervals.push(Activity {
+                start_time,
+                stop_time: self.stop_time,
+                breaks: vec![],
+                ..self.clone()
+            });
+        }
+        intervals
+    }
     /// Return activity title (its tags joined by a space)
     pub fn get_title(&self) -> String {
         self.tags.join(" ")
@@ -46,6 +142,132 @@ impl Activity {
     pub fn get_description(&self) -> Option<Description> {
         self.description.clone()
     }
+
+    /// Return metadata
+    pub fn get_metadata(&self) -> Metadata {
+        self.metadata.clone()
+    }
+
+    /// Drop stop time, uid is kept
+    ///
+    /// Used to build a modified copy of a finished activity (see `OngoingActivity::into_activity`)
+    pub fn into_ongoing_activity(self) -> OngoingActivity {
+        OngoingActivity {
+            uid: self.uid,
+            start_time: self.start_time,
+            tags: self.tags,
+            description: self.description,
+            breaks: self.breaks,
+            metadata: self.metadata,
+        }
+    }
+
+    /// Split activity in 2 adjacent activities at `time`
+    ///
+    /// The first activity keeps the uid, the second one gets a new uid and `second_tags` if provided.
+    /// Breaks are split accordingly, both activities keep the metadata.
+    pub fn split_at(
+        self,
+        time: DateTimeW,
+        second_tags: Option<Tags>,
+    ) -> anyhow::Result<(Activity, Activity)> {
+        if time == self.start_time || time == self.stop_time {
+            return Err(anyhow!(
+                "split time ({}) must be strictly between start time ({}) and stop time ({})",
+                time,
+                self.start_time,
+                self.stop_time
+            ));
+        }
+        let (first_breaks, second_breaks): (Vec<Break>, Vec<Break>) = (
+            self.breaks
+                .iter()
+                .filter(|b| b.start_time < time)
+                .map(|b| Break {
+                    start_time: b.start_time,
+                    stop_time: b.stop_time.map(|s| min(s, time)),
+                })
+                .collect(),
+            self.breaks
+                .iter()
+                .filter(|b| b.stop_time.is_none_or(|s| s > time))
+                .map(|b| Break {
+                    start_time: max(b.start_time, time),
+                    stop_time: b.stop_time,
+                })
+                .collect(),
+        );
+        let stop_time = self.stop_time;
+        let second = OngoingActivity {
+            breaks: second_breaks,
+            metadata: self.metadata.clone(),
+            ..OngoingActivity::new(
+                time,
+                second_tags.unwrap_or_else(|| self.tags.clone()),
+                self.description.clone(),
+            )
+        };
+        let first = OngoingActivity {
+            breaks: first_breaks,
+            ..self.into_ongoing_activity()
+        };
+        Ok((first.into_activity(time)?, second.into_activity(stop_time)?))
+    }
+
+    /// Merge activities into a single activity spanning all of them
+    ///
+    /// The merged activity keeps the uid of the earliest activity and the tags of all activities,
+    /// descriptions are concatenated, metadata are combined (earliest activity first)
+    /// and periods not worked in any activity become breaks.
+    pub fn merge(mut activities: Vec<Activity>) -> anyhow::Result<Activity> {
+        activities.sort();
+        let first = activities
+            .first()
+            .cloned()
+            .ok_or_else(|| anyhow!("no activity to merge"))?;
+        let mut tags: Tags = vec![];
+        for tag in activities.iter().flat_map(|a| a.tags.iter()) {
+            if !tags.contains(tag) {
+                tags.push(tag.clone());
+            }
+        }
+        let mut worked: Vec<Activity> = activities
+            .iter()
+            .flat_map(Activity::split_at_breaks)
+            .collect();
+        worked.sort();
+        let mut breaks: Vec<Break> = vec![];
+        let mut stop_time = first.start_time;
+        for interval in worked.iter() {
+            if stop_time < interval.start_time {
+                breaks.push(Break {
+                    start_time: stop_time,
+                    stop_time: Some(interval.start_time),
+                });
+            }
+            stop_time = max(stop_time, interval.stop_time);
+        }
+        let descriptions: Vec<Description> = activities
+            .iter()
+            .filter_map(|a| a.description.clone())
+            .collect();
+        let mut metadata = Metadata::new();
+        for activity in activities.iter().rev() {
+            metadata.extend(activity.metadata.clone());
+        }
+        let merged = OngoingActivity {
+            metadata,
+            description: if descriptions.is_empty() {
+                None
+            } else {
+                Some(descriptions.join("\n"))
+            },
+            tags,
+            breaks,
+            ..first.into_ongoing_activity()
+        };
+        merged.into_activity(stop_time)
+    }
 }
 
 /// Activities are sorted by start time
@@ -64,12 +286,21 @@ impl PartialOrd for Activity {
 /// A started and unfinished activity (no stop time)
 #[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
 pub struct OngoingActivity {
+    /// Stable identifier, kept when the activity is finished
+    #[serde(default)]
+    pub uid: ActivityUid,
     /// start time
     pub start_time: DateTimeW,
     /// Activity tags
     pub tags: Tags,
     #[serde(default)]
     pub description: Option<Description>,
+    /// Breaks, the last one is open while paused (not serialized when empty)
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub breaks: Vec<Break>,
+    /// Key-value fields (not serialized when empty)
+    #[serde(default, skip_serializing_if = "Metadata::is_empty")]
+    pub metadata: Metadata,
 }
 
 /// OngoingActivities are sorted by start time
@@ -86,12 +317,30 @@ impl PartialOrd for OngoingActivity {
 }
 
 impl OngoingActivity {
-    /// Constructor
+    /// Constructor, a new uid is generated
     pub fn new(start_time: DateTimeW, tags: Tags, description: Option<Description>) -> Self {
         OngoingActivity {
+            uid: Uuid::new_v4(),
             start_time,
             tags,
             description,
+            breaks: vec![],
+            metadata: Metadata::new(),
+        }
+    }
+    /// Set metadata
+    pub fn with_metadata(self, metadata: Metadata) -> Self {
+        OngoingActivity { metadata, ..self }
+    }
+    /// Give a uid to activities stored without one
+    pub fn with_legacy_uid(self) -> Self {
+        if self.uid.is_nil() {
+            OngoingActivity {
+                uid: legacy_uid(&self),
+                ..self
+            }
+        } else {
+            self
         }
     }
     /// Start time getter
@@ -102,15 +351,100 @@ impl OngoingActivity {
     pub fn get_title(&self) -> String {
         self.tags.join(" ")
     }
+    /// Is the activity paused (open break)
+    pub fn is_paused(&self) -> bool {
+        self.breaks.last().is_some_and(|b| b.stop_time.is_none())
+    }
+    /// Return breaks total duration, open break ends at `now`
+    pub fn get_break_duration(&self, now: DateTimeW) -> DurationW {
+        total_break_duration(&self.breaks, now)
+    }
+    /// Return activity duration until `now` excluding breaks
+    pub fn get_net_duration(&self, now: DateTimeW) -> DurationW {
+        (now - self.start_time) - self.get_break_duration(now)
+    }
+    /// Start a break at `time`
+    ///
+    /// `time` should be >= start time and >= previous break stop time otherwise error
+    pub fn pause(mut self, time: DateTimeW) -> anyhow::Result<Self> {
+        if self.is_paused() {
+            return Err(anyhow!("{} is already paused", self.get_title()));
+        }
+        let resumed_time = self
+            .breaks
+            .last()
+            .and_then(|b| b.stop_time)
+            .unwrap_or(self.start_time);
+        if time < resumed_time {
+            return Err(anyhow!(
+                "pause time ({}) < last start or resume time ({})",
+                time,
+                resumed_time
+            ));
+        }
+        self.breaks.push(Break {
+            start_time: time,
+            stop_time: None,
+        });
+        Ok(self)
+    }
+    /// Stop current break at `time`
+    ///
+    /// `time` should be >= break start time otherwise error
+    pub fn resume(mut self, time: DateTimeW) -> anyhow::Result<Self> {
+        match self.breaks.last_mut() {
+            Some(b) if b.stop_time.is_none() => {
+                if time < b.start_time {
+                    return Err(anyhow!(
+                        "resume time ({}) < pause time ({})",
+                        time,
+                        b.start_time
+                    ));
+                }
+                b.stop_time = Some(time);
+                Ok(self)
+            }
+            _ => Err(anyhow!("{} is not paused", self.get_title())),
+        }
+    }
     /// Convert active activity to finished activity
     /// `stop_time` should be >= `start_time` otherwise error
+    ///
+    /// An open break is stopped at `stop_time`,
+    /// breaks should be within `start_time` and `stop_time` otherwise error
     pub fn into_activity(self, stop_time: DateTimeW) -> anyhow::Result<Activity> {
         if self.start_time <= stop_time {
+            let breaks: Vec<Break> = self
+                .breaks
+                .into_iter()
+                .map(|b| Break {
+                    stop_time: Some(b.stop_time.unwrap_or(stop_time)),
+                    ..b
+                })
+                .collect();
+            let start_time = self.start_time;
+            let outside = breaks.iter().find(|b| {
+                b.start_time < start_time
+                    || b.stop_time
+                        .is_none_or(|s| s < b.start_time || s > stop_time)
+            });
+            if let Some(outside) = outside {
+                return Err(anyhow!(
+                    "break ({} - {}) is not within start time ({}) and stop time ({})",
+                    outside.start_time,
+                    outside.stop_time.unwrap_or(stop_time),
+                    self.start_time,
+                    stop_time
+                ));
+            }
             Ok(Activity {
+                uid: self.uid,
                 start_time: self.start_time,
                 stop_time,
                 tags: self.tags,
                 description: self.description,
+                breaks,
+                metadata: self.metadata,
             })
         } else {
             Err(anyhow!(
@@ -146,12 +480,200 @@ pub fn overlap(finished: &Activity, other: &Activity) -> Option<Activity> {
 
 #[cfg(test)]
 mod tests {
-    use crate::rtw_core::activity::{intersect, overlap, Activity};
+    use crate::rtw_core::activity::{intersect, overlap, Activity, Break, OngoingActivity};
+    use crate::rtw_core::datetimew::DateTimeW;
+    use crate::rtw_core::Metadata;
     use chrono::{Local, TimeZone};
+    use uuid::Uuid;
+
+    fn datetime(s: &str) -> DateTimeW {
+        Local
+            .datetime_from_str(s, "%Y-%m-%dT%H:%M:%S")
+            .unwrap()
+            .into()
+    }
+
+    #[test]
+    fn test_pause_resume() {
+        let ongoing = OngoingActivity::new(datetime("2020-12-25T09:00:00"), vec![], None);
+        assert!(ongoing
+            .clone()
+            .resume(datetime("2020-12-25T09:30:00"))
+            .is_err());
+        assert!(ongoing
+            .clone()
+            .pause(datetime("2020-12-25T08:30:00"))
+            .is_err());
+        let paused = ongoing.pause(datetime("2020-12-25T09:30:00")).unwrap();
+        assert!(paused.is_paused());
+        assert!(paused
+            .clone()
+            .pause(datetime("2020-12-25T09:40:00"))
+            .is_err());
+        assert!(paused
+            .clone()
+            .resume(datetime("2020-12-25T09:20:00"))
+            .is_err());
+        let resumed = paused.resume(datetime("2020-12-25T09:45:00")).unwrap();
+        assert!(!resumed.is_paused());
+        assert!(resumed
+            .clone()
+            .pause(datetime("2020-12-25T09:40:00"))
+            .is_err());
+        let now = datetime("2020-12-25T10:00:00");
+        assert_eq!(resumed.get_break_duration(now).to_string(), "00:15:00");
+        assert_eq!(resumed.get_net_duration(now).to_string(), "00:45:00");
+    }
+
+    #[test]
+    fn test_into_activity_with_breaks() {
+        let paused = OngoingActivity::new(datetime("2020-12-25T09:00:00"), vec![], None)
+            .pause(datetime("2020-12-25T09:30:00"))
+            .unwrap();
+        assert!(paused
+            .clone()
+            .into_activity(datetime("2020-12-25T09:15:00"))
+            .is_err());
+        // open break is stopped with the activity
+        let finished = paused
+            .into_activity(datetime("2020-12-25T10:00:00"))
+            .unwrap();
+        assert_eq!(finished.get_duration().to_string(), "01:00:00");
+        assert_eq!(finished.get_net_duration().to_string(), "00:30:00");
+        let resumed = OngoingActivity::new(datetime("2020-12-25T09:00:00"), vec![], None)
+            .pause(datetime("2020-12-25T09:30:00"))
+            .unwrap()
+            .resume(datetime("2020-12-25T09:45:00"))
+            .unwrap()
+            .into_activity(datetime("2020-12-25T10:00:00"))
+            .unwrap();
+        let intervals: Vec<(String, String)> = resumed
+            .split_at_breaks()
+            .iter()
+            .map(|a| {
+                (
+                    a.get_start_time().to_string(),
+                    a.get_stop_time().to_string(),
+                )
+            })
+            .collect();
+        assert_eq!(
+            intervals,
+            vec![
+                (
+                    String::from("2020-12-25T09:00:00"),
+                    String::from("2020-12-25T09:30:00")
+                ),
+                (
+                    String::from("2020-12-25T09:45:00"),
+                    String::from("2020-12-25T10:00:00")
+                )
+            ]
+        );
+    }
+
+    #[test]
+    fn test_split_at() {
+        let activity = OngoingActivity::new(datetime("2020-12-25T09:00:00"), vec![], None)
+            .pause(datetime("2020-12-25T09:30:00"))
+            .unwrap()
+            .resume(datetime("2020-12-25T09:45:00"))
+            .unwrap()
+            .into_activity(datetime("2020-12-25T10:00:00"))
+            .unwrap();
+        assert!(activity
+            .clone()
+            .split_at(datetime("2020-12-25T08:00:00"), None)
+            .is_err());
+        assert!(activity
+            .clone()
+            .split_at(datetime("2020-12-25T10:00:00"), None)
+            .is_err());
+        let (first, second) = activity
+            .clone()
+            .split_at(
+                datetime("2020-12-25T09:40:00"),
+                Some(vec![String::from("foo")]),
+            )
+            .unwrap();
+        assert_eq!(first.get_uid(), activity.get_uid());
+        assert_ne!(second.get_uid(), activity.get_uid());
+        assert_eq!(first.get_stop_time(), second.get_start_time());
+        assert_eq!(second.get_title(), "foo");
+        assert_eq!(first.get_net_duration().to_string(), "00:30:00");
+        assert_eq!(second.get_net_duration().to_string(), "00:15:00");
+    }
+
+    #[test]
+    fn test_merge() {
+        let first = OngoingActivity::new(
+            datetime("2020-12-25T09:00:00"),
+            vec![String::from("foo")],
+            Some(String::from("first")),
+        )
+        .into_activity(datetime("2020-12-25T09:30:00"))
+        .unwrap();
+        let second = OngoingActivity::new(
+            datetime("2020-12-25T09:40:00"),
+            vec![String::from("foo")],
+            Some(String::from("second")),
+        )
+        .into_activity(datetime("2020-12-25T10:00:00"))
+        .unwrap();
+        let merged = Activity::merge(vec![second, first.clone()]).unwrap();
+        assert_eq!(merged.get_uid(), first.get_uid());
+        assert_eq!(merged.get_start_time(), datetime("2020-12-25T09:00:00"));
+        assert_eq!(merged.get_stop_time(), datetime("2020-12-25T10:00:00"));
+        assert_eq!(
+            merged.get_description(),
+            Some(String::from("first\nsecond"))
+        );
+        assert_eq!(merged.get_net_duration().to_string(), "00:50:00");
+        assert!(Activity::merge(vec![]).is_err());
+    }
+
+    #[test]
+    fn test_merge_overlapping_with_different_tags() {
+        let first = OngoingActivity::new(
+            datetime("2020-12-25T09:00:00"),
+            vec![String::from("foo")],
+            None,
+        )
+        .pause(datetime("2020-12-25T09:30:00"))
+        .unwrap()
+        .resume(datetime("2020-12-25T10:30:00"))
+        .unwrap()
+        .into_activity(datetime("2020-12-25T11:00:00"))
+        .unwrap();
+        let second = OngoingActivity::new(
+            datetime("2020-12-25T09:45:00"),
+            vec![String::from("bar"), String::from("foo")],
+            None,
+        )
+        .into_activity(datetime("2020-12-25T10:00:00"))
+        .unwrap();
+        let merged = Activity::merge(vec![second, first]).unwrap();
+        assert_eq!(merged.get_title(), "foo bar");
+        assert_eq!(
+            merged.get_breaks(),
+            vec![
+                Break {
+                    start_time: datetime("2020-12-25T09:30:00"),
+                    stop_time: Some(datetime("2020-12-25T09:45:00")),
+                },
+                Break {
+                    start_time: datetime("2020-12-25T10:00:00"),
+                    stop_time: Some(datetime("2020-12-25T10:30:00")),
+                }
+            ]
+        );
+        assert_eq!(merged.get_net_duration().to_string(), "01:15:00");
+    }
 
     #[test]
     fn test_intersect() {
         let finished = Activity {
+            uid: Uuid::nil(),
             start_time: Local
                 .datetime_from_str("2020-12-25T09:00:00", "%Y-%m-%dT%H:%M:%S")
                 .unwrap()
@@ -162,6 +684,8 @@ mod tests {
                 .into(),
             tags: vec![],
             description: None,
+            breaks: vec![],
+            metadata: Metadata::new(),
         };
         let date = Local
             .datetime_from_str("2020-12-25T09:30:00", "%Y-%m-%dT%H:%M:%S")
@@ -178,6 +702,7 @@ mod tests {
     #[test]
     fn test_overlap() {
         let finished = Activity {
+            uid: Uuid::nil(),
             start_time: Local
                 .datetime_from_str("2020-12-25T09:00:00", "%Y-%m-%dT%H:%M:%S")
                 .unwrap()
@@ -188,8 +713,11 @@ mod tests {
                 .into(),
             tags: vec![],
             description: None,
+            breaks: vec![],
+            metadata: Metadata::new(),
         };
         let other = Activity {
+            uid: Uuid::nil(),
             start_time: Local
                 .datetime_from_str("2020-12-25T09:30:00", "%Y-%m-%dT%H:%M:%S")
                 .unwrap()
@@ -200,9 +728,12 @@ mod tests {
                 .into(),
             tags: vec![],
             description: None,
+            breaks: vec![],
+            metadata: Metadata::new(),
         };
         assert!(overlap(&finished, &other).is_some());
         let other = Activity {
+            uid: Uuid::nil(),
             start_time: Local
                 .datetime_from_str("2020-12-25T08:30:00", "%Y-%m-%dT%H:%M:%S")
                 .unwrap()
@@ -213,9 +744,12 @@ mod tests {
                 .into(),
             tags: vec![],
             description: None,
+            breaks: vec![],
+            metadata: Metadata::new(),
         };
         assert!(overlap(&finished, &other).is_some());
         let other = Activity {
+            uid: Uuid::nil(),
             start_time: Local
                 .datetime_from_str("2020-12-25T08:30:00", "%Y-%m-%dT%H:%M:%S")
                 .unwrap()
@@ -226,9 +760,12 @@ mod tests {
                 .into(),
             tags: vec![],
             description: None,
+            breaks: vec![],
+            metadata: Metadata::new(),
         };
         assert!(overlap(&finished, &other).is_some());
         let other = Activity {
+            uid: Uuid::nil(),
             start_time: Local
                 .datetime_from_str("2020-12-25T09:30:00", "%Y-%m-%dT%H:%M:%S")
                 .unwrap()
@@ -239,9 +776,12 @@ mod tests {
                 .into(),
             tags: vec![],
             description: None,
+            breaks: vec![],
+            metadata: Metadata::new(),
         };
         assert!(overlap(&finished, &other).is_some());
         let other = Activity {
+            uid: Uuid::nil(),
             start_time: Local
                 .datetime_from_str("2020-12-25T10:30:00", "%Y-%m-%dT%H:%M:%S")
                 .unwrap()
@@ -252,7 +792,79 @@ mod tests {
                 .into(),
             tags: vec![],
             description: None,
+            breaks: vec![],
+            metadata: Metadata::new(),
         };
         assert!(overlap(&finished, &other).is_none());
     }
+
+    #[test]
+    fn test_legacy_uid() {
+        let legacy: Activity = serde_json::from_str(
+            r#"{"start_time":"2020-12-25T09:00:00+01:00","stop_time":"2020-12-25T10:00:00+01:00","tags":["foo"]}"#,
+        )
+        .unwrap();
+        assert!(legacy.get_uid().is_nil());
+        let first = legacy.clone().with_legacy_uid();
+        let second = legacy.with_legacy_uid();
+        assert!(!first.get_uid().is_nil());
+        assert_eq!(first.get_uid(), second.get_uid());
+        // not overwritten once set
+        assert_eq!(first.clone().with_legacy_uid().get_uid(), first.get_uid());
+    }
+
+    #[test]
+    fn test_metadata() {
+        let legacy: Activity = serde_json::from_str(
+            r#"{"start_time":"2020-12-25T09:00:00+01:00","stop_time":"2020-12-25T10:00:00+01:00","tags":["foo"]}"#,
+        )
+        .unwrap();
+        assert!(legacy.get_metadata().is_empty());
+        assert!(!serde_json::to_string(&legacy).unwrap().contains("metadata"));
+        let metadata = |pairs: &[(&str, &str)]| -> Metadata {
+            pairs
+                .iter()
+                .map(|(k, v)| (k.to_string(), v.to_string()))
+                .collect()
+        };
+        let first = OngoingActivity::new(datetime("2020-12-25T09:00:00"), vec![], None)
+            .with_metadata(metadata(&[("ticket", "A-1"), ("client", "acme")]))
+            .into_activity(datetime("2020-12-25T09:30:00"))
+            .unwrap();
+        let second = OngoingActivity::new(datetime("2020-12-25T09:30:00"), vec![], None)
+            .with_metadata(metadata(&[("ticket", "A-2"), ("billable", "yes")]))
+            .into_activity(datetime("2020-12-25T10:00:00"))
+            .unwrap();
+        let merged = Activity::merge(vec![second, first]).unwrap();
+        assert_eq!(
+            merged.get_metadata(),
+            metadata(&[("ticket", "A-1"), ("client", "acme"), ("billable", "yes")])
+        );
+        let (before, after) = merged
+            .split_at(datetime("2020-12-25T09:15:00"), None)
+            .unwrap();
+        assert_eq!(before.get_metadata(), after.get_metadata());
+    }
+
+    #[test]
+    fn test_into_activity_keeps_uid() {
+        let ongoing = OngoingActivity::new(
+            Local
+                .datetime_from_str("2020-12-25T09:00:00", "%Y-%m-%dT%H:%M:%S")
+                .unwrap()
+                .into(),
+            vec![],
+            None,
+        );
+        let uid = ongoing.uid;
+        let finished = ongoing
+            .into_activity(
+                Local
+                    .datetime_from_str("2020-12-25T10:00:00", "%Y-%m-%dT%H:%M:%S")
+                    .unwrap()
+                    .into(),
+            )
+            .unwrap();
+        assert_eq!(finished.get_uid(), uid);
+    }
 }
@@ -3,9 +3,11 @@ use chrono::Duration;
 use std::fmt;
 use std::fmt::{Error, Formatter};
 use std::iter::Sum;
-use std::ops::Add;
+use std::ops::{Add, Sub};
+use std::str::FromStr;
 
 /// Newtype on `chrono::Duration`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
 pub struct DurationW(chrono::Duration);
 
 impl fmt::Display for DurationW {
@@ -52,8 +54,70 @@ impl Add<DurationW> for DurationW {
     }
 }
 
+impl Sub<DurationW> for DurationW {
+    type Output = DurationW;
+
+    fn sub(self, rhs: DurationW) -> Self::Output {
+        DurationW::new(self.0 - rhs.0)
+    }
+}
+
+/// Parse `HH:MM:SS` or a number followed by a unit (`s`, `m`/`min`, `h`) e.g. `15min`
+impl FromStr for DurationW {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || anyhow::anyhow!("invalid duration: {}", s);
+        let s = s.trim();
+        if s.contains(':') {
+            let parts: Vec<i64> = s
+                .split(':')
+                .map(|part| part.parse::<i64>().map_err(|_| invalid()))
+                .collect::<Result<_, _>>()?;
+            match parts.as_slice() {
+                [h, m, sec] if *m < 60 && *sec < 60 => Ok(DurationW::new(
+                    Duration::hours(*h) + Duration::minutes(*m) + Duration::seconds(*sec),
+                )),
+                _ => Err(invalid()),
+            }
+        } else {
+            let unit_start = s.find(|c: char| !c.is_ascii_digit()).unwrap_or(s.len());
+            let (value, unit) = s.split_at(unit_start);
+            let value: i64 = value.parse().map_err(|_| invalid())?;
+            match unit.trim() {
+                "s" => Ok(DurationW::new(Duration::seconds(value))),
+                "m" | "min" => Ok(DurationW::new(Duration::minutes(value))),
+                "h" => Ok(DurationW::new(Duration::hours(value))),
+                _ => Err(invalid()),
+            }
+        }
+    }
+}
+
 impl Sum for DurationW {
     fn sum<I: Iterator<Item = DurationW>>(iter: I) -> Self {
         iter.fold(DurationW::default(), Add::add)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use crate::rtw_core::durationw::DurationW;
+    use std::str::FromStr;
+
+    #[test]
+    fn test_from_str() {
+        for (s, expected) in &[
+            ("01:02:03", "01:02:03"),
+            ("90s", "00:01:30"),
+            ("15m", "00:15:00"),
+            ("15min", "00:15:00"),
+            ("2h", "02:00:00"),
+        ] {
+            assert_eq!(DurationW::from_str(s).unwrap().to_string(), *expected);
+        }
+        for s in &["", "15", "15 days", "01:60:00", "abc"] {
+            assert!(DurationW::from_str(s).is_err());
+        }
+    }
+}
@@ -16,6 +16,103 @@ pub type Tag = String;
 /// `Tags` = `Vec<Tag>`
 pub type Tags = Vec<Tag>;
 /// `ActivityId` = `usize`
+///
+/// Relative index: 0 <=> last activity, it shifts when activities are added or removed.
 pub type ActivityId = usize;
+/// `ActivityUid` = `Uuid`
+///
+/// Stable identifier, persisted along with the activity.
+pub type ActivityUid = uuid::Uuid;
 /// `Description` = `String`
 pub type Description = String;
+/// `Metadata` = `BTreeMap<String, String>`
+///
+/// Key-value fields e.g. `ticket=ABC-123`, kept out of tags (and titles).
+pub type Metadata = std::collections::BTreeMap<String, String>;
+
+/// Uid prefixes shorter than this are not accepted
+///
+/// Prefixes must also contain a non-digit character (`a-f` or `-`), digits only are an `ActivityId`.
+pub const UID_PREFIX_MIN_LEN: usize = 8;
+
+/// Select an activity either by its relative id or by its stable uid
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ActivitySelector {
+    /// Relative id, e.g. `0` for the last activity
+    Id(ActivityId),
+    /// Full uid or uid prefix (at least `UID_PREFIX_MIN_LEN` characters, not only digits)
+    Uid(String),
+}
+
+impl ActivitySelector {
+    /// Return true if the activity with `id` and `uid` is selected
+    pub fn matches(&self, id: ActivityId, uid: &ActivityUid) -> bool {
+        match self {
+            ActivitySelector::Id(selected_id) => *selected_id == id,
+            ActivitySelector::Uid(prefix) => uid.to_string().starts_with(prefix.as_str()),
+        }
+    }
+}
+
+impl std::str::FromStr for ActivitySelector {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if s.len() < UID_PREFIX_MIN_LEN || s.chars().all(|c| c.is_ascii_digit()) {
+            s.parse::<ActivityId>()
+                .map(ActivitySelector::Id)
+                .map_err(|_| anyhow::anyhow!("invalid id: {}", s))
+        } else if s.chars().all(|c| c.is_ascii_hexdigit() || c == '-') {
+            Ok(ActivitySelector::Uid(s.to_lowercase()))
+        } else {
+            Err(anyhow::anyhow!("invalid uid: {}", s))
+        }
+    }
+}
+
+impl std::fmt::Display for ActivitySelector {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ActivitySelector::Id(id) => write!(f, "{}", id),
+            ActivitySelector::Uid(uid) => write!(f, "{}", uid),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::rtw_core::ActivitySelector;
+    use std::str::FromStr;
+    use uuid::Uuid;
+
+    #[test]
+    fn test_parse_selector() {
+        assert_eq!(
+            ActivitySelector::from_str("42").unwrap(),
+            ActivitySelector::Id(42)
+        );
+        assert_eq!(
+            ActivitySelector::from_str("0A1B2C3D").unwrap(),
+            ActivitySelector::Uid(String::from("0a1b2c3d"))
+        );
+        assert_eq!(
+            ActivitySelector::from_str("12345678").unwrap(),
+            ActivitySelector::Id(12345678)
+        );
+        assert_eq!(
+            ActivitySelector::from_str("12345678-").unwrap(),
+            ActivitySelector::Uid(String::from("12345678-"))
+        );
+        assert!(ActivitySelector::from_str("foo").is_err());
+        assert!(ActivitySelector::from_str("not-a-uid").is_err());
+    }
+
+    #[test]
+    fn test_selector_matches() {
+        let uid = Uuid::parse_str("0a1b2c3d-0000-4000-8000-000000000000").unwrap();
+        assert!(ActivitySelector::Id(1).matches(1, &uid));
+        assert!(!ActivitySelector::Id(2).matches(1, &uid));
+        assert!(ActivitySelector::Uid(String::from("0a1b2c3d")).matches(2, &uid));
+        assert!(!ActivitySelector::Uid(String::from("0a1b2c3e")).matches(2, &uid));
+    }
+}
@@ -1,7 +1,7 @@
 //! A service for activities: abstracts activities queries and modifications.
 use crate::rtw_core::activity::{Activity, OngoingActivity};
 use crate::rtw_core::datetimew::DateTimeW;
-use crate::rtw_core::ActivityId;
+use crate::rtw_core::{ActivityId, ActivityUid};
 
 /// A service for activities
 ///
@@ -11,10 +11,6 @@ pub trait ActivityService {
     ///
     /// May fail depending on backend implementation
     fn get_ongoing_activities(&self) -> anyhow::Result<Vec<(ActivityId, OngoingActivity)>>;
-    /// Get ongoing activity with id if any
-    ///
-    /// May fail depending on backend implementation
-    fn get_ongoing_activity(&self, id: ActivityId) -> anyhow::Result<Option<OngoingActivity>>;
     /// Start a new activity
     ///
     /// May fail depending on backend implementation
@@ -33,7 +29,7 @@ pub trait ActivityService {
     fn stop_ongoing_activity(
         &mut self,
         time: DateTimeW,
-        id: ActivityId,
+        uid: ActivityUid,
         deny_overlapping: bool,
     ) -> anyhow::Result<Option<Activity>>;
     /// Cancel current activity
@@ -43,7 +39,18 @@ pub trait ActivityService {
     /// Returns cancelled activity if any
     fn cancel_ongoing_activity(
         &mut self,
-        id: ActivityId,
+        uid: ActivityUid,
+    ) -> anyhow::Result<Option<OngoingActivity>>;
+    /// Replace ongoing activity with uid by `modified`
+    ///
+    /// May fail depending on backend implementation
+    ///
+    /// Returns replaced activity if any
+    fn modify_ongoing_activity(
+        &mut self,
+        uid: ActivityUid,
+        modified: OngoingActivity,
+        deny_overlapping: bool,
     ) -> anyhow::Result<Option<OngoingActivity>>;
     /// Filter finished activities
     ///
@@ -55,6 +62,16 @@ pub trait ActivityService {
     fn filter_activities<P>(&self, p: P) -> anyhow::Result<Vec<(ActivityId, Activity)>>
     where
         P: Fn(&(ActivityId, Activity)) -> bool;
+    /// Filter finished activities started between `range_start` and `range_end` (inclusive)
+    ///
+    /// May fail depending on implementation
+    ///
+    /// Returns finished activities sorted by start date
+    fn filter_activities_by_range(
+        &self,
+        range_start: DateTimeW,
+        range_end: DateTimeW,
+    ) -> anyhow::Result<Vec<(ActivityId, Activity)>>;
     /// Get all finished activities
     ///
     /// May fail depending on implementation
@@ -63,12 +80,23 @@ pub trait ActivityService {
     ///
     /// ActivityId: 0 <=> last finished activity
     fn get_finished_activities(&self) -> anyhow::Result<Vec<(ActivityId, Activity)>>;
-    /// Delete activity with id
+    /// Delete activity with uid
     ///
     /// May fail depending on implementation
     ///
     /// Returns deleted activity if successful
-    fn delete_activity(&self, id: ActivityId) -> anyhow::Result<Option<Activity>>;
+    fn delete_activity(&self, uid: ActivityUid) -> anyhow::Result<Option<Activity>>;
+    /// Replace finished activity with uid by `edited`
+    ///
+    /// May fail depending on backend implementation
+    ///
+    /// Returns replaced activity if any
+    fn edit_activity(
+        &mut self,
+        uid: ActivityUid,
+        edited: Activity,
+        deny_overlapping: bool,
+    ) -> anyhow::Result<Option<Activity>>;
     /// Track a finished activity
     ///
     /// May fail depending on backend implementation
@@ -1,6 +1,7 @@
 //! Storage: abstracts activities storage (file, memory...)
 use crate::rtw_core::activity::{Activity, OngoingActivity};
-use crate::rtw_core::ActivityId;
+use crate::rtw_core::datetimew::DateTimeW;
+use crate::rtw_core::{ActivityId, ActivityUid};
 use std::error::Error;
 
 pub trait Storage {
@@ -21,6 +22,22 @@ pub trait Storage {
     fn filter_activities<P>(&self, p: P) -> Result<Vec<(ActivityId, Activity)>, Self::StorageError>
     where
         P: Fn(&(ActivityId, Activity)) -> bool;
+    /// Filter finished activities started between `range_start` and `range_end` (inclusive)
+    ///
+    /// May fail depending on implementation
+    ///
+    /// Returns finished activities sorted by start date
+    ///
+    /// Backends able to query by range (e.g. an index) should override the default implementation.
+    fn filter_activities_by_range(
+        &self,
+        range_start: DateTimeW,
+        range_end: DateTimeW,
+    ) -> Result<Vec<(ActivityId, Activity)>, Self::StorageError> {
+        self.filter_activities(|(_id, a)| {
+            range_start <= a.get_start_time() && a.get_start_time() <= range_end
+        })
+    }
     /// Get all finished activities
     ///
     /// May fail depending on implementation
@@ -29,35 +46,51 @@ pub trait Storage {
     ///
     /// ActivityId: 0 <=> last finished activity
     fn get_finished_activities(&self) -> Result<Vec<(ActivityId, Activity)>, Self::StorageError>;
-    /// Delete activity with id
+    /// Delete activity with uid
     ///
     /// May fail depending on implementation
     ///
     /// Returns deleted activity if successful
-    fn delete_activity(&self, id: ActivityId) -> Result<Option<Activity>, Self::StorageError>;
+    fn delete_activity(&self, uid: ActivityUid) -> Result<Option<Activity>, Self::StorageError>;
     /// Retrieve ongoing activities if any
     ///
     /// May fail depending on backend implementation
     fn get_ongoing_activities(
         &self,
     ) -> Result<Vec<(ActivityId, OngoingActivity)>, Self::StorageError>;
-    /// Retrieve ongoing activity with id if any
+    /// Retrieve ongoing activity with uid if any
     ///
     /// May fail depending on backend implementation
     fn get_ongoing_activity(
         &self,
-        id: ActivityId,
+        uid: ActivityUid,
     ) -> Result<Option<OngoingActivity>, Self::StorageError>;
     /// Add `activity` to ongoing activities
     ///
     /// May fail depending on backend implementation
     fn add_ongoing_activity(&mut self, activity: OngoingActivity)
         -> Result<(), Self::StorageError>;
-    /// Remove ongoing activity
+    /// Remove ongoing activity with uid
     ///
     /// May fail depending on backend implementation
     fn remove_ongoing_activity(
         &mut self,
-        id: ActivityId,
+        uid: ActivityUid,
     ) -> Result<Option<OngoingActivity>, Self::StorageError>;
+    /// Start a unit of reads and writes: until `end`, other processes can't change activities
+    ///
+    /// Units may be nested, only the outermost one counts.
+    ///
+    /// May fail depending on backend implementation
+    fn begin(&mut self) -> Result<(), Self::StorageError> {
+        Ok(())
+    }
+    /// End the unit started by `begin`
+    ///
+    /// Backends supporting it keep the writes of the unit only if `commit` (all or nothing).
+    ///
+    /// May fail depending on backend implementation
+    fn end(&mut self, _commit: bool) -> Result<(), Self::StorageError> {
+        Ok(())
+    }
 }
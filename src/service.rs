@@ -1,10 +1,14 @@
 //! Logic above an activity storage
+use crate::journal::Change;
 use crate::rtw_core::activity::{intersect, overlap, Activity, OngoingActivity};
 use crate::rtw_core::datetimew::DateTimeW;
 use crate::rtw_core::service::ActivityService;
 use crate::rtw_core::storage::Storage;
-use crate::rtw_core::ActivityId;
+use crate::rtw_core::{ActivityId, ActivityUid};
 use anyhow::anyhow;
+use std::collections::HashMap;
+
+type ActivityWithId = (ActivityId, Activity);
 
 pub struct Service<S>
 where
@@ -20,6 +24,73 @@ where
     pub fn new(storage: S) -> Self {
         Service { storage }
     }
+
+    /// Run `f` as a single storage unit (see `Storage::begin`)
+    ///
+    /// Other processes can't interleave changes between the reads and writes of `f`.
+    pub fn atomically<T, F>(&mut self, f: F) -> anyhow::Result<T>
+    where
+        F: FnOnce(&mut Self) -> anyhow::Result<T>,
+    {
+        self.storage.begin()?;
+        match f(self) {
+            Ok(value) => {
+                self.storage.end(true)?;
+                Ok(value)
+            }
+            Err(e) => {
+                // the error of `f` matters more than a failed rollback
+                let _ = self.storage.end(false);
+                Err(e)
+            }
+        }
+    }
+
+    /// Check `changes` can be applied in order to the current activities
+    ///
+    /// Fails if an activity to remove is gone or an activity to add is already there.
+    pub fn check_changes(&self, changes: &[Change]) -> anyhow::Result<()> {
+        // presence of activities already added or removed by previous changes
+        let mut updated: HashMap<(ActivityUid, bool), bool> = HashMap::new();
+        for change in changes {
+            let (uid, finished, expected) = match change {
+                Change::AddFinished(a) => (a.get_uid(), true, false),
+                Change::RemoveFinished(a) => (a.get_uid(), true, true),
+                Change::AddOngoing(a) => (a.uid, false, false),
+                Change::RemoveOngoing(a) => (a.uid, false, true),
+            };
+            let present = match updated.get(&(uid, finished)) {
+                Some(present) => *present,
+                None if finished => !self
+                    .storage
+                    .filter_activities(|(_id, a)| a.get_uid() == uid)?
+                    .is_empty(),
+                None => self.storage.get_ongoing_activity(uid)?.is_some(),
+            };
+            match (expected, present) {
+                (true, false) => return Err(anyhow!("activity {} no longer exists", uid)),
+                (false, true) => return Err(anyhow!("activity {} already exists", uid)),
+                _ => {}
+            }
+            updated.insert((uid, finished), !expected);
+        }
+        Ok(())
+    }
+
+    /// Apply `change` as is (without overlap checks)
+    pub fn apply_change(&mut self, change: &Change) -> anyhow::Result<()> {
+        match change {
+            Change::AddFinished(a) => self.storage.write_activity(a.clone())?,
+            Change::RemoveFinished(a) => {
+                let _removed = self.storage.delete_activity(a.get_uid())?;
+            }
+            Change::AddOngoing(a) => self.storage.add_ongoing_activity(a.clone())?,
+            Change::RemoveOngoing(a) => {
+                let _removed = self.storage.remove_ongoing_activity(a.uid)?;
+            }
+        }
+        Ok(())
+    }
 }
 
 impl<S> ActivityService for Service<S>
@@ -30,88 +101,118 @@ where
         self.storage.get_ongoing_activities().map_err(|e| e.into())
     }
 
-    fn get_ongoing_activity(&self, id: ActivityId) -> anyhow::Result<Option<OngoingActivity>> {
-        self.storage.get_ongoing_activity(id).map_err(|e| e.into())
-    }
-
     fn start_activity(
         &mut self,
         activity: OngoingActivity,
         deny_overlapping: bool,
     ) -> anyhow::Result<(OngoingActivity, Option<Activity>)> {
-        let finished = self.storage.get_finished_activities()?;
-        if deny_overlapping {
-            let intersections = time_intersections(finished.as_slice(), &activity.start_time);
-            if intersections.is_empty() {
-                let ongoing_activities = self.storage.get_ongoing_activities()?;
-                match ongoing_activities.as_slice() {
-                    [] => {
-                        self.storage.add_ongoing_activity(activity.clone())?;
-                        Ok((activity, None))
-                    }
-                    [(ongoing_id, _ongoing)] => {
-                        let stopped_maybe =
-                            self.stop_ongoing_activity(activity.start_time, *ongoing_id, true)?;
-                        self.storage.add_ongoing_activity(activity.clone())?;
-                        Ok((activity, stopped_maybe))
+        self.atomically(|service| {
+            let finished = service.storage.get_finished_activities()?;
+            if deny_overlapping {
+                let intersections = time_intersections(finished.as_slice(), &activity.start_time);
+                if intersections.is_empty() {
+                    let ongoing_activities = service.storage.get_ongoing_activities()?;
+                    match ongoing_activities.as_slice() {
+                        [] => {
+                            service.storage.add_ongoing_activity(activity.clone())?;
+                            Ok((activity, None))
+                        }
+                        [(_ongoing_id, ongoing)] => {
+                            let stopped_maybe =
+                                service.stop_ongoing_activity(activity.start_time, ongoing.uid, true)?;
+                            service.storage.add_ongoing_activity(activity.clone())?;
+                            Ok((activity, stopped_maybe))
+                        }
+                        _ => Err(anyhow!(
+                            "multiple ongoing activities but overlapping is disabled\n\
+                            Tip: you can enable overlapping using `rtw --overlap (start|stop|track|...)`"
+                        )),
                     }
-                    _ => Err(anyhow!(
-                        "multiple ongoing activities but overlapping is disabled\n\
-                        Tip: you can enable overlapping using `rtw --overlap (start|stop|track|...)`"
-                    )),
+                } else {
+                    Err(anyhow!(
+                        "{:?} would overlap {:?}\n\
+                    Tip: you can enable overlapping using `rtw --overlap (start|stop|track|...)`",
+                        activity,
+                        intersections
+                    ))
                 }
             } else {
-                Err(anyhow!(
-                    "{:?} would overlap {:?}\n\
-                Tip: you can enable overlapping using `rtw --overlap (start|stop|track|...)`",
-                    activity,
-                    intersections
-                ))
+                service.storage.add_ongoing_activity(activity.clone())?;
+                Ok((activity, None))
             }
-        } else {
-            self.storage.add_ongoing_activity(activity.clone())?;
-            Ok((activity, None))
-        }
+        })
     }
 
     fn stop_ongoing_activity(
         &mut self,
         time: DateTimeW,
-        id: ActivityId,
+        uid: ActivityUid,
         deny_overlapping: bool,
     ) -> anyhow::Result<Option<Activity>> {
-        let stopped_maybe = self.storage.get_ongoing_activity(id)?;
-        match stopped_maybe {
-            None => Ok(None),
-            Some(ongoing_activity) => {
-                let stopped = ongoing_activity.clone().into_activity(time)?;
-                let finished = self.storage.get_finished_activities()?;
-                let intersections = activity_intersections(finished.as_slice(), &stopped);
-                if !deny_overlapping || intersections.is_empty() {
-                    self.storage.write_activity(stopped)?;
-                    self.storage.remove_ongoing_activity(id)?;
-                    Ok(Some(ongoing_activity.into_activity(time)?))
-                } else {
-                    Err(anyhow!(
-                        "{:?} would overlap {:?}\n\
-                    Tip: you can enable overlapping using `rtw --overlap (start|stop|track|...)`",
-                        stopped,
-                        intersections
-                    ))
+        self.atomically(|service| {
+            let stopped_maybe = service.storage.get_ongoing_activity(uid)?;
+            match stopped_maybe {
+                None => Ok(None),
+                Some(ongoing_activity) => {
+                    let stopped = ongoing_activity.clone().into_activity(time)?;
+                    let finished = service.storage.get_finished_activities()?;
+                    let intersections = activity_intersections(finished.as_slice(), &stopped);
+                    if !deny_overlapping || intersections.is_empty() {
+                        service.storage.write_activity(stopped)?;
+                        service.storage.remove_ongoing_activity(uid)?;
+                        Ok(Some(ongoing_activity.into_activity(time)?))
+                    } else {
+                        Err(anyhow!(
+                            "{:?} would overlap {:?}\n\
+                        Tip: you can enable overlapping using `rtw --overlap (start|stop|track|...)`",
+                            stopped,
+                            intersections
+                        ))
+                    }
                 }
             }
-        }
+        })
     }
 
     fn cancel_ongoing_activity(
         &mut self,
-        id: ActivityId,
+        uid: ActivityUid,
     ) -> anyhow::Result<Option<OngoingActivity>> {
         self.storage
-            .remove_ongoing_activity(id)
+            .remove_ongoing_activity(uid)
             .map_err(|e| e.into())
     }
 
+    fn modify_ongoing_activity(
+        &mut self,
+        uid: ActivityUid,
+        modified: OngoingActivity,
+        deny_overlapping: bool,
+    ) -> anyhow::Result<Option<OngoingActivity>> {
+        self.atomically(|service| {
+            match service.storage.get_ongoing_activity(uid)? {
+                None => Ok(None),
+                Some(replaced) => {
+                    let finished = service.storage.get_finished_activities()?;
+                    let intersections =
+                        ongoing_intersections(finished.as_slice(), &modified.start_time);
+                    if !deny_overlapping || intersections.is_empty() {
+                        service.storage.remove_ongoing_activity(uid)?;
+                        service.storage.add_ongoing_activity(modified)?;
+                        Ok(Some(replaced))
+                    } else {
+                        Err(anyhow!(
+                            "{:?} would overlap {:?}\n\
+                        Tip: you can enable overlapping using `rtw --overlap (start|stop|track|...)`",
+                            modified,
+                            intersections
+                        ))
+                    }
+                }
+            }
+        })
+    }
+
     fn filter_activities<P>(&self, p: P) -> anyhow::Result<Vec<(ActivityId, Activity)>>
     where
         P: Fn(&(ActivityId, Activity)) -> bool,
@@ -119,12 +220,55 @@ where
         self.storage.filter_activities(p).map_err(|e| e.into())
     }
 
+    fn filter_activities_by_range(
+        &self,
+        range_start: DateTimeW,
+        range_end: DateTimeW,
+    ) -> anyhow::Result<Vec<(ActivityId, Activity)>> {
+        self.storage
+            .filter_activities_by_range(range_start, range_end)
+            .map_err(|e| e.into())
+    }
+
     fn get_finished_activities(&self) -> anyhow::Result<Vec<(ActivityId, Activity)>> {
         self.storage.get_finished_activities().map_err(|e| e.into())
     }
 
-    fn delete_activity(&self, id: ActivityId) -> anyhow::Result<Option<Activity>> {
-        self.storage.delete_activity(id).map_err(|e| e.into())
+    fn delete_activity(&self, uid: ActivityUid) -> anyhow::Result<Option<Activity>> {
+        self.storage.delete_activity(uid).map_err(|e| e.into())
+    }
+
+    fn edit_activity(
+        &mut self,
+        uid: ActivityUid,
+        edited: Activity,
+        deny_overlapping: bool,
+    ) -> anyhow::Result<Option<Activity>> {
+        self.atomically(|service| {
+            let (replaced, others): (Vec<ActivityWithId>, Vec<ActivityWithId>) = service
+                .storage
+                .get_finished_activities()?
+                .into_iter()
+                .partition(|(_id, a)| a.get_uid() == uid);
+            match replaced.into_iter().next() {
+                None => Ok(None),
+                Some((_id, replaced)) => {
+                    let intersections = activity_intersections(others.as_slice(), &edited);
+                    if !deny_overlapping || intersections.is_empty() {
+                        service.storage.delete_activity(uid)?;
+                        service.storage.write_activity(edited)?;
+                        Ok(Some(replaced))
+                    } else {
+                        Err(anyhow!(
+                            "{:?} would overlap {:?}\n\
+                        Tip: you can enable overlapping using `rtw --overlap (start|stop|track|...)`",
+                            edited,
+                            intersections
+                        ))
+                    }
+                }
+            }
+        })
     }
 
     fn track_activity(
@@ -132,19 +276,21 @@ where
         activity: Activity,
         deny_overlapping: bool,
     ) -> anyhow::Result<Activity> {
-        let finished = self.storage.get_finished_activities()?;
-        let intersections = activity_intersections(finished.as_slice(), &activity);
-        if !deny_overlapping || intersections.is_empty() {
-            self.storage.write_activity(activity.clone())?;
-            Ok(activity)
-        } else {
-            Err(anyhow!(
-                "{:?} would overlap {:?}\n\
-            Tip: you can enable overlapping using `rtw --overlap (start|stop|track|...)`",
-                activity,
-                intersections
-            ))
-        }
+        self.atomically(|service| {
+            let finished = service.storage.get_finished_activities()?;
+            let intersections = activity_intersections(finished.as_slice(), &activity);
+            if !deny_overlapping || intersections.is_empty() {
+                service.storage.write_activity(activity.clone())?;
+                Ok(activity)
+            } else {
+                Err(anyhow!(
+                    "{:?} would overlap {:?}\n\
+                Tip: you can enable overlapping using `rtw --overlap (start|stop|track|...)`",
+                    activity,
+                    intersections
+                ))
+            }
+        })
     }
 }
 
@@ -168,6 +314,20 @@ fn time_intersections(
         .collect()
 }
 
+/// Finished activities overlapping an ongoing activity started at `start_time`
+///
+/// An ongoing activity runs until now, so any finished activity stopping after its start overlaps it.
+fn ongoing_intersections(
+    activities: &[(ActivityId, Activity)],
+    start_time: &DateTimeW,
+) -> Vec<Activity> {
+    activities
+        .iter()
+        .filter(|(_, a)| &a.get_stop_time() > start_time)
+        .map(|(_, a)| a.clone())
+        .collect()
+}
+
 #[cfg(test)]
 mod tests {
     use crate::chrono_clock::ChronoClock;
@@ -177,8 +337,11 @@ mod tests {
     use crate::rtw_core::datetimew::DateTimeW;
     use crate::rtw_core::service::ActivityService;
     use crate::service::Service;
+    use crate::sqlite_storage::SqliteStorage;
+    use anyhow::anyhow;
     use chrono::{Local, TimeZone};
     use tempfile::{tempdir, TempDir};
+    use uuid::Uuid;
 
     fn build_json_service(test_dir: &TempDir) -> Service<JsonStorage> {
         let finished_path = test_dir.path().join(".rtwh.json");
@@ -192,7 +355,7 @@ mod tests {
         let test_dir = tempdir().expect("error while creating tempdir");
         let mut service = build_json_service(&test_dir);
         assert!(service
-            .stop_ongoing_activity(clock.get_time(), 0, true)
+            .stop_ongoing_activity(clock.get_time(), Uuid::nil(), true)
             .is_ok());
         assert!(service.get_ongoing_activities().unwrap().is_empty());
     }
@@ -203,14 +366,10 @@ mod tests {
         let test_dir = tempdir().expect("error while creating tempdir");
         let mut service = build_json_service(&test_dir);
         assert!(service
-            .stop_ongoing_activity(clock.get_time(), 0, true)
+            .stop_ongoing_activity(clock.get_time(), Uuid::nil(), true)
             .is_ok());
         let start = service.start_activity(
-            OngoingActivity {
-                start_time: clock.get_time(),
-                tags: vec![String::from("a")],
-                description: None,
-            },
+            OngoingActivity::new(clock.get_time(), vec![String::from("a")], None),
             true,
         );
         start.unwrap();
@@ -224,18 +383,15 @@ mod tests {
         let clock = ChronoClock {};
         let test_dir = tempdir().expect("error while creating tempdir");
         let mut service = build_json_service(&test_dir);
-        let start = service.start_activity(
-            OngoingActivity {
-                start_time: clock.get_time(),
-                tags: vec![String::from("a")],
-                description: None,
-            },
-            true,
-        );
-        start.unwrap();
+        let (started, _) = service
+            .start_activity(
+                OngoingActivity::new(clock.get_time(), vec![String::from("a")], None),
+                true,
+            )
+            .unwrap();
         assert!(!service.get_ongoing_activities().unwrap().is_empty());
         assert!(service
-            .stop_ongoing_activity(clock.get_time(), 0, true)
+            .stop_ongoing_activity(clock.get_time(), started.uid, true)
             .is_ok());
         assert!(service.get_ongoing_activities().unwrap().is_empty());
     }
@@ -245,25 +401,18 @@ mod tests {
         let clock = ChronoClock {};
         let test_dir = tempdir().expect("error while creating tempdir");
         let mut service = build_json_service(&test_dir);
-        let start_0 = service.start_activity(
-            OngoingActivity {
-                start_time: clock.get_time(),
-                tags: vec![String::from("a")],
-                description: None,
-            },
-            true,
-        );
-        assert!(start_0.is_ok());
+        let (start_0, _) = service
+            .start_activity(
+                OngoingActivity::new(clock.get_time(), vec![String::from("a")], None),
+                true,
+            )
+            .unwrap();
         assert!(!service.get_ongoing_activities().unwrap().is_empty());
-        let stop = service.stop_ongoing_activity(clock.get_time(), 0, true);
+        let stop = service.stop_ongoing_activity(clock.get_time(), start_0.uid, true);
         assert!(stop.is_ok());
         assert!(service.get_ongoing_activities().unwrap().is_empty());
         let start_1 = service.start_activity(
-            OngoingActivity {
-                start_time: clock.get_time(),
-                tags: vec![String::from("b")],
-                description: None,
-            },
+            OngoingActivity::new(clock.get_time(), vec![String::from("b")], None),
             true,
         );
         assert!(start_1.is_ok());
@@ -334,17 +483,98 @@ mod tests {
         );
         let started = service.start_activity(other, true);
         assert!(started.is_ok());
+        let (started, _) = started.unwrap();
         let stopped = service.stop_ongoing_activity(
             Local
                 .datetime_from_str("2020-12-25T09:30:00", "%Y-%m-%dT%H:%M:%S")
                 .unwrap()
                 .into(),
-            0, // only one ongoing activity => id is 0
+            started.uid,
             true,
         );
         assert!(stopped.is_err());
     }
 
+    #[test]
+    fn test_edit_activity() {
+        let test_dir = tempdir().expect("error while creating tempdir");
+        let mut service = build_json_service(&test_dir);
+        let today = chrono::Local::today();
+        let first = OngoingActivity::new(today.and_hms(8, 0, 0).into(), vec![], None)
+            .into_activity(today.and_hms(9, 0, 0).into())
+            .unwrap();
+        let second = OngoingActivity::new(today.and_hms(10, 0, 0).into(), vec![], None)
+            .into_activity(today.and_hms(11, 0, 0).into())
+            .unwrap();
+        service.track_activity(first.clone(), true).unwrap();
+        service.track_activity(second.clone(), true).unwrap();
+        let overlapping = OngoingActivity {
+            start_time: today.and_hms(8, 30, 0).into(),
+            ..second.clone().into_ongoing_activity()
+        }
+        .into_activity(second.get_stop_time())
+        .unwrap();
+        assert!(service
+            .edit_activity(second.get_uid(), overlapping, true)
+            .is_err());
+        let edited = OngoingActivity {
+            start_time: today.and_hms(9, 30, 0).into(),
+            ..second.clone().into_ongoing_activity()
+        }
+        .into_activity(second.get_stop_time())
+        .unwrap();
+        let replaced = service
+            .edit_activity(second.get_uid(), edited.clone(), true)
+            .unwrap();
+        assert_eq!(replaced, Some(second));
+        let activities = service.get_finished_activities().unwrap();
+        assert_eq!(activities, vec![(1, first), (0, edited)]);
+    }
+
+    #[test]
+    fn test_modify_ongoing_activity() {
+        let test_dir = tempdir().expect("error while creating tempdir");
+        let mut service = build_json_service(&test_dir);
+        let today = chrono::Local::today();
+        let finished = OngoingActivity::new(today.and_hms(8, 0, 0).into(), vec![], None)
+            .into_activity(today.and_hms(9, 0, 0).into())
+            .unwrap();
+        service.track_activity(finished, true).unwrap();
+        let (started, _) = service
+            .start_activity(
+                OngoingActivity::new(today.and_hms(10, 0, 0).into(), vec![], None),
+                true,
+            )
+            .unwrap();
+        let overlapping = OngoingActivity {
+            start_time: today.and_hms(8, 30, 0).into(),
+            ..started.clone()
+        };
+        assert!(service
+            .modify_ongoing_activity(started.uid, overlapping, true)
+            .is_err());
+        let covering = OngoingActivity {
+            start_time: today.and_hms(7, 30, 0).into(),
+            ..started.clone()
+        };
+        assert!(service
+            .modify_ongoing_activity(started.uid, covering, true)
+            .is_err());
+        let modified = OngoingActivity {
+            start_time: today.and_hms(9, 30, 0).into(),
+            tags: vec![String::from("a")],
+            ..started.clone()
+        };
+        let replaced = service
+            .modify_ongoing_activity(started.uid, modified.clone(), true)
+            .unwrap();
+        assert_eq!(replaced, Some(started));
+        assert_eq!(
+            service.get_ongoing_activities().unwrap(),
+            vec![(0, modified)]
+        );
+    }
+
     #[test]
     fn test_summary_nothing() {
         let clock = ChronoClock {};
@@ -442,4 +672,30 @@ mod tests {
         let tracked = service.track_activity(other, true);
         assert!(tracked.is_err());
     }
+
+    #[test]
+    fn test_atomically() {
+        let clock = ChronoClock {};
+        let test_dir = tempdir().expect("error while creating tempdir");
+        let mut service = build_json_service(&test_dir);
+        // nested units and storage writes inside a unit don't wait for their own lock
+        service
+            .atomically(|service| {
+                service.atomically(|service| {
+                    service
+                        .start_activity(OngoingActivity::new(clock.get_time(), vec![], None), true)
+                })
+            })
+            .unwrap();
+        assert_eq!(service.get_ongoing_activities().unwrap().len(), 1);
+        let mut service =
+            Service::new(SqliteStorage::open(&test_dir.path().join(".rtw.sqlite")).unwrap());
+        let failed: anyhow::Result<()> = service.atomically(|service| {
+            service.start_activity(OngoingActivity::new(clock.get_time(), vec![], None), true)?;
+            Err(anyhow!("failure after the write"))
+        });
+        assert!(failed.is_err());
+        // rolled back
+        assert!(service.get_ongoing_activities().unwrap().is_empty());
+    }
 }
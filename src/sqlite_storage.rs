@@ -0,0 +1,605 @@
+//! Store activities (current, finished) in a SQLite database.
+use crate::rtw_core::activity::{Activity, OngoingActivity};
+use crate::rtw_core::datetimew::DateTimeW;
+use crate::rtw_core::storage::Storage;
+use crate::rtw_core::{ActivityId, ActivityUid, Description, Tags};
+use chrono::{DateTime, Local, TimeZone};
+use rusqlite::{params, Connection, Row};
+use std::path::Path;
+use std::time::Duration;
+use thiserror::Error;
+
+type ActivityWithId = (ActivityId, Activity);
+type OngoingActivityWithId = (ActivityId, OngoingActivity);
+
+/// Schema migrations, `PRAGMA user_version` stores how many were applied.
+///
+/// Times are stored as nanoseconds since UNIX epoch so that range queries can use the indexes.
+const MIGRATIONS: &[&str] = &[r#"
+    CREATE TABLE finished (
+        uid TEXT NOT NULL,
+        start_time INTEGER NOT NULL,
+        stop_time INTEGER NOT NULL,
+        tags TEXT NOT NULL,
+        description TEXT,
+        breaks TEXT NOT NULL,
+        metadata TEXT NOT NULL
+    );
+    CREATE INDEX finished_start_time ON finished(start_time);
+    CREATE INDEX finished_stop_time ON finished(stop_time);
+    CREATE INDEX finished_uid ON finished(uid);
+    CREATE TABLE ongoing (
+        uid TEXT NOT NULL,
+        start_time INTEGER NOT NULL,
+        tags TEXT NOT NULL,
+        description TEXT,
+        breaks TEXT NOT NULL,
+        metadata TEXT NOT NULL
+    );
+    CREATE INDEX ongoing_uid ON ongoing(uid);
+    CREATE TABLE meta (key TEXT PRIMARY KEY, value TEXT NOT NULL);
+    "#];
+
+/// Recorded in `meta` (with the imported activities) once json files are imported
+const MARK_IMPORTED: &str = "INSERT INTO meta (key, value) VALUES ('imported', 'json')";
+
+// ActivityId: 0 <=> last finished activity (see `SqliteStorage::with_ids`)
+// ties on start time are broken by insertion order (same as `JsonStorage`)
+const SELECT_FINISHED: &str = r#"
+    SELECT f.start_time, f.stop_time, f.tags, f.description, f.uid, f.rowid, f.breaks, f.metadata
+    FROM finished AS f
+    "#;
+
+// ORDER BY matching the relative ids
+const ORDER_FINISHED: &str = "ORDER BY f.start_time, f.rowid";
+
+const COUNT_LATER: &str = r#"
+    SELECT COUNT(*) FROM finished
+    WHERE start_time > ?1 OR (start_time = ?1 AND rowid > ?2)
+    "#;
+
+const SELECT_ONGOING: &str = r#"
+    SELECT rowid, start_time, tags, description, uid, breaks, metadata FROM ongoing
+    "#;
+
+#[derive(Error, Debug)]
+pub enum SqliteStorageError {
+    #[error("sqlite error")]
+    SqliteError(#[from] rusqlite::Error),
+    #[error("(de)serialization failed")]
+    SerdeJsonError(#[from] serde_json::error::Error),
+    #[error("invalid activity")]
+    InvalidActivity(#[from] anyhow::Error),
+    #[error("invalid uid")]
+    UuidError(#[from] uuid::Error),
+    #[error("time out of storable range: {0}")]
+    TimeOutOfRange(DateTimeW),
+}
+
+pub struct SqliteStorage {
+    connection: Connection,
+    /// Nesting depth of units (see `Storage::begin`), the outermost one is a transaction
+    unit_depth: usize,
+}
+
+// nanoseconds fit in an i64 roughly between years 1677 and 2262
+fn to_nanos(d: DateTimeW) -> Result<i64, SqliteStorageError> {
+    let datetime: DateTime<Local> = d.into();
+    datetime
+        .timestamp()
+        .checked_mul(1_000_000_000)
+        .and_then(|nanos| nanos.checked_add(i64::from(datetime.timestamp_subsec_nanos())))
+        .ok_or(SqliteStorageError::TimeOutOfRange(d))
+}
+
+fn from_nanos(nanos: i64) -> DateTimeW {
+    Local.timestamp_nanos(nanos).into()
+}
+
+// (rowid, activity)
+fn finished_from_row(row: &Row) -> Result<(i64, Activity), SqliteStorageError> {
+    let start_time: i64 = row.get(0)?;
+    let stop_time: i64 = row.get(1)?;
+    let tags: String = row.get(2)?;
+    let description: Option<Description> = row.get(3)?;
+    let uid: String = row.get(4)?;
+    let rowid: i64 = row.get(5)?;
+    let breaks: String = row.get(6)?;
+    let metadata: String = row.get(7)?;
+    let ongoing = OngoingActivity {
+        uid: ActivityUid::parse_str(&uid)?,
+        start_time: from_nanos(start_time),
+        tags: serde_json::from_str(&tags)?,
+        description,
+        breaks: serde_json::from_str(&breaks)?,
+        metadata: serde_json::from_str(&metadata)?,
+    };
+    Ok((rowid, ongoing.into_activity(from_nanos(stop_time))?))
+}
+
+// (rowid, activity)
+fn ongoing_from_row(row: &Row) -> Result<(i64, OngoingActivity), SqliteStorageError> {
+    let rowid: i64 = row.get(0)?;
+    let start_time: i64 = row.get(1)?;
+    let tags: String = row.get(2)?;
+    let description: Option<Description> = row.get(3)?;
+    let uid: String = row.get(4)?;
+    let breaks: String = row.get(5)?;
+    let metadata: String = row.get(6)?;
+    let tags: Tags = serde_json::from_str(&tags)?;
+    let ongoing = OngoingActivity {
+        uid: ActivityUid::parse_str(&uid)?,
+        start_time: from_nanos(start_time),
+        tags,
+        description,
+        breaks: serde_json::from_str(&breaks)?,
+        metadata: serde_json::from_str(&metadata)?,
+    };
+    Ok((rowid, ongoing))
+}
+
+impl SqliteStorage {
+    /// Open (or create) the database at `path` and apply pending schema migrations
+    pub fn open(path: &Path) -> Result<Self, SqliteStorageError> {
+        let connection = Connection::open(path)?;
+        // concurrent `rtw` processes wait for each other instead of failing
+        connection.busy_timeout(Duration::from_secs(5))?;
+        let storage = SqliteStorage {
+            connection,
+            unit_depth: 0,
+        };
+        storage.migrate()?;
+        Ok(storage)
+    }
+
+    fn migrate(&self) -> Result<(), SqliteStorageError> {
+        let applied: usize = self
+            .connection
+            .query_row("PRAGMA user_version", [], |row| row.get(0))?;
+        for (version, migration) in MIGRATIONS.iter().enumerate().skip(applied) {
+            let tx = self.connection.unchecked_transaction()?;
+            tx.execute_batch(migration)?;
+            tx.pragma_update(None, "user_version", version + 1)?;
+            tx.commit()?;
+        }
+        Ok(())
+    }
+
+    /// Copy all activities (finished and ongoing) from another storage, unless already done
+    ///
+    /// Activities are inserted in a single transaction which also records the import,
+    /// a failed import is retried on next open.
+    ///
+    /// Returns the number of copied activities (`None` if already imported)
+    pub fn import_from<S: Storage>(&mut self, other: &S) -> anyhow::Result<Option<usize>> {
+        let tx = self.connection.transaction()?;
+        let imported: bool = tx.query_row(
+            "SELECT EXISTS (SELECT 1 FROM meta WHERE key = 'imported')",
+            [],
+            |row| row.get(0),
+        )?;
+        if imported {
+            return Ok(None);
+        }
+        let finished = other.get_finished_activities()?;
+        let ongoing = other.get_ongoing_activities()?;
+        for (_id, activity) in finished.iter() {
+            insert_finished(&tx, activity)?;
+        }
+        for (_id, activity) in ongoing.iter() {
+            insert_ongoing(&tx, activity)?;
+        }
+        tx.execute(MARK_IMPORTED, [])?;
+        tx.commit()?;
+        Ok(Some(finished.len() + ongoing.len()))
+    }
+
+    // (rowid, activity)
+    fn query_finished(
+        &self,
+        sql: &str,
+        params: impl rusqlite::Params,
+    ) -> Result<Vec<(i64, Activity)>, SqliteStorageError> {
+        let mut statement = self.connection.prepare(sql)?;
+        let mut rows = statement.query(params)?;
+        let mut activities = vec![];
+        while let Some(row) = rows.next()? {
+            activities.push(finished_from_row(row)?);
+        }
+        Ok(activities)
+    }
+
+    /// Number `rows` (sorted with `ORDER_FINISHED`, consecutive) with relative ids
+    ///
+    /// Only rows after the last one are counted (using the start time index).
+    fn with_ids(
+        &self,
+        rows: Vec<(i64, Activity)>,
+    ) -> Result<Vec<ActivityWithId>, SqliteStorageError> {
+        let later: i64 = match rows.last() {
+            None => 0,
+            Some((rowid, last)) => self.connection.query_row(
+                COUNT_LATER,
+                params![to_nanos(last.get_start_time())?, rowid],
+                |row| row.get(0),
+            )?,
+        };
+        let count = rows.len();
+        Ok(rows
+            .into_iter()
+            .enumerate()
+            .map(|(index, (_rowid, activity))| (later as usize + count - 1 - index, activity))
+            .collect())
+    }
+
+    // (rowid, activity)
+    fn query_ongoing(&self, sql: &str) -> Result<Vec<(i64, OngoingActivity)>, SqliteStorageError> {
+        let mut statement = self.connection.prepare(sql)?;
+        let mut rows = statement.query([])?;
+        let mut activities = vec![];
+        while let Some(row) = rows.next()? {
+            activities.push(ongoing_from_row(row)?);
+        }
+        Ok(activities)
+    }
+
+    // (rowid, activity) sorted by start time
+    fn get_ongoing_rows(&self) -> Result<Vec<(i64, OngoingActivity)>, SqliteStorageError> {
+        self.query_ongoing(&format!("{} ORDER BY start_time, rowid", SELECT_ONGOING))
+    }
+}
+
+fn insert_finished(connection: &Connection, activity: &Activity) -> Result<(), SqliteStorageError> {
+    connection.execute(
+        "INSERT INTO finished (uid, start_time, stop_time, tags, description, breaks, metadata) VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)",
+        params![
+            activity.get_uid().to_string(),
+            to_nanos(activity.get_start_time())?,
+            to_nanos(activity.get_stop_time())?,
+            serde_json::to_string(&activity.get_tags())?,
+            activity.get_description(),
+            serde_json::to_string(&activity.get_breaks())?,
+            serde_json::to_string(&activity.get_metadata())?,
+        ],
+    )?;
+    Ok(())
+}
+
+fn insert_ongoing(
+    connection: &Connection,
+    activity: &OngoingActivity,
+) -> Result<(), SqliteStorageError> {
+    connection.execute(
+        "INSERT INTO ongoing (uid, start_time, tags, description, breaks, metadata) VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
+        params![
+            activity.uid.to_string(),
+            to_nanos(activity.get_start_time())?,
+            serde_json::to_string(&activity.tags)?,
+            activity.description,
+            serde_json::to_string(&activity.breaks)?,
+            serde_json::to_string(&activity.metadata)?,
+        ],
+    )?;
+    Ok(())
+}
+
+impl Storage for SqliteStorage {
+    type StorageError = SqliteStorageError;
+
+    fn write_activity(&mut self, activity: Activity) -> Result<(), Self::StorageError> {
+        insert_finished(&self.connection, &activity)
+    }
+
+    fn filter_activities<P>(&self, p: P) -> Result<Vec<ActivityWithId>, Self::StorageError>
+    where
+        P: Fn(&(ActivityId, Activity)) -> bool,
+    {
+        let activities = self.get_finished_activities()?;
+        Ok(activities.into_iter().filter(p).collect())
+    }
+
+    fn filter_activities_by_range(
+        &self,
+        range_start: DateTimeW,
+        range_end: DateTimeW,
+    ) -> Result<Vec<ActivityWithId>, Self::StorageError> {
+        let sql = format!(
+            "{} WHERE f.start_time BETWEEN ?1 AND ?2 {}",
+            SELECT_FINISHED, ORDER_FINISHED
+        );
+        let activities =
+            self.query_finished(&sql, params![to_nanos(range_start)?, to_nanos(range_end)?])?;
+        self.with_ids(activities)
+    }
+
+    fn get_finished_activities(&self) -> Result<Vec<ActivityWithId>, Self::StorageError> {
+        let sql = format!("{} {}", SELECT_FINISHED, ORDER_FINISHED);
+        let activities = self.query_finished(&sql, [])?;
+        self.with_ids(activities)
+    }
+
+    fn delete_activity(&self, uid: ActivityUid) -> Result<Option<Activity>, Self::StorageError> {
+        let sql = format!("{} WHERE f.uid = ?1 LIMIT 1", SELECT_FINISHED);
+        let deleted = self.query_finished(&sql, params![uid.to_string()])?;
+        match deleted.into_iter().next() {
+            None => Ok(None),
+            Some((rowid, deleted)) => {
+                self.connection
+                    .execute("DELETE FROM finished WHERE rowid = ?1", params![rowid])?;
+                Ok(Some(deleted))
+            }
+        }
+    }
+
+    fn get_ongoing_activities(&self) -> Result<Vec<OngoingActivityWithId>, Self::StorageError> {
+        Ok(self
+            .get_ongoing_rows()?
+            .into_iter()
+            .map(|(_rowid, a)| a)
+            .enumerate()
+            .collect())
+    }
+
+    fn get_ongoing_activity(
+        &self,
+        uid: ActivityUid,
+    ) -> Result<Option<OngoingActivity>, Self::StorageError> {
+        let ongoing_rows = self.get_ongoing_rows()?;
+        Ok(ongoing_rows
+            .into_iter()
+            .map(|(_rowid, a)| a)
+            .find(|a| a.uid == uid))
+    }
+
+    fn add_ongoing_activity(
+        &mut self,
+        activity: OngoingActivity,
+    ) -> Result<(), Self::StorageError> {
+        insert_ongoing(&self.connection, &activity)
+    }
+
+    fn remove_ongoing_activity(
+        &mut self,
+        uid: ActivityUid,
+    ) -> Result<Option<OngoingActivity>, Self::StorageError> {
+        let ongoing_rows = self.get_ongoing_rows()?;
+        match ongoing_rows.into_iter().find(|(_rowid, a)| a.uid == uid) {
+            None => Ok(None),
+            Some((rowid, removed)) => {
+                self.connection
+                    .execute("DELETE FROM ongoing WHERE rowid = ?1", params![rowid])?;
+                Ok(Some(removed))
+            }
+        }
+    }
+
+    fn begin(&mut self) -> Result<(), Self::StorageError> {
+        if self.unit_depth == 0 {
+            // IMMEDIATE: take the write lock now, reads of the unit can't go stale
+            self.connection.execute_batch("BEGIN IMMEDIATE")?;
+        }
+        self.unit_depth += 1;
+        Ok(())
+    }
+
+    fn end(&mut self, commit: bool) -> Result<(), Self::StorageError> {
+        self.unit_depth = self.unit_depth.saturating_sub(1);
+        if self.unit_depth == 0 {
+            let statement = if commit { "COMMIT" } else { "ROLLBACK" };
+            self.connection.execute_batch(statement)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::json_storage::JsonStorage;
+    use crate::rtw_core::activity::{Activity, OngoingActivity};
+    use crate::rtw_core::datetimew::DateTimeW;
+    use crate::rtw_core::storage::Storage;
+    use crate::sqlite_storage::{SqliteStorage, SqliteStorageError};
+    use chrono::{Local, TimeZone};
+    use tempfile::tempdir;
+
+    fn datetime(s: &str) -> DateTimeW {
+        Local
+            .datetime_from_str(s, "%Y-%m-%dT%H:%M:%S")
+            .unwrap()
+            .into()
+    }
+
+    fn activity(start: &str, stop: &str, tag: &str) -> Activity {
+        OngoingActivity::new(datetime(start), vec![String::from(tag)], None)
+            .into_activity(datetime(stop))
+            .unwrap()
+    }
+
+    #[test]
+    fn test_write_and_filter_by_range() {
+        let test_dir = tempdir().expect("error while creating tempdir");
+        let mut storage = SqliteStorage::open(&test_dir.path().join(".rtw.sqlite")).unwrap();
+        storage
+            .write_activity(activity("2020-12-25T10:00:00", "2020-12-25T11:00:00", "b"))
+            .unwrap();
+        storage
+            .write_activity(activity("2020-12-25T09:00:00", "2020-12-25T10:00:00", "a"))
+            .unwrap();
+        storage
+            .write_activity(activity("2020-12-26T09:00:00", "2020-12-26T10:00:00", "c"))
+            .unwrap();
+        let all = storage.get_finished_activities().unwrap();
+        let titles: Vec<(usize, String)> = all.iter().map(|(i, a)| (*i, a.get_title())).collect();
+        assert_eq!(
+            titles,
+            vec![
+                (2, String::from("a")),
+                (1, String::from("b")),
+                (0, String::from("c"))
+            ]
+        );
+        let in_range = storage
+            .filter_activities_by_range(
+                datetime("2020-12-25T09:30:00"),
+                datetime("2020-12-26T09:00:00"),
+            )
+            .unwrap();
+        let titles: Vec<(usize, String)> =
+            in_range.iter().map(|(i, a)| (*i, a.get_title())).collect();
+        assert_eq!(titles, vec![(1, String::from("b")), (0, String::from("c"))]);
+    }
+
+    #[test]
+    fn test_time_out_of_range_is_an_error() {
+        let test_dir = tempdir().expect("error while creating tempdir");
+        let mut storage = SqliteStorage::open(&test_dir.path().join(".rtw.sqlite")).unwrap();
+        let far_future = activity("2300-12-25T09:00:00", "2300-12-25T10:00:00", "a");
+        assert!(matches!(
+            storage.write_activity(far_future),
+            Err(SqliteStorageError::TimeOutOfRange(_))
+        ));
+        assert!(storage.get_finished_activities().unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_delete_activity() {
+        let test_dir = tempdir().expect("error while creating tempdir");
+        let mut storage = SqliteStorage::open(&test_dir.path().join(".rtw.sqlite")).unwrap();
+        storage
+            .write_activity(activity("2020-12-25T09:00:00", "2020-12-25T10:00:00", "a"))
+            .unwrap();
+        storage
+            .write_activity(activity("2020-12-25T10:00:00", "2020-12-25T11:00:00", "b"))
+            .unwrap();
+        let (_id, first) = storage.get_finished_activities().unwrap()[0].clone();
+        let deleted = storage.delete_activity(first.get_uid()).unwrap();
+        assert_eq!(deleted.unwrap().get_title(), "a");
+        assert!(storage.delete_activity(first.get_uid()).unwrap().is_none());
+        assert_eq!(storage.get_finished_activities().unwrap().len(), 1);
+    }
+
+    #[test]
+    fn test_ongoing_activities() {
+        let test_dir = tempdir().expect("error while creating tempdir");
+        let mut storage = SqliteStorage::open(&test_dir.path().join(".rtw.sqlite")).unwrap();
+        storage
+            .add_ongoing_activity(OngoingActivity::new(
+                datetime("2020-12-25T10:00:00"),
+                vec![String::from("b")],
+                None,
+            ))
+            .unwrap();
+        storage
+            .add_ongoing_activity(OngoingActivity::new(
+                datetime("2020-12-25T09:00:00"),
+                vec![String::from("a")],
+                Some(String::from("description")),
+            ))
+            .unwrap();
+        let (_id, first) = storage.get_ongoing_activities().unwrap()[0].clone();
+        let ongoing = storage.get_ongoing_activity(first.uid).unwrap().unwrap();
+        assert_eq!(ongoing.get_title(), "a");
+        assert_eq!(ongoing.description, Some(String::from("description")));
+        let removed = storage.remove_ongoing_activity(first.uid).unwrap().unwrap();
+        assert_eq!(removed.get_title(), "a");
+        let ongoing = storage.get_ongoing_activities().unwrap();
+        assert_eq!(ongoing.len(), 1);
+        assert_eq!(ongoing[0].1.get_title(), "b");
+    }
+
+    #[test]
+    fn test_breaks_are_stored() {
+        let test_dir = tempdir().expect("error while creating tempdir");
+        let mut storage = SqliteStorage::open(&test_dir.path().join(".rtw.sqlite")).unwrap();
+        let paused = OngoingActivity::new(datetime("2020-12-25T09:00:00"), vec![], None)
+            .pause(datetime("2020-12-25T09:30:00"))
+            .unwrap();
+        storage.add_ongoing_activity(paused.clone()).unwrap();
+        assert_eq!(
+            storage.get_ongoing_activities().unwrap(),
+            vec![(0, paused.clone())]
+        );
+        let finished = paused
+            .into_activity(datetime("2020-12-25T10:00:00"))
+            .unwrap();
+        storage.write_activity(finished.clone()).unwrap();
+        assert_eq!(
+            storage.get_finished_activities().unwrap(),
+            vec![(0, finished)]
+        );
+    }
+
+    #[test]
+    fn test_metadata_is_stored() {
+        let test_dir = tempdir().expect("error while creating tempdir");
+        let mut storage = SqliteStorage::open(&test_dir.path().join(".rtw.sqlite")).unwrap();
+        let metadata = vec![(String::from("ticket"), String::from("ABC-123"))]
+            .into_iter()
+            .collect();
+        let ongoing = OngoingActivity::new(datetime("2020-12-25T09:00:00"), vec![], None)
+            .with_metadata(metadata);
+        storage.add_ongoing_activity(ongoing.clone()).unwrap();
+        assert_eq!(
+            storage.get_ongoing_activities().unwrap(),
+            vec![(0, ongoing.clone())]
+        );
+        let finished = ongoing
+            .into_activity(datetime("2020-12-25T10:00:00"))
+            .unwrap();
+        storage.write_activity(finished.clone()).unwrap();
+        assert_eq!(
+            storage.get_finished_activities().unwrap(),
+            vec![(0, finished)]
+        );
+    }
+
+    #[test]
+    fn test_import_from_json() {
+        let test_dir = tempdir().expect("error while creating tempdir");
+        let mut json_storage = JsonStorage::new(
+            test_dir.path().join(".rtw.json"),
+            test_dir.path().join(".rtwh.json"),
+        );
+        json_storage
+            .write_activity(activity("2020-12-25T09:00:00", "2020-12-25T10:00:00", "a"))
+            .unwrap();
+        json_storage
+            .add_ongoing_activity(OngoingActivity::new(
+                datetime("2020-12-25T10:00:00"),
+                vec![String::from("b")],
+                None,
+            ))
+            .unwrap();
+        let mut storage = SqliteStorage::open(&test_dir.path().join(".rtw.sqlite")).unwrap();
+        assert_eq!(storage.import_from(&json_storage).unwrap(), Some(2));
+        assert_eq!(
+            storage.get_finished_activities().unwrap(),
+            json_storage.get_finished_activities().unwrap()
+        );
+        assert_eq!(
+            storage.get_ongoing_activities().unwrap(),
+            json_storage.get_ongoing_activities().unwrap()
+        );
+        drop(storage);
+        let mut storage = SqliteStorage::open(&test_dir.path().join(".rtw.sqlite")).unwrap();
+        assert_eq!(storage.import_from(&json_storage).unwrap(), None);
+        assert_eq!(storage.get_finished_activities().unwrap().len(), 1);
+    }
+
+    #[test]
+    fn test_failed_import_is_retried() {
+        let test_dir = tempdir().expect("error while creating tempdir");
+        let finished_path = test_dir.path().join(".rtwh.json");
+        std::fs::write(&finished_path, "not json").unwrap();
+        let mut json_storage = JsonStorage::new(test_dir.path().join(".rtw.json"), finished_path);
+        let mut storage = SqliteStorage::open(&test_dir.path().join(".rtw.sqlite")).unwrap();
+        assert!(storage.import_from(&json_storage).is_err());
+        drop(storage);
+        std::fs::remove_file(test_dir.path().join(".rtwh.json")).unwrap();
+        json_storage
+            .write_activity(activity("2020-12-25T09:00:00", "2020-12-25T10:00:00", "a"))
+            .unwrap();
+        let mut storage = SqliteStorage::open(&test_dir.path().join(".rtw.sqlite")).unwrap();
+        assert_eq!(storage.import_from(&json_storage).unwrap(), Some(1));
+    }
+}
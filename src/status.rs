@@ -28,10 +28,16 @@ where
                     let started: Duration = (ongoing.start_time - now).into();
                     format_string
                         .replace("{id}", &format!("{}", id))
+                        .replace("{uid}", &format!("{}", ongoing.uid))
                         .replace("{ongoing}", &ongoing.get_title())
                         .replace("{start}", &format!("{}", ongoing.start_time))
                         .replace("{human_duration}", &format!("{}", HumanTime::from(started)))
                         .replace("{duration}", &format!("{}", now - ongoing.start_time))
+                        .replace(
+                            "{net_duration}",
+                            &format!("{}", ongoing.get_net_duration(now)),
+                        )
+                        .replace("{paused}", if ongoing.is_paused() { "paused" } else { "" })
                 })
                 .join(" "),
         ))
@@ -0,0 +1,175 @@
+//! Hierarchical tags (e.g. `client:acme:backend`) rolled up into a tree (see `summary --report --depth`).
+//!
+//! The path of an activity is the concatenation of its tags split on the tag separator:
+//! `client:acme backend` and `client acme backend` both give `client > acme > backend`.
+use crate::json_export::seconds;
+use crate::rtw_core::activity::Activity;
+use crate::rtw_core::durationw::DurationW;
+use crate::rtw_core::Tags;
+
+/// Name of the node of activities without tags
+const UNTAGGED: &str = "(untagged)";
+
+/// Activities sharing a tag path prefix summed up
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct TagNode {
+    pub name: String,
+    pub duration: DurationW,
+    pub net_duration: DurationW,
+    pub has_breaks: bool,
+    pub segments: usize,
+    /// Net duration of each activity (see `Rounding::round_group`)
+    pub net_durations: Vec<DurationW>,
+    /// In order of first appearance
+    pub children: Vec<TagNode>,
+}
+
+impl TagNode {
+    fn new(name: &str) -> Self {
+        TagNode {
+            name: name.to_string(),
+            duration: DurationW::default(),
+            net_duration: DurationW::default(),
+            has_breaks: false,
+            segments: 0,
+            net_durations: vec![],
+            children: vec![],
+        }
+    }
+
+    fn add(&mut self, path: &[String], activity: &Activity) {
+        self.duration = self.duration + activity.get_duration();
+        self.net_duration = self.net_duration + activity.get_net_duration();
+        self.has_breaks |= !activity.get_breaks().is_empty();
+        self.segments += 1;
+        self.net_durations.push(activity.get_net_duration());
+        if let Some((name, rest)) = path.split_first() {
+            child(&mut self.children, name).add(rest, activity);
+        }
+    }
+
+    /// Nodes in depth-first order with their path
+    pub fn walk(&self) -> Vec<(Vec<String>, &TagNode)> {
+        let mut nodes = vec![(vec![self.name.clone()], self)];
+        for child in self.children.iter() {
+            nodes.extend(child.walk().into_iter().map(|(path, node)| {
+                let mut full_path = vec![self.name.clone()];
+                full_path.extend(path);
+                (full_path, node)
+            }));
+        }
+        nodes
+    }
+}
+
+fn child<'a>(children: &'a mut Vec<TagNode>, name: &str) -> &'a mut TagNode {
+    match children.iter().position(|c| c.name == name) {
+        Some(index) => &mut children[index],
+        None => {
+            children.push(TagNode::new(name));
+            children.last_mut().expect("just pushed")
+        }
+    }
+}
+
+/// `part` / `total` in percent, rounded to 1 decimal (0 if `total` is 0)
+pub(crate) fn percent(part: DurationW, total: DurationW) -> f64 {
+    let total = seconds(total);
+    if total == 0 {
+        0.0
+    } else {
+        (seconds(part) as f64 * 1000.0 / total as f64).round() / 10.0
+    }
+}
+
+/// Split tags on `separator` (an empty separator keeps tags whole)
+pub(crate) fn tag_path(tags: &Tags, separator: &str) -> Vec<String> {
+    tags.iter()
+        .flat_map(|tag| {
+            if separator.is_empty() {
+                vec![tag.clone()]
+            } else {
+                tag.split(separator)
+                    .filter(|segment| !segment.is_empty())
+                    .map(String::from)
+                    .collect()
+            }
+        })
+        .collect()
+}
+
+/// Build the tag trees of `activities`, paths are truncated to `depth` levels
+///
+/// Returns root nodes (one per first path segment).
+pub(crate) fn build_tag_tree(
+    activities: &[Activity],
+    separator: &str,
+    depth: usize,
+) -> Vec<TagNode> {
+    let mut roots: Vec<TagNode> = vec![];
+    for activity in activities {
+        let mut path = tag_path(&activity.get_tags(), separator);
+        if path.is_empty() {
+            path.push(String::from(UNTAGGED));
+        }
+        path.truncate(depth);
+        let (root, rest) = path.split_first().expect("at least 1 segment");
+        child(&mut roots, root).add(rest, activity);
+    }
+    roots
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::rtw_core::activity::{Activity, OngoingActivity};
+    use crate::rtw_core::datetimew::DateTimeW;
+    use crate::tag_tree::{build_tag_tree, tag_path};
+    use chrono::{Local, TimeZone};
+
+    fn activity(start_hour: u32, stop_hour: u32, tags: &[&str]) -> Activity {
+        let start: DateTimeW = Local.ymd(2020, 12, 25).and_hms(start_hour, 0, 0).into();
+        let stop: DateTimeW = Local.ymd(2020, 12, 25).and_hms(stop_hour, 0, 0).into();
+        OngoingActivity::new(start, tags.iter().map(|t| t.to_string()).collect(), None)
+            .into_activity(stop)
+            .unwrap()
+    }
+
+    #[test]
+    fn test_tag_path() {
+        let tags = vec![String::from("client:acme"), String::from("backend")];
+        assert_eq!(tag_path(&tags, ":"), vec!["client", "acme", "backend"]);
+        assert_eq!(tag_path(&tags, ""), tags);
+        assert_eq!(tag_path(&vec![String::from("a::b:")], ":"), vec!["a", "b"]);
+    }
+
+    #[test]
+    fn test_build_tag_tree() {
+        let activities = vec![
+            activity(8, 9, &["client:acme:backend"]),
+            activity(9, 11, &["client:acme:frontend"]),
+            activity(11, 12, &["client:globex"]),
+            activity(12, 13, &[]),
+        ];
+        let roots = build_tag_tree(&activities, ":", 2);
+        assert_eq!(roots.len(), 2);
+        let client = &roots[0];
+        assert_eq!(client.name, "client");
+        assert_eq!(client.segments, 3);
+        assert_eq!(client.duration.to_string(), "04:00:00");
+        let names: Vec<&str> = client.children.iter().map(|c| c.name.as_str()).collect();
+        assert_eq!(names, vec!["acme", "globex"]);
+        assert_eq!(client.children[0].segments, 2);
+        // truncated at depth 2
+        assert!(client.children[0].children.is_empty());
+        assert_eq!(roots[1].name, "(untagged)");
+        let paths: Vec<Vec<String>> = client.walk().into_iter().map(|(p, _)| p).collect();
+        assert_eq!(
+            paths,
+            vec![
+                vec!["client"],
+                vec!["client", "acme"],
+                vec!["client", "globex"]
+            ]
+        );
+    }
+}
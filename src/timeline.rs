@@ -21,9 +21,7 @@ fn chunkify(s: &str, size: usize) -> Vec<String> {
         let inter: Vec<char> = s.chars().collect();
         let chunks = inter.chunks_exact(size);
         let remainder = chunks.remainder().to_vec();
-        let padding: Vec<char> = std::iter::repeat(' ')
-            .take(size - remainder.len())
-            .collect();
+        let padding: Vec<char> = std::iter::repeat_n(' ', size - remainder.len()).collect();
         let padded_remainder: Vec<char> = remainder.iter().chain(padding.iter()).cloned().collect();
         let chunks: Vec<String> = chunks
             .chain(std::iter::once(padded_remainder.as_slice()))
@@ -190,13 +188,21 @@ pub(crate) fn render_days(activities: &[Interval], colors: &[Rgb]) -> anyhow::Re
     let (min_day, max_day) = days(activities);
     let mut rendered: Vec<String> = vec![];
     for day in min_day..=max_day {
-        let day_activities: Vec<Interval> = activities
+        let on_day = |(_, a): &Interval| {
+            let start_time: DateTime<Local> = a.get_start_time().into();
+            start_time.num_days_from_ce() == day
+        };
+        // breaks included, only used for the gross day total
+        let gross_day_activities: Vec<Interval> = activities
             .iter()
             .flat_map(split_interval)
-            .filter(|(_, a)| {
-                let start_time: DateTime<Local> = a.get_start_time().into();
-                start_time.num_days_from_ce() == day
-            })
+            .filter(on_day)
+            .collect();
+        let day_activities: Vec<Interval> = activities
+            .iter()
+            .flat_map(|(id, a)| a.split_at_breaks().into_iter().map(move |part| (*id, part)))
+            .flat_map(|interval| split_interval(&interval))
+            .filter(on_day)
             .collect();
         let day_month = day_activities
             .first()
@@ -205,10 +211,19 @@ pub(crate) fn render_days(activities: &[Interval], colors: &[Rgb]) -> anyhow::Re
                 start_time.format("%d/%m").to_string()
             })
             .unwrap_or_else(|| "??/??".to_string());
-        let total: DurationW = DurationW::from(day_total(day_activities.as_slice()));
-        let total_string = total.to_string();
+        let gross_total = day_total(gross_day_activities.as_slice());
+        let net_total = day_total(day_activities.as_slice());
+        let total_string = if gross_total == net_total {
+            DurationW::from(net_total).to_string()
+        } else {
+            format!(
+                "{} (net {})",
+                DurationW::from(gross_total),
+                DurationW::from(net_total)
+            )
+        };
         let right_padding = total_string.len() + 1; // +1 space
-        let available_length = max(0, width - right_padding as usize) as usize;
+        let available_length = max(0, width - right_padding);
         let data = Renderer::new(day_activities.as_slice(), &bounds, &|a| label(a, colors))
             .with_renderer(&render)
             .with_length(available_length)
@@ -0,0 +1,357 @@
+//! Timewarrior data files import and export.
+//!
+//! Timewarrior stores intervals in monthly `YYYY-MM.data` files, one interval per line:
+//!
+//! `inc 20201225T090000Z - 20201225T100000Z # tag1 "tag 2" # "annotation"`
+use crate::rtw_core::activity::{Activity, OngoingActivity};
+use crate::rtw_core::datetimew::DateTimeW;
+use crate::rtw_core::{Description, Tags};
+use anyhow::anyhow;
+use chrono::{DateTime, Local, NaiveDateTime, TimeZone, Utc};
+use std::path::{Path, PathBuf};
+use uuid::Uuid;
+
+/// Namespace used to derive uids of imported intervals
+///
+/// Importing the same interval twice yields the same uid.
+const TIMEWARRIOR_UID_NAMESPACE: Uuid = Uuid::from_u128(0x9b1d_3c47_52e8_4f0a_a3c6_7e21_d84b_f915);
+
+/// Timewarrior datetime format (UTC)
+const TIMEWARRIOR_DATETIME_FMT: &str = "%Y%m%dT%H%M%SZ";
+
+/// A Timewarrior interval, without stop time if still open
+#[derive(Debug, Clone, PartialEq)]
+struct Interval {
+    start_time: DateTimeW,
+    stop_time: Option<DateTimeW>,
+    tags: Tags,
+    annotation: Option<Description>,
+}
+
+fn parse_datetime(s: &str) -> anyhow::Result<DateTimeW> {
+    let naive = NaiveDateTime::parse_from_str(s, TIMEWARRIOR_DATETIME_FMT)
+        .map_err(|_| anyhow!("invalid datetime: {}", s))?;
+    Ok(Utc.from_utc_datetime(&naive).with_timezone(&Local).into())
+}
+
+// Split line into tokens, quoted tokens may contain spaces and escaped quotes.
+// Returns (token, quoted) pairs so that a quoted "#" is not mistaken for a separator.
+pub(crate) fn tokenize(line: &str) -> anyhow::Result<Vec<(String, bool)>> {
+    let mut tokens = vec![];
+    let mut chars = line.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            ' ' | '\t' => {}
+            '"' => {
+                let mut token = String::new();
+                loop {
+                    match chars.next() {
+                        Some('"') => break,
+                        Some('\\') => match chars.next() {
+                            Some('n') => token.push('\n'),
+                            Some('t') => token.push('\t'),
+                            Some(escaped) => token.push(escaped),
+                            None => return Err(anyhow!("unterminated quote")),
+                        },
+                        Some(c) => token.push(c),
+                        None => return Err(anyhow!("unterminated quote")),
+                    }
+                }
+                tokens.push((token, true));
+            }
+            c => {
+                let mut token = c.to_string();
+                while let Some(c) = chars.peek() {
+                    if *c == ' ' || *c == '\t' {
+                        break;
+                    }
+                    token.push(*c);
+                    chars.next();
+                }
+                tokens.push((token, false));
+            }
+        }
+    }
+    Ok(tokens)
+}
+
+fn parse_line(line: &str) -> anyhow::Result<Option<Interval>> {
+    let tokens = tokenize(line)?;
+    let mut tokens = tokens.into_iter().peekable();
+    match tokens.next() {
+        None => return Ok(None),
+        Some((keyword, false)) if keyword == "inc" => {}
+        Some((keyword, _)) => return Err(anyhow!("unexpected keyword: {}", keyword)),
+    }
+    let start_time = match tokens.next() {
+        Some((start, false)) => parse_datetime(&start)?,
+        _ => return Err(anyhow!("missing start time")),
+    };
+    let stop_time = match tokens.peek() {
+        Some((dash, false)) if dash == "-" => {
+            tokens.next();
+            match tokens.next() {
+                Some((stop, false)) => Some(parse_datetime(&stop)?),
+                _ => return Err(anyhow!("missing stop time")),
+            }
+        }
+        _ => None,
+    };
+    let mut tags = vec![];
+    let mut annotation = None;
+    match tokens.next() {
+        None => {}
+        Some((hash, false)) if hash == "#" => {
+            for (token, quoted) in tokens.by_ref() {
+                if !quoted && token == "#" {
+                    annotation = tokens.next().map(|(annotation, _)| annotation);
+                    break;
+                }
+                tags.push(token);
+            }
+        }
+        Some((token, _)) => return Err(anyhow!("unexpected token: {}", token)),
+    }
+    Ok(Some(Interval {
+        start_time,
+        stop_time,
+        tags,
+        annotation,
+    }))
+}
+
+/// Return monthly data files (`YYYY-MM.data`) of a Timewarrior data directory, sorted by month
+///
+/// Timewarrior root directory (containing `data`) is accepted too.
+fn data_files(dir: &Path) -> anyhow::Result<Vec<PathBuf>> {
+    let data_dir = if dir.join("data").is_dir() {
+        dir.join("data")
+    } else {
+        dir.to_path_buf()
+    };
+    let mut files: Vec<PathBuf> = std::fs::read_dir(&data_dir)?
+        .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+        .filter(|path| {
+            let name = path
+                .file_name()
+                .and_then(|name| name.to_str())
+                .unwrap_or("");
+            name.len() == "YYYY-MM.data".len()
+                && name.ends_with(".data")
+                && name[..7].chars().enumerate().all(|(i, c)| {
+                    if i == 4 {
+                        c == '-'
+                    } else {
+                        c.is_ascii_digit()
+                    }
+                })
+        })
+        .collect();
+    files.sort();
+    Ok(files)
+}
+
+/// Read Timewarrior intervals from data directory `dir`
+///
+/// Returns finished activities and ongoing activities (open intervals).
+pub(crate) fn read_timewarrior_data(
+    dir: &Path,
+) -> anyhow::Result<(Vec<Activity>, Vec<OngoingActivity>)> {
+    let mut finished = vec![];
+    let mut ongoing = vec![];
+    for path in data_files(dir)? {
+        let content = std::fs::read_to_string(&path)?;
+        for (line_number, line) in content.lines().enumerate() {
+            let interval = parse_line(line)
+                .map_err(|e| anyhow!("{}:{}: {}", path.display(), line_number + 1, e))?;
+            if let Some(interval) = interval {
+                let activity = OngoingActivity {
+                    uid: Uuid::new_v5(&TIMEWARRIOR_UID_NAMESPACE, line.trim().as_bytes()),
+                    ..OngoingActivity::new(interval.start_time, interval.tags, interval.annotation)
+                };
+                match interval.stop_time {
+                    None => ongoing.push(activity),
+                    Some(stop_time) => {
+                        finished.push(activity.into_activity(stop_time).map_err(|e| {
+                            anyhow!("{}:{}: {}", path.display(), line_number + 1, e)
+                        })?)
+                    }
+                }
+            }
+        }
+    }
+    Ok((finished, ongoing))
+}
+
+fn format_datetime(d: DateTimeW) -> String {
+    let local: DateTime<Local> = d.into();
+    local
+        .with_timezone(&Utc)
+        .format(TIMEWARRIOR_DATETIME_FMT)
+        .to_string()
+}
+
+fn quote(s: &str) -> String {
+    let escaped = s
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+        .replace('\t', "\\t");
+    format!("\"{}\"", escaped)
+}
+
+pub(crate) fn format_tag(tag: &str) -> String {
+    if tag.is_empty()
+        || tag.contains(|c: char| c.is_whitespace() || c == '"' || c == '#' || c == '\\')
+    {
+        quote(tag)
+    } else {
+        tag.to_string()
+    }
+}
+
+fn format_interval(activity: &Activity) -> String {
+    let mut line = format!(
+        "inc {} - {}",
+        format_datetime(activity.get_start_time()),
+        format_datetime(activity.get_stop_time())
+    );
+    let description = activity.get_description();
+    if !activity.get_tags().is_empty() || description.is_some() {
+        line.push_str(" #");
+        for tag in activity.get_tags() {
+            line.push(' ');
+            line.push_str(&format_tag(&tag));
+        }
+    }
+    if let Some(description) = description {
+        line.push_str(" # ");
+        line.push_str(&quote(&description));
+    }
+    line
+}
+
+/// Convert activities to Timewarrior intervals grouped by month (`YYYY-MM`, UTC)
+///
+/// Breaks are not supported by Timewarrior: activities are split at breaks.
+pub(crate) fn export_activities_to_timewarrior(
+    activities: &[Activity],
+) -> Vec<(String, Vec<String>)> {
+    let mut months: Vec<(String, Vec<String>)> = vec![];
+    let mut intervals: Vec<Activity> = activities
+        .iter()
+        .flat_map(Activity::split_at_breaks)
+        .collect();
+    intervals.sort();
+    for interval in intervals {
+        let start_time: DateTime<Local> = interval.get_start_time().into();
+        let month = start_time.with_timezone(&Utc).format("%Y-%m").to_string();
+        let line = format_interval(&interval);
+        match months.last_mut() {
+            Some((last_month, lines)) if *last_month == month => lines.push(line),
+            _ => months.push((month, vec![line])),
+        }
+    }
+    months
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::rtw_core::activity::OngoingActivity;
+    use crate::timewarrior::{
+        export_activities_to_timewarrior, parse_datetime, parse_line, read_timewarrior_data,
+        Interval,
+    };
+    use std::io::Write;
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_parse_line() {
+        assert_eq!(parse_line("").unwrap(), None);
+        assert_eq!(
+            parse_line(r#"inc 20201225T090000Z - 20201225T100000Z # foo "bar baz" # "a \"note\"""#)
+                .unwrap(),
+            Some(Interval {
+                start_time: parse_datetime("20201225T090000Z").unwrap(),
+                stop_time: Some(parse_datetime("20201225T100000Z").unwrap()),
+                tags: vec![String::from("foo"), String::from("bar baz")],
+                annotation: Some(String::from("a \"note\"")),
+            })
+        );
+        assert_eq!(
+            parse_line("inc 20201225T090000Z").unwrap(),
+            Some(Interval {
+                start_time: parse_datetime("20201225T090000Z").unwrap(),
+                stop_time: None,
+                tags: vec![],
+                annotation: None,
+            })
+        );
+        assert!(parse_line("exc 20201225T090000Z").is_err());
+        assert!(parse_line("inc 2020-12-25 # foo").is_err());
+        assert!(parse_line("inc 20201225T090000Z - # foo").is_err());
+    }
+
+    #[test]
+    fn test_read_timewarrior_data() {
+        let test_dir = tempdir().expect("could not create temp directory");
+        let data_dir = test_dir.path().join("data");
+        std::fs::create_dir(&data_dir).unwrap();
+        let mut file = std::fs::File::create(data_dir.join("2020-12.data")).unwrap();
+        writeln!(file, "inc 20201225T090000Z - 20201225T100000Z # foo").unwrap();
+        writeln!(file, "inc 20201225T110000Z # bar").unwrap();
+        std::fs::write(data_dir.join("tags.data"), "{}").unwrap();
+        let (finished, ongoing) = read_timewarrior_data(test_dir.path()).unwrap();
+        assert_eq!(finished.len(), 1);
+        assert_eq!(finished[0].get_title(), "foo");
+        assert_eq!(ongoing.len(), 1);
+        // uids are derived from lines
+        let (finished_again, _) = read_timewarrior_data(&data_dir).unwrap();
+        assert_eq!(finished[0].get_uid(), finished_again[0].get_uid());
+    }
+
+    #[test]
+    fn test_export_then_parse() {
+        let activity = OngoingActivity::new(
+            parse_datetime("20201231T230000Z").unwrap(),
+            vec![String::from("foo"), String::from("bar baz")],
+            Some(String::from("multi\nline \"note\"")),
+        )
+        .into_activity(parse_datetime("20201231T233000Z").unwrap())
+        .unwrap();
+        let next_month = OngoingActivity::new(
+            parse_datetime("20210101T090000Z").unwrap(),
+            vec![String::from("foo")],
+            None,
+        )
+        .into_activity(parse_datetime("20210101T100000Z").unwrap())
+        .unwrap();
+        let months = export_activities_to_timewarrior(&[next_month, activity]);
+        assert_eq!(
+            months,
+            vec![
+                (
+                    String::from("2020-12"),
+                    vec![String::from(
+                        r#"inc 20201231T230000Z - 20201231T233000Z # foo "bar baz" # "multi\nline \"note\"""#
+                    )]
+                ),
+                (
+                    String::from("2021-01"),
+                    vec![String::from(
+                        "inc 20210101T090000Z - 20210101T100000Z # foo"
+                    )]
+                )
+            ]
+        );
+        let interval = parse_line(&months[0].1[0]).unwrap().unwrap();
+        assert_eq!(
+            interval.tags,
+            vec![String::from("foo"), String::from("bar baz")]
+        );
+        assert_eq!(
+            interval.annotation,
+            Some(String::from("multi\nline \"note\""))
+        );
+    }
+}
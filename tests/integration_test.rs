@@ -1,6 +1,7 @@
 #[cfg(test)]
 mod tests {
     use assert_cmd::Command;
+    use predicates::prelude::*;
     use tempfile::tempdir;
 
     const NO_ACTIVE_TIME_TRACKING: &str = "There is no active time tracking.\n";
@@ -213,6 +214,940 @@ mod tests {
             .stdout(predicates::str::contains("SUMMARY:foo"));
     }
 
+    #[test]
+    fn dump_ical_ongoing() {
+        let test_dir = tempdir().expect("could not create temp directory");
+        let test_dir_path = test_dir.path().to_str().unwrap();
+        let mut cmd = Command::cargo_bin("rtw").unwrap();
+        cmd.arg("-d")
+            .arg(test_dir_path)
+            .arg("start")
+            .arg("foo")
+            .assert()
+            .success();
+        let mut cmd = Command::cargo_bin("rtw").unwrap();
+        cmd.arg("-d")
+            .arg(test_dir_path)
+            .arg("dump")
+            .assert()
+            .success()
+            .stdout(predicates::str::contains("SUMMARY:foo").not());
+        let mut cmd = Command::cargo_bin("rtw").unwrap();
+        cmd.arg("-d")
+            .arg(test_dir_path)
+            .arg("dump")
+            .arg("--ongoing")
+            .assert()
+            .success()
+            .stdout(predicates::str::contains("SUMMARY:foo"))
+            .stdout(predicates::str::contains("CATEGORIES:foo"))
+            .stdout(predicates::str::is_match("DTEND:[0-9]{8}T[0-9]{6}Z").unwrap());
+    }
+
+    #[test]
+    fn dump_ical_ongoing_started_before_range() {
+        let test_dir = tempdir().expect("could not create temp directory");
+        let test_dir_path = test_dir.path().to_str().unwrap();
+        let mut cmd = Command::cargo_bin("rtw").unwrap();
+        cmd.arg("-d")
+            .arg(test_dir_path)
+            .arg("start")
+            .arg("60 min ago")
+            .arg("foo")
+            .assert()
+            .success();
+        let mut cmd = Command::cargo_bin("rtw").unwrap();
+        cmd.arg("-d")
+            .arg(test_dir_path)
+            .arg("dump")
+            .arg("--ongoing")
+            .arg("30 min ago")
+            .arg("-")
+            .arg("now")
+            .assert()
+            .success()
+            .stdout(predicates::str::contains("SUMMARY:foo").not());
+    }
+
+    // spawn `rtw -d <dir> <subcommand> --port 0`
+    // returns the child, the server address and its stdout (kept open for server logs)
+    fn spawn_server(
+        dir: &str,
+        subcommand: &str,
+    ) -> (
+        std::process::Child,
+        String,
+        std::io::BufReader<std::process::ChildStdout>,
+    ) {
+        use std::io::{BufRead, BufReader};
+        let mut server = std::process::Command::new(assert_cmd::cargo::cargo_bin("rtw"))
+            .arg("-d")
+            .arg(dir)
+            .arg(subcommand)
+            .arg("--port")
+            .arg("0")
+            .stdout(std::process::Stdio::piped())
+            .spawn()
+            .unwrap();
+        let mut stdout = BufReader::new(server.stdout.take().unwrap());
+        let mut first_line = String::new();
+        stdout.read_line(&mut first_line).unwrap();
+        let address = first_line
+            .trim()
+            .rsplit("http://")
+            .next()
+            .unwrap()
+            .trim_end_matches('/')
+            .to_string();
+        (server, address, stdout)
+    }
+
+    // minimal HTTP/1.1 client, returns the raw response
+    fn http_request(address: &str, method: &str, path: &str, body: &str) -> String {
+        use std::io::{Read, Write};
+        let mut stream = std::net::TcpStream::connect(address).unwrap();
+        write!(
+            stream,
+            "{} {} HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\nContent-Type: application/json\r\nContent-Length: {}\r\n\r\n{}",
+            method,
+            path,
+            body.len(),
+            body
+        )
+        .unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).unwrap();
+        response
+    }
+
+    #[test]
+    fn serve_ics_feed() {
+        let test_dir = tempdir().expect("could not create temp directory");
+        let test_dir_path = test_dir.path().to_str().unwrap();
+        for tags in &["foo", "bar"] {
+            let mut cmd = Command::cargo_bin("rtw").unwrap();
+            cmd.arg("-d")
+                .arg(test_dir_path)
+                .arg("track")
+                .arg("09:00")
+                .arg("-")
+                .arg("10:00")
+                .arg(tags)
+                .assert()
+                .success();
+        }
+        let (mut server, address, _stdout) = spawn_server(test_dir_path, "serve-ics");
+        let all = http_request(&address, "GET", "/rtw.ics", "");
+        let foo = http_request(&address, "GET", "/rtw.ics?range=today&tags=foo", "");
+        let invalid = http_request(&address, "GET", "/rtw.ics?range=month", "");
+        server.kill().unwrap();
+        server.wait().unwrap();
+        assert!(all.starts_with("HTTP/1.1 200"));
+        assert!(all.contains("text/calendar"));
+        assert!(all.contains("SUMMARY:foo") && all.contains("SUMMARY:bar"));
+        assert!(foo.contains("SUMMARY:foo") && !foo.contains("SUMMARY:bar"));
+        assert!(invalid.starts_with("HTTP/1.1 400"));
+    }
+
+    #[test]
+    fn serve_ics_dry_run() {
+        let test_dir = tempdir().expect("could not create temp directory");
+        let test_dir_path = test_dir.path().to_str().unwrap();
+        let mut cmd = Command::cargo_bin("rtw").unwrap();
+        cmd.arg("-d")
+            .arg(test_dir_path)
+            .arg("--dry")
+            .arg("serve-ics")
+            .arg("--port")
+            .arg("0")
+            .timeout(std::time::Duration::from_secs(10))
+            .assert()
+            .success()
+            .stdout("(dry-run) nothing done\n");
+    }
+
+    #[test]
+    fn serve_api_start_stop() {
+        let test_dir = tempdir().expect("could not create temp directory");
+        let test_dir_path = test_dir.path().to_str().unwrap();
+        let (mut server, address, _stdout) = spawn_server(test_dir_path, "serve");
+        let start = "{\"tags\": [\"foo\"], \"start\": \"1 min ago\"}";
+        let planned = http_request(&address, "POST", "/activities/start?dry=true", start);
+        let ongoing_after_dry_run = http_request(&address, "GET", "/activities/ongoing", "");
+        let started = http_request(&address, "POST", "/activities/start", start);
+        let ongoing = http_request(&address, "GET", "/activities/ongoing", "");
+        let stopped = http_request(&address, "POST", "/activities/stop", "");
+        let finished = http_request(&address, "GET", "/activities?range=today", "");
+        let invalid = http_request(&address, "POST", "/activities/start", "{\"foo\": 1}");
+        let not_found = http_request(&address, "GET", "/foo", "");
+        server.kill().unwrap();
+        server.wait().unwrap();
+        assert!(planned.contains("{\"dry_run\":true,\"mutation\":{\"type\":\"start\""));
+        assert!(ongoing_after_dry_run.ends_with("\r\n\r\n[]\n"));
+        assert!(started.contains("{\"dry_run\":false,\"mutation\":{\"type\":\"start\""));
+        assert!(ongoing.contains("\"tags\":[\"foo\"]"));
+        assert!(stopped.contains("\"type\":\"stop\""));
+        assert!(finished.contains("\"tags\":[\"foo\"]"));
+        assert!(finished.contains("\"ongoing\":false"));
+        assert!(invalid.starts_with("HTTP/1.1 400"));
+        assert!(not_found.starts_with("HTTP/1.1 404"));
+        // changes made through the API are journaled
+        let mut cmd = Command::cargo_bin("rtw").unwrap();
+        cmd.arg("-d")
+            .arg(test_dir_path)
+            .arg("undo")
+            .assert()
+            .success();
+        let mut cmd = Command::cargo_bin("rtw").unwrap();
+        cmd.arg("-d")
+            .arg(test_dir_path)
+            .assert()
+            .success()
+            .stdout(predicates::str::contains("foo"));
+    }
+
+    #[test]
+    fn rpc_start_stop_notifications() {
+        let test_dir = tempdir().expect("could not create temp directory");
+        let test_dir_path = test_dir.path().to_str().unwrap();
+        let mut cmd = Command::cargo_bin("rtw").unwrap();
+        let output = cmd
+            .arg("-d")
+            .arg(test_dir_path)
+            .arg("rpc")
+            .write_stdin(concat!(
+                "{\"jsonrpc\": \"2.0\", \"id\": 1, \"method\": \"start_activity\", \"params\": {\"tags\": [\"foo\"]}}\n",
+                "{\"jsonrpc\": \"2.0\", \"id\": 2, \"method\": \"stop_ongoing_activity\"}\n",
+            ))
+            .output()
+            .unwrap();
+        assert!(output.status.success());
+        let stdout = String::from_utf8(output.stdout).unwrap();
+        let lines: Vec<&str> = stdout.lines().collect();
+        assert_eq!(lines.len(), 4);
+        assert!(lines[0].contains("\"id\":1") && lines[0].contains("\"type\":\"start\""));
+        assert!(lines[1].contains("\"method\":\"ongoing_changed\""));
+        assert!(lines[1].contains("\"tags\":[\"foo\"]"));
+        assert!(lines[2].contains("\"id\":2") && lines[2].contains("\"type\":\"stop\""));
+        assert!(lines[3].contains("\"params\":{\"ongoing\":[]}"));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn hooks_pre_abort_post_notified() {
+        use std::os::unix::fs::PermissionsExt;
+        let test_dir = tempdir().expect("could not create temp directory");
+        let test_dir_path = test_dir.path().to_str().unwrap();
+        let config_dir = tempdir().expect("could not create temp directory");
+        let write_script = |name: &str, content: &str| {
+            let path = config_dir.path().join(name);
+            std::fs::write(&path, content).unwrap();
+            std::fs::set_permissions(&path, std::fs::Permissions::from_mode(0o755)).unwrap();
+            path
+        };
+        let pre_start = write_script(
+            "pre_start.sh",
+            "#!/bin/sh\nif grep -q blocked; then echo 'not now' >&2; exit 1; fi\n",
+        );
+        let log_path = config_dir.path().join("hook.log");
+        let post_stop = write_script(
+            "post_stop.sh",
+            &format!(
+                "#!/bin/sh\necho \"$RTW_HOOK\" >> {0}\ncat >> {0}\n",
+                log_path.display()
+            ),
+        );
+        std::fs::write(
+            config_dir.path().join("rtw_config.json"),
+            format!(
+                "{{\"hooks\": {{\"pre\": {{\"start\": \"{}\"}}, \"post\": {{\"stop\": \"{}\"}}}}}}",
+                pre_start.display(),
+                post_stop.display()
+            ),
+        )
+        .unwrap();
+        let mut cmd = Command::cargo_bin("rtw").unwrap();
+        cmd.env("XDG_CONFIG_HOME", config_dir.path())
+            .arg("-d")
+            .arg(test_dir_path)
+            .arg("start")
+            .arg("blocked")
+            .assert()
+            .failure()
+            .stderr(predicates::str::contains("pre_start hook failed"));
+        let mut cmd = Command::cargo_bin("rtw").unwrap();
+        cmd.env("XDG_CONFIG_HOME", config_dir.path())
+            .arg("-d")
+            .arg(test_dir_path)
+            .assert()
+            .success()
+            .stdout(predicates::str::contains(
+                "There is no active time tracking",
+            ));
+        // starting bar stops foo
+        for args in &[vec!["start", "foo"], vec!["start", "bar"], vec!["stop"]] {
+            let mut cmd = Command::cargo_bin("rtw").unwrap();
+            cmd.env("XDG_CONFIG_HOME", config_dir.path())
+                .arg("-d")
+                .arg(test_dir_path)
+                .args(args)
+                .assert()
+                .success();
+        }
+        // starting before the ongoing activity is reported by the service, no post hook runs
+        let mut cmd = Command::cargo_bin("rtw").unwrap();
+        cmd.env("XDG_CONFIG_HOME", config_dir.path())
+            .arg("-d")
+            .arg(test_dir_path)
+            .arg("start")
+            .arg("foo")
+            .assert()
+            .success();
+        let mut cmd = Command::cargo_bin("rtw").unwrap();
+        cmd.env("XDG_CONFIG_HOME", config_dir.path())
+            .arg("-d")
+            .arg(test_dir_path)
+            .arg("start")
+            .arg("2 hours ago")
+            .arg("bar")
+            .assert()
+            .failure()
+            .stderr(predicates::str::contains("< start_time"));
+        let mut cmd = Command::cargo_bin("rtw").unwrap();
+        cmd.env("XDG_CONFIG_HOME", config_dir.path())
+            .arg("-d")
+            .arg(test_dir_path)
+            .assert()
+            .success()
+            .stdout(predicates::str::contains("Tracking foo"));
+        let log = std::fs::read_to_string(&log_path).unwrap();
+        assert!(log.starts_with("post_stop\n{"));
+        assert_eq!(log.matches("post_stop\n").count(), 2);
+        assert!(log.contains("\"tags\":[\"foo\"]"));
+        assert!(log.contains("\"tags\":[\"bar\"]"));
+        assert!(!log.contains("\"id\""));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn external_subcommand_plugin() {
+        use std::os::unix::fs::PermissionsExt;
+        let test_dir = tempdir().expect("could not create temp directory");
+        let test_dir_path = test_dir.path().to_str().unwrap();
+        let plugin_dir = tempdir().expect("could not create temp directory");
+        let plugin_path = plugin_dir.path().join("rtw-hello");
+        std::fs::write(
+            &plugin_path,
+            "#!/bin/sh\necho \"args: $*\"\necho \"dir: $RTW_STORAGE_DIR\"\ncat \"$RTW_SNAPSHOT\"\n",
+        )
+        .unwrap();
+        std::fs::set_permissions(&plugin_path, std::fs::Permissions::from_mode(0o755)).unwrap();
+        let path = format!(
+            "{}:{}",
+            plugin_dir.path().display(),
+            std::env::var("PATH").unwrap_or_default()
+        );
+        let mut cmd = Command::cargo_bin("rtw").unwrap();
+        cmd.arg("-d")
+            .arg(test_dir_path)
+            .arg("start")
+            .arg("foo")
+            .assert()
+            .success();
+        let mut cmd = Command::cargo_bin("rtw").unwrap();
+        cmd.env("PATH", &path)
+            .arg("-d")
+            .arg(test_dir_path)
+            .arg("hello")
+            .arg("--month")
+            .arg("bar")
+            .assert()
+            .success()
+            .stdout(predicates::str::contains("args: --month bar"))
+            .stdout(predicates::str::contains(format!("dir: {}", test_dir_path)))
+            .stdout(predicates::str::contains("\"ongoing\":[{"))
+            .stdout(predicates::str::contains("\"tags\":[\"foo\"]"));
+        let mut cmd = Command::cargo_bin("rtw").unwrap();
+        cmd.env("PATH", &path)
+            .arg("-d")
+            .arg(test_dir_path)
+            .arg("goodbye")
+            .assert()
+            .failure()
+            .stderr(predicates::str::contains("unknown subcommand: goodbye"));
+    }
+
+    #[test]
+    fn dump_timewarrior_then_import() {
+        let test_dir = tempdir().expect("could not create temp directory");
+        let test_dir_path = test_dir.path().to_str().unwrap();
+        let mut cmd = Command::cargo_bin("rtw").unwrap();
+        cmd.arg("-d")
+            .arg(test_dir_path)
+            .arg("track")
+            .arg("09:00")
+            .arg("-")
+            .arg("10:00")
+            .arg("foo")
+            .arg("-d")
+            .arg("some notes")
+            .assert()
+            .success();
+        let mut cmd = Command::cargo_bin("rtw").unwrap();
+        cmd.arg("-d")
+            .arg(test_dir_path)
+            .arg("dump")
+            .arg("--format")
+            .arg("timewarrior")
+            .assert()
+            .success()
+            .stdout(predicates::str::starts_with("inc "))
+            .stdout(predicates::str::ends_with(" # foo # \"some notes\"\n"));
+        let timewarrior_dir = tempdir().expect("could not create temp directory");
+        let mut cmd = Command::cargo_bin("rtw").unwrap();
+        cmd.arg("-d")
+            .arg(test_dir_path)
+            .arg("dump")
+            .arg("--format")
+            .arg("timewarrior")
+            .arg("--output-dir")
+            .arg(timewarrior_dir.path())
+            .assert()
+            .success()
+            .stdout(predicates::str::starts_with("Writing "));
+        let other_dir = tempdir().expect("could not create temp directory");
+        let mut cmd = Command::cargo_bin("rtw").unwrap();
+        cmd.arg("-d")
+            .arg(other_dir.path())
+            .arg("import")
+            .arg("timewarrior")
+            .arg(timewarrior_dir.path())
+            .assert()
+            .success()
+            .stdout("Imported 1 finished activities\n");
+    }
+
+    #[test]
+    fn dump_csv_then_import() {
+        let test_dir = tempdir().expect("could not create temp directory");
+        let test_dir_path = test_dir.path().to_str().unwrap();
+        let mut cmd = Command::cargo_bin("rtw").unwrap();
+        cmd.arg("-d")
+            .arg(test_dir_path)
+            .arg("track")
+            .arg("09:00")
+            .arg("-")
+            .arg("10:00")
+            .arg("foo")
+            .assert()
+            .success();
+        let mut cmd = Command::cargo_bin("rtw").unwrap();
+        let output = cmd
+            .arg("-d")
+            .arg(test_dir_path)
+            .arg("dump")
+            .arg("--format")
+            .arg("csv")
+            .arg("--time-format")
+            .arg("%Y-%m-%d %H:%M")
+            .output()
+            .unwrap();
+        let csv = String::from_utf8(output.stdout).unwrap();
+        assert!(csv.starts_with("id,start,stop,duration,tags,description,breaks\n"));
+        assert!(csv.contains(" 09:00,"));
+        let csv_dir = tempdir().expect("could not create temp directory");
+        let csv_path = csv_dir.path().join("activities.csv");
+        std::fs::write(
+            &csv_path,
+            format!("{},2020-12-25 11:00,2020-12-25 10:00,,bar,\n", csv),
+        )
+        .unwrap();
+        let other_dir = tempdir().expect("could not create temp directory");
+        let mut cmd = Command::cargo_bin("rtw").unwrap();
+        cmd.arg("-d")
+            .arg(other_dir.path())
+            .arg("import")
+            .arg("csv")
+            .arg("--time-format")
+            .arg("%Y-%m-%d %H:%M")
+            .arg(&csv_path)
+            .assert()
+            .success()
+            .stdout(predicates::str::starts_with("Invalid  line 3: "))
+            .stdout(predicates::str::ends_with(
+                "Imported 1 finished activities\n",
+            ));
+    }
+
+    #[test]
+    fn import_ics_twice() {
+        let test_dir = tempdir().expect("could not create temp directory");
+        let ics_path = test_dir.path().join("calendar.ics");
+        std::fs::write(
+            &ics_path,
+            concat!(
+                "BEGIN:VCALENDAR\r\n",
+                "BEGIN:VEVENT\r\n",
+                "UID:standup@example.com\r\n",
+                "SUMMARY:standup\r\n",
+                "DTSTART;TZID=Europe/Paris:20201225T090000\r\n",
+                "DTEND;TZID=Europe/Paris:20201225T091500\r\n",
+                "END:VEVENT\r\n",
+                "BEGIN:VEVENT\r\n",
+                "UID:lunch@example.com\r\n",
+                "SUMMARY:lunch\r\n",
+                "DTSTART:20201225T120000Z\r\n",
+                "DTEND:20201225T130000Z\r\n",
+                "END:VEVENT\r\n",
+                "END:VCALENDAR\r\n",
+            ),
+        )
+        .unwrap();
+        let data_dir = tempdir().expect("could not create temp directory");
+        let mut cmd = Command::cargo_bin("rtw").unwrap();
+        cmd.arg("-d")
+            .arg(data_dir.path())
+            .arg("import")
+            .arg("ics")
+            .arg(&ics_path)
+            .arg("--tags")
+            .arg("standup")
+            .assert()
+            .success()
+            .stdout("Imported 1 finished activities\n");
+        let mut cmd = Command::cargo_bin("rtw").unwrap();
+        cmd.arg("-d")
+            .arg(data_dir.path())
+            .arg("import")
+            .arg("ics")
+            .arg(&ics_path)
+            .assert()
+            .success()
+            .stdout(predicates::str::starts_with(
+                "Ignored  1 already imported activities\n",
+            ))
+            .stdout(predicates::str::ends_with(
+                "Imported 1 finished activities\n",
+            ));
+    }
+
+    #[test]
+    fn track_with_metadata_then_filter() {
+        let test_dir = tempdir().expect("could not create temp directory");
+        let test_dir_path = test_dir.path().to_str().unwrap();
+        let tracked = [
+            ("09:00", "10:00", "foo", "client=acme"),
+            ("10:00", "11:00", "bar", "client=globex"),
+        ];
+        for (start, stop, tag, metadata) in tracked.iter() {
+            let mut cmd = Command::cargo_bin("rtw").unwrap();
+            cmd.arg("-d")
+                .arg(test_dir_path)
+                .arg("track")
+                .arg(start)
+                .arg("-")
+                .arg(stop)
+                .arg(tag)
+                .arg("--set")
+                .arg(metadata)
+                .arg("--set")
+                .arg("billable=yes")
+                .assert()
+                .success();
+        }
+        let mut cmd = Command::cargo_bin("rtw").unwrap();
+        cmd.arg("-d")
+            .arg(test_dir_path)
+            .arg("summary")
+            .arg("--where")
+            .arg("client=acme")
+            .arg("--where")
+            .arg("billable")
+            .arg("--format")
+            .arg("jsonl")
+            .arg("08:00")
+            .arg("-")
+            .arg("12:00")
+            .assert()
+            .success()
+            .stdout(predicates::str::contains(
+                "\"tags\":[\"foo\"],\"description\":null,\"metadata\":{\"billable\":\"yes\",\"client\":\"acme\"}",
+            ))
+            .stdout(predicates::str::contains("bar").not());
+        let mut cmd = Command::cargo_bin("rtw").unwrap();
+        cmd.arg("-d")
+            .arg(test_dir_path)
+            .arg("track")
+            .arg("12:00")
+            .arg("-")
+            .arg("13:00")
+            .arg("baz")
+            .arg("--set")
+            .arg("nokey")
+            .assert()
+            .failure()
+            .stderr(predicates::str::contains("expected KEY=VALUE"));
+    }
+
+    #[test]
+    fn summary_report_depth() {
+        let test_dir = tempdir().expect("could not create temp directory");
+        let test_dir_path = test_dir.path().to_str().unwrap();
+        let tracked = [
+            ("09:00", "10:00", "client:acme:backend"),
+            ("10:00", "12:00", "client:acme:frontend"),
+            ("12:00", "13:00", "client:globex"),
+        ];
+        for (start, stop, tag) in tracked.iter() {
+            let mut cmd = Command::cargo_bin("rtw").unwrap();
+            cmd.arg("-d")
+                .arg(test_dir_path)
+                .arg("--default")
+                .arg("track")
+                .arg(start)
+                .arg("-")
+                .arg(stop)
+                .arg(tag)
+                .assert()
+                .success();
+        }
+        let mut cmd = Command::cargo_bin("rtw").unwrap();
+        cmd.arg("-d")
+            .arg(test_dir_path)
+            .arg("--default")
+            .arg("summary")
+            .arg("--report")
+            .arg("--depth")
+            .arg("2")
+            .arg("08:00")
+            .arg("-")
+            .arg("14:00")
+            .assert()
+            .success()
+            .stdout(
+                "client   04:00:00 100.0% (3 segments)\n  \
+                 acme   03:00:00  75.0% (2 segments)\n  \
+                 globex 01:00:00  25.0% (1 segment)\n",
+            );
+        let mut cmd = Command::cargo_bin("rtw").unwrap();
+        cmd.arg("-d")
+            .arg(test_dir_path)
+            .arg("--default")
+            .arg("summary")
+            .arg("--report")
+            .arg("--depth")
+            .arg("3")
+            .arg("--format")
+            .arg("jsonl")
+            .arg("08:00")
+            .arg("-")
+            .arg("14:00")
+            .assert()
+            .success()
+            .stdout(predicates::str::contains(
+                "{\"path\":[\"client\",\"acme\",\"frontend\"],\"duration\":7200,\"net_duration\":7200,\"segments\":1,\"percent\":50.0}\n",
+            ));
+        let mut cmd = Command::cargo_bin("rtw").unwrap();
+        cmd.arg("-d")
+            .arg(test_dir_path)
+            .arg("summary")
+            .arg("--depth")
+            .arg("2")
+            .assert()
+            .failure();
+    }
+
+    #[test]
+    fn summary_by_tag() {
+        let test_dir = tempdir().expect("could not create temp directory");
+        let test_dir_path = test_dir.path().to_str().unwrap();
+        let tracked = [
+            ("09:00", "10:00", vec!["foo", "review"]),
+            ("10:00", "11:00", vec!["review", "foo"]),
+            ("11:00", "13:00", vec!["bar", "review"]),
+            ("13:00", "14:00", vec!["meeting"]),
+        ];
+        for (start, stop, tags) in tracked.iter() {
+            let mut cmd = Command::cargo_bin("rtw").unwrap();
+            cmd.arg("-d")
+                .arg(test_dir_path)
+                .arg("track")
+                .arg(start)
+                .arg("-")
+                .arg(stop)
+                .args(tags)
+                .assert()
+                .success();
+        }
+        let mut cmd = Command::cargo_bin("rtw").unwrap();
+        cmd.arg("-d")
+            .arg(test_dir_path)
+            .arg("summary")
+            .arg("--by-tag")
+            .arg("08:00")
+            .arg("-")
+            .arg("15:00")
+            .assert()
+            .success()
+            .stdout(
+                "foo     02:00:00 (2 segments)\n\
+                 review  04:00:00 (3 segments)\n\
+                 bar     02:00:00 (1 segment)\n\
+                 meeting 01:00:00 (1 segment)\n",
+            );
+        let mut cmd = Command::cargo_bin("rtw").unwrap();
+        cmd.arg("-d")
+            .arg(test_dir_path)
+            .arg("summary")
+            .arg("--by-tag")
+            .arg("--split-evenly")
+            .arg("--include-tag")
+            .arg("review")
+            .arg("--exclude-tag")
+            .arg("bar")
+            .arg("08:00")
+            .arg("-")
+            .arg("15:00")
+            .assert()
+            .success()
+            .stdout("foo    01:00:00 (2 segments)\nreview 01:00:00 (2 segments)\n");
+    }
+
+    #[test]
+    fn summary_rounding() {
+        let test_dir = tempdir().expect("could not create temp directory");
+        let test_dir_path = test_dir.path().to_str().unwrap();
+        let config_dir = tempdir().expect("could not create temp directory");
+        let config_path = config_dir.path().join("rtw_config.json");
+        std::fs::write(
+            &config_path,
+            r#"{"rounding": {"increment_minutes": 15, "mode": "up", "scope": "activity"}}"#,
+        )
+        .unwrap();
+        for (start, stop) in [("09:00", "09:10"), ("10:00", "10:20")].iter() {
+            let mut cmd = Command::cargo_bin("rtw").unwrap();
+            cmd.env("XDG_CONFIG_HOME", config_dir.path())
+                .arg("-d")
+                .arg(test_dir_path)
+                .arg("track")
+                .arg(start)
+                .arg("-")
+                .arg(stop)
+                .arg("foo")
+                .assert()
+                .success();
+        }
+        let mut cmd = Command::cargo_bin("rtw").unwrap();
+        cmd.env("XDG_CONFIG_HOME", config_dir.path())
+            .arg("-d")
+            .arg(test_dir_path)
+            .arg("summary")
+            .arg("--report")
+            .arg("08:00")
+            .arg("-")
+            .arg("11:00")
+            .assert()
+            .success()
+            .stdout(predicates::str::contains(
+                "foo   00:30:00 (rounded 00:45:00) (2 segments)\nTotal 00:30:00 (rounded 00:45:00)\n",
+            ));
+        let mut cmd = Command::cargo_bin("rtw").unwrap();
+        cmd.env("XDG_CONFIG_HOME", config_dir.path())
+            .arg("-d")
+            .arg(test_dir_path)
+            .arg("dump")
+            .arg("--format")
+            .arg("jsonl")
+            .arg("08:00")
+            .arg("-")
+            .arg("11:00")
+            .assert()
+            .success()
+            .stdout(predicates::str::contains(
+                "\"net_duration\":600,\"rounded_net_duration\":900,",
+            ));
+        std::fs::write(
+            &config_path,
+            r#"{"rounding": {"increment_minutes": 15, "mode": "up", "scope": "group"}}"#,
+        )
+        .unwrap();
+        let mut cmd = Command::cargo_bin("rtw").unwrap();
+        cmd.env("XDG_CONFIG_HOME", config_dir.path())
+            .arg("-d")
+            .arg(test_dir_path)
+            .arg("summary")
+            .arg("--report")
+            .arg("08:00")
+            .arg("-")
+            .arg("11:00")
+            .assert()
+            .success()
+            .stdout(predicates::str::contains(
+                "foo   00:30:00 (rounded 00:30:00) (2 segments)\n",
+            ));
+        let mut cmd = Command::cargo_bin("rtw").unwrap();
+        cmd.env("XDG_CONFIG_HOME", config_dir.path())
+            .arg("-d")
+            .arg(test_dir_path)
+            .arg("summary")
+            .arg("08:00")
+            .arg("-")
+            .arg("11:00")
+            .assert()
+            .success()
+            .stdout(predicates::str::contains("(rounded 00:15:00)\n"))
+            .stdout(predicates::str::ends_with(
+                "Total 00:30:00 (rounded per title 00:30:00)\n",
+            ));
+    }
+
+    #[test]
+    fn invoice_markdown_and_html() {
+        let test_dir = tempdir().expect("could not create temp directory");
+        let test_dir_path = test_dir.path().to_str().unwrap();
+        let config_dir = tempdir().expect("could not create temp directory");
+        std::fs::write(
+            config_dir.path().join("rtw_config.json"),
+            r#"{"billing": {"currency": "EUR", "client_rates": {"acme": 100.0}, "rounding": {"increment_minutes": 15, "mode": "up"}}}"#,
+        )
+        .unwrap();
+        let tracked = [
+            ("09:00", "09:50", "acme:backend"),
+            ("10:00", "10:20", "acme:backend"),
+            ("11:00", "12:00", "globex"),
+        ];
+        for (start, stop, tag) in tracked.iter() {
+            let mut cmd = Command::cargo_bin("rtw").unwrap();
+            cmd.env("XDG_CONFIG_HOME", config_dir.path())
+                .arg("-d")
+                .arg(test_dir_path)
+                .arg("track")
+                .arg(start)
+                .arg("-")
+                .arg(stop)
+                .arg(tag)
+                .assert()
+                .success();
+        }
+        let mut cmd = Command::cargo_bin("rtw").unwrap();
+        cmd.env("XDG_CONFIG_HOME", config_dir.path())
+            .arg("-d")
+            .arg(test_dir_path)
+            .arg("invoice")
+            .arg("--client")
+            .arg("acme")
+            .arg("08:00")
+            .arg("-")
+            .arg("13:00")
+            .assert()
+            .success()
+            .stdout(predicates::str::starts_with("# Invoice: acme\n"))
+            .stdout(predicates::str::contains(
+                "| acme:backend | 2 | 1.25 | 100.00 EUR | 125.00 EUR |\n",
+            ))
+            .stdout(predicates::str::contains("globex").not());
+        let mut cmd = Command::cargo_bin("rtw").unwrap();
+        cmd.env("XDG_CONFIG_HOME", config_dir.path())
+            .arg("-d")
+            .arg(test_dir_path)
+            .arg("invoice")
+            .arg("--client")
+            .arg("acme")
+            .arg("--format")
+            .arg("html")
+            .arg("08:00")
+            .arg("-")
+            .arg("13:00")
+            .assert()
+            .success()
+            .stdout(predicates::str::contains(
+                "<tr><th>Total</th><td></td><th>1.25</th><td></td><th>125.00 EUR</th></tr>",
+            ));
+        let mut cmd = Command::cargo_bin("rtw").unwrap();
+        cmd.env("XDG_CONFIG_HOME", config_dir.path())
+            .arg("-d")
+            .arg(test_dir_path)
+            .arg("invoice")
+            .arg("--client")
+            .arg("globex")
+            .arg("08:00")
+            .arg("-")
+            .arg("13:00")
+            .assert()
+            .failure()
+            .stderr(predicates::str::contains("no rate for globex"));
+    }
+
+    #[test]
+    fn summary_and_current_json() {
+        let test_dir = tempdir().expect("could not create temp directory");
+        let test_dir_path = test_dir.path().to_str().unwrap();
+        let mut cmd = Command::cargo_bin("rtw").unwrap();
+        cmd.arg("-d")
+            .arg(test_dir_path)
+            .arg("--format")
+            .arg("json")
+            .assert()
+            .success()
+            .stdout("[]\n");
+        let mut cmd = Command::cargo_bin("rtw").unwrap();
+        cmd.arg("-d")
+            .arg(test_dir_path)
+            .arg("track")
+            .arg("09:00")
+            .arg("-")
+            .arg("10:00")
+            .arg("foo")
+            .assert()
+            .success();
+        let mut cmd = Command::cargo_bin("rtw").unwrap();
+        cmd.arg("-d")
+            .arg(test_dir_path)
+            .arg("start")
+            .arg("bar")
+            .assert()
+            .success();
+        let mut cmd = Command::cargo_bin("rtw").unwrap();
+        cmd.arg("-d")
+            .arg(test_dir_path)
+            .arg("summary")
+            .arg("--format")
+            .arg("jsonl")
+            .arg("08:00")
+            .arg("-")
+            .arg("11:00")
+            .assert()
+            .success()
+            .stdout(predicates::str::starts_with("{\"id\":0,\"uid\":"))
+            .stdout(predicates::str::contains(
+                "\"duration\":3600,\"net_duration\":3600,\"tags\":[\"foo\"]",
+            ));
+        let mut cmd = Command::cargo_bin("rtw").unwrap();
+        cmd.arg("-d")
+            .arg(test_dir_path)
+            .arg("summary")
+            .arg("--report")
+            .arg("--format")
+            .arg("jsonl")
+            .arg("08:00")
+            .arg("-")
+            .arg("11:00")
+            .assert()
+            .success()
+            .stdout(
+                "{\"tags\":[\"foo\"],\"duration\":3600,\"net_duration\":3600,\"segments\":1}\n",
+            );
+        let mut cmd = Command::cargo_bin("rtw").unwrap();
+        cmd.arg("-d")
+            .arg(test_dir_path)
+            .arg("--format")
+            .arg("jsonl")
+            .assert()
+            .success()
+            .stdout(predicates::str::contains("\"stop\":null"))
+            .stdout(predicates::str::contains("\"ongoing\":true"));
+    }
+
     #[test]
     fn timeline_day_nothing() {
         let test_dir = tempdir().expect("could not create temp directory");
@@ -220,171 +1155,818 @@ mod tests {
         let mut cmd = Command::cargo_bin("rtw").unwrap();
         cmd.arg("-d")
             .arg(test_dir_path)
-            .arg("day")
+            .arg("day")
+            .assert()
+            .success();
+    }
+
+    #[test]
+    fn timeline_week_nothing() {
+        let test_dir = tempdir().expect("could not create temp directory");
+        let test_dir_path = test_dir.path().to_str().unwrap();
+        let mut cmd = Command::cargo_bin("rtw").unwrap();
+        cmd.arg("-d")
+            .arg(test_dir_path)
+            .arg("week")
+            .assert()
+            .success();
+    }
+
+    #[test]
+    fn timeline_nothing() {
+        let test_dir = tempdir().expect("could not create temp directory");
+        let test_dir_path = test_dir.path().to_str().unwrap();
+        let mut cmd = Command::cargo_bin("rtw").unwrap();
+        cmd.arg("-d")
+            .arg(test_dir_path)
+            .arg("timeline")
+            .arg("09:00")
+            .arg("-")
+            .arg("10:00")
+            .assert()
+            .success();
+    }
+
+    #[test]
+    fn timeline_several_day_span() {
+        let test_dir = tempdir().expect("could not create temp directory");
+        let test_dir_path = test_dir.path().to_str().unwrap();
+        let mut cmd = Command::cargo_bin("rtw").unwrap();
+        cmd.arg("-d")
+            .arg(test_dir_path)
+            .arg("track")
+            .arg("2019-12-24T19:43:00")
+            .arg("-")
+            .arg("2019-12-26T10:45:00")
+            .arg("christmas")
+            .assert()
+            .success();
+        let mut cmd = Command::cargo_bin("rtw").unwrap();
+        cmd.arg("-d")
+            .arg(test_dir_path)
+            .arg("timeline")
+            .arg("2019-12-20T10:43:00")
+            .arg("-")
+            .arg("2019-12-30T10:43:00")
+            .assert()
+            .success();
+    }
+
+    #[test]
+    fn timeline_with_break() {
+        let test_dir = tempdir().expect("could not create temp directory");
+        let test_dir_path = test_dir.path().to_str().unwrap();
+        let mut cmd = Command::cargo_bin("rtw").unwrap();
+        cmd.arg("-d")
+            .arg(test_dir_path)
+            .arg("start")
+            .arg("2019-12-24T09:00:00")
+            .arg("foo")
+            .assert()
+            .success();
+        let mut cmd = Command::cargo_bin("rtw").unwrap();
+        cmd.arg("-d")
+            .arg(test_dir_path)
+            .arg("pause")
+            .arg("2019-12-24T09:30:00")
+            .assert()
+            .success();
+        let mut cmd = Command::cargo_bin("rtw").unwrap();
+        cmd.arg("-d")
+            .arg(test_dir_path)
+            .arg("resume")
+            .arg("2019-12-24T10:00:00")
+            .assert()
+            .success();
+        let mut cmd = Command::cargo_bin("rtw").unwrap();
+        cmd.arg("-d")
+            .arg(test_dir_path)
+            .arg("stop")
+            .arg("2019-12-24T10:30:00")
+            .assert()
+            .success();
+        let mut cmd = Command::cargo_bin("rtw").unwrap();
+        cmd.arg("-d")
+            .arg(test_dir_path)
+            .arg("timeline")
+            .arg("2019-12-24T08:00:00")
+            .arg("-")
+            .arg("2019-12-24T11:00:00")
+            .assert()
+            .success()
+            .stdout(predicates::str::contains("01:30:00 (net 01:00:00)"));
+    }
+
+    #[test]
+    fn continue_none() {
+        let test_dir = tempdir().expect("could not create temp directory");
+        let test_dir_path = test_dir.path().to_str().unwrap();
+        let mut cmd = Command::cargo_bin("rtw").unwrap();
+        cmd.arg("-d")
+            .arg(test_dir_path)
+            .arg("continue")
+            .assert()
+            .success()
+            .stdout("No activity to continue from.\n");
+    }
+
+    #[test]
+    fn continue_last() {
+        let test_dir = tempdir().expect("could not create temp directory");
+        let test_dir_path = test_dir.path().to_str().unwrap();
+        let mut cmd = Command::cargo_bin("rtw").unwrap();
+        cmd.arg("-d")
+            .arg(test_dir_path)
+            .arg("track")
+            .arg("09:00")
+            .arg("-")
+            .arg("10:00")
+            .arg("foo")
+            .assert()
+            .success()
+            .stdout(predicates::str::contains("Recorded foo"));
+        let mut cmd = Command::cargo_bin("rtw").unwrap();
+        cmd.arg("-d")
+            .arg(test_dir_path)
+            .arg("continue")
+            .assert()
+            .success()
+            .stdout("Tracking foo\n");
+    }
+
+    #[test]
+    fn continue_id_1() {
+        let test_dir = tempdir().expect("could not create temp directory");
+        let test_dir_path = test_dir.path().to_str().unwrap();
+        let mut cmd = Command::cargo_bin("rtw").unwrap();
+        cmd.arg("-d")
+            .arg(test_dir_path)
+            .arg("track")
+            .arg("09:00")
+            .arg("-")
+            .arg("10:00")
+            .arg("foo")
+            .assert()
+            .success();
+        let mut cmd = Command::cargo_bin("rtw").unwrap();
+        cmd.arg("-d")
+            .arg(test_dir_path)
+            .arg("track")
+            .arg("10:00")
+            .arg("-")
+            .arg("11:00")
+            .arg("unexpected")
+            .assert()
+            .success();
+        let mut cmd = Command::cargo_bin("rtw").unwrap();
+        cmd.arg("-d")
+            .arg(test_dir_path)
+            .arg("continue")
+            .arg("1") // next to last
+            .assert()
+            .success()
+            .stdout("Tracking foo\n");
+    }
+
+    #[test]
+    fn delete_none() {
+        let test_dir = tempdir().expect("could not create temp directory");
+        let test_dir_path = test_dir.path().to_str().unwrap();
+        let mut cmd = Command::cargo_bin("rtw").unwrap();
+        cmd.arg("-d")
+            .arg(test_dir_path)
+            .arg("delete")
+            .arg("42")
+            .assert()
+            .success()
+            .stdout("No activity found for id 42.\n");
+    }
+
+    #[test]
+    fn delete_some() {
+        let test_dir = tempdir().expect("could not create temp directory");
+        let test_dir_path = test_dir.path().to_str().unwrap();
+        let mut cmd = Command::cargo_bin("rtw").unwrap();
+        cmd.arg("-d")
+            .arg(test_dir_path)
+            .arg("track")
+            .arg("09:00")
+            .arg("-")
+            .arg("10:00")
+            .arg("foo")
+            .assert()
+            .success()
+            .stdout(predicates::str::contains("Recorded foo"));
+        let mut cmd = Command::cargo_bin("rtw").unwrap();
+        cmd.arg("-d")
+            .arg(test_dir_path)
+            .arg("delete")
+            .arg("0")
+            .assert()
+            .success()
+            .stdout(predicates::str::contains("Deleted foo"));
+    }
+
+    #[test]
+    fn delete_uid_after_track() {
+        let test_dir = tempdir().expect("could not create temp directory");
+        let test_dir_path = test_dir.path().to_str().unwrap();
+        let mut cmd = Command::cargo_bin("rtw").unwrap();
+        cmd.arg("-d")
+            .arg(test_dir_path)
+            .arg("track")
+            .arg("2020-12-25T10:00:00")
+            .arg("-")
+            .arg("2020-12-25T11:00:00")
+            .arg("foo")
+            .assert()
+            .success();
+        let mut cmd = Command::cargo_bin("rtw").unwrap();
+        let summary = cmd
+            .arg("-d")
+            .arg(test_dir_path)
+            .arg("summary")
+            .arg("--uid")
+            .arg("2020-12-25T00:00:00")
+            .arg("-")
+            .arg("2020-12-26T00:00:00")
+            .output()
+            .unwrap();
+        let uid = String::from_utf8(summary.stdout).unwrap();
+        let uid = uid.split_whitespace().next().unwrap().to_string();
+        // tracking an older activity shifts relative ids, not uids
+        let mut cmd = Command::cargo_bin("rtw").unwrap();
+        cmd.arg("-d")
+            .arg(test_dir_path)
+            .arg("track")
+            .arg("2020-12-25T08:00:00")
+            .arg("-")
+            .arg("2020-12-25T09:00:00")
+            .arg("bar")
+            .assert()
+            .success();
+        let mut cmd = Command::cargo_bin("rtw").unwrap();
+        cmd.arg("-d")
+            .arg(test_dir_path)
+            .arg("delete")
+            .arg(&uid)
+            .assert()
+            .success()
+            .stdout(predicates::str::contains("Deleted foo"));
+    }
+
+    #[test]
+    fn edit_tags_and_start() {
+        let test_dir = tempdir().expect("could not create temp directory");
+        let test_dir_path = test_dir.path().to_str().unwrap();
+        let mut cmd = Command::cargo_bin("rtw").unwrap();
+        cmd.arg("-d")
+            .arg(test_dir_path)
+            .arg("track")
+            .arg("2020-12-25T09:00:00")
+            .arg("-")
+            .arg("2020-12-25T10:00:00")
+            .arg("foo")
+            .assert()
+            .success();
+        let mut cmd = Command::cargo_bin("rtw").unwrap();
+        cmd.arg("-d")
+            .arg(test_dir_path)
+            .arg("edit")
+            .arg("0")
+            .arg("--start")
+            .arg("2020-12-25T08:30:00")
+            .arg("--tags")
+            .arg("bar")
+            .arg("baz")
+            .assert()
+            .success()
+            .stdout(predicates::str::starts_with("Edited  bar baz"));
+        let mut cmd = Command::cargo_bin("rtw").unwrap();
+        cmd.arg("-d")
+            .arg(test_dir_path)
+            .arg("summary")
+            .arg("--report")
+            .arg("2020-12-25T00:00:00")
+            .arg("-")
+            .arg("2020-12-26T00:00:00")
+            .assert()
+            .success()
+            .stdout("bar baz 01:30:00 (1 segment)\n");
+    }
+
+    #[test]
+    fn edit_dry_run() {
+        let test_dir = tempdir().expect("could not create temp directory");
+        let test_dir_path = test_dir.path().to_str().unwrap();
+        let mut cmd = Command::cargo_bin("rtw").unwrap();
+        cmd.arg("-d")
+            .arg(test_dir_path)
+            .arg("track")
+            .arg("2020-12-25T09:00:00")
+            .arg("-")
+            .arg("2020-12-25T10:00:00")
+            .arg("foo")
+            .assert()
+            .success();
+        let mut cmd = Command::cargo_bin("rtw").unwrap();
+        cmd.arg("-d")
+            .arg(test_dir_path)
+            .arg("--dry")
+            .arg("edit")
+            .arg("0")
+            .arg("--tags")
+            .arg("bar")
+            .assert()
+            .success()
+            .stdout(predicates::str::ends_with("(dry-run) nothing done\n"));
+        let mut cmd = Command::cargo_bin("rtw").unwrap();
+        cmd.arg("-d")
+            .arg(test_dir_path)
+            .arg("summary")
+            .arg("--report")
+            .arg("2020-12-25T00:00:00")
+            .arg("-")
+            .arg("2020-12-26T00:00:00")
+            .assert()
+            .success()
+            .stdout("foo 01:00:00 (1 segment)\n");
+    }
+
+    #[test]
+    fn edit_overlap_not_allowed() {
+        let test_dir = tempdir().expect("could not create temp directory");
+        let test_dir_path = test_dir.path().to_str().unwrap();
+        let mut cmd = Command::cargo_bin("rtw").unwrap();
+        cmd.arg("-d")
+            .arg(test_dir_path)
+            .arg("track")
+            .arg("2020-12-25T09:00:00")
+            .arg("-")
+            .arg("2020-12-25T10:00:00")
+            .arg("foo")
+            .assert()
+            .success();
+        let mut cmd = Command::cargo_bin("rtw").unwrap();
+        cmd.arg("-d")
+            .arg(test_dir_path)
+            .arg("track")
+            .arg("2020-12-25T11:00:00")
+            .arg("-")
+            .arg("2020-12-25T12:00:00")
+            .arg("bar")
+            .assert()
+            .success();
+        let mut cmd = Command::cargo_bin("rtw").unwrap();
+        cmd.arg("-d")
+            .arg(test_dir_path)
+            .arg("--no_overlap")
+            .arg("edit")
+            .arg("0")
+            .arg("--start")
+            .arg("2020-12-25T09:30:00")
+            .assert()
+            .failure()
+            .stderr(predicates::str::contains("would overlap"));
+    }
+
+    #[test]
+    fn modify_nothing() {
+        let test_dir = tempdir().expect("could not create temp directory");
+        let test_dir_path = test_dir.path().to_str().unwrap();
+        let mut cmd = Command::cargo_bin("rtw").unwrap();
+        cmd.arg("-d")
+            .arg(test_dir_path)
+            .arg("modify")
+            .arg("--tags")
+            .arg("foo")
+            .assert()
+            .success()
+            .stdout("There is no active time tracking.\n");
+    }
+
+    #[test]
+    fn start_then_modify() {
+        let test_dir = tempdir().expect("could not create temp directory");
+        let test_dir_path = test_dir.path().to_str().unwrap();
+        let mut cmd = Command::cargo_bin("rtw").unwrap();
+        cmd.arg("-d")
+            .arg(test_dir_path)
+            .arg("start")
+            .arg("foo")
             .assert()
             .success();
+        let mut cmd = Command::cargo_bin("rtw").unwrap();
+        cmd.arg("-d")
+            .arg(test_dir_path)
+            .arg("modify")
+            .arg("--start")
+            .arg("15 min ago")
+            .arg("--tags")
+            .arg("bar")
+            .assert()
+            .success()
+            .stdout(predicates::str::starts_with("Modified bar"));
+        let mut cmd = Command::cargo_bin("rtw").unwrap();
+        cmd.arg("-d")
+            .arg(test_dir_path)
+            .assert()
+            .success()
+            .stdout(predicates::str::starts_with(
+                "Tracking bar\nTotal    00:15:",
+            ));
     }
 
     #[test]
-    fn timeline_week_nothing() {
+    fn start_pause_resume_stop() {
         let test_dir = tempdir().expect("could not create temp directory");
         let test_dir_path = test_dir.path().to_str().unwrap();
         let mut cmd = Command::cargo_bin("rtw").unwrap();
         cmd.arg("-d")
             .arg(test_dir_path)
-            .arg("week")
+            .arg("start")
+            .arg("60 min ago")
+            .arg("foo")
             .assert()
             .success();
+        let mut cmd = Command::cargo_bin("rtw").unwrap();
+        cmd.arg("-d")
+            .arg(test_dir_path)
+            .arg("pause")
+            .arg("40 min ago")
+            .assert()
+            .success()
+            .stdout(predicates::str::starts_with("Paused  foo"));
+        let mut cmd = Command::cargo_bin("rtw").unwrap();
+        cmd.arg("-d")
+            .arg(test_dir_path)
+            .arg("pause")
+            .assert()
+            .failure();
+        let mut cmd = Command::cargo_bin("rtw").unwrap();
+        cmd.arg("-d")
+            .arg(test_dir_path)
+            .assert()
+            .success()
+            .stdout(predicates::str::contains("Paused\n"));
+        let mut cmd = Command::cargo_bin("rtw").unwrap();
+        cmd.arg("-d")
+            .arg(test_dir_path)
+            .arg("resume")
+            .arg("30 min ago")
+            .assert()
+            .success()
+            .stdout(predicates::str::contains("Breaks  00:10:00"));
+        let mut cmd = Command::cargo_bin("rtw").unwrap();
+        cmd.arg("-d")
+            .arg(test_dir_path)
+            .arg("stop")
+            .assert()
+            .success()
+            .stdout(predicates::str::contains("Net     00:50:"));
+        let mut cmd = Command::cargo_bin("rtw").unwrap();
+        cmd.arg("-d")
+            .arg(test_dir_path)
+            .arg("summary")
+            .assert()
+            .success()
+            .stdout(predicates::str::contains("(net 00:50:"));
     }
 
     #[test]
-    fn timeline_nothing() {
+    fn edit_then_undo() {
         let test_dir = tempdir().expect("could not create temp directory");
         let test_dir_path = test_dir.path().to_str().unwrap();
         let mut cmd = Command::cargo_bin("rtw").unwrap();
         cmd.arg("-d")
             .arg(test_dir_path)
-            .arg("timeline")
+            .arg("track")
             .arg("09:00")
             .arg("-")
             .arg("10:00")
+            .arg("foo")
+            .assert()
+            .success();
+        let mut cmd = Command::cargo_bin("rtw").unwrap();
+        cmd.arg("-d")
+            .arg(test_dir_path)
+            .arg("edit")
+            .arg("0")
+            .arg("--tags")
+            .arg("bar")
+            .assert()
+            .success();
+        let mut cmd = Command::cargo_bin("rtw").unwrap();
+        cmd.arg("-d")
+            .arg(test_dir_path)
+            .arg("undo")
             .assert()
             .success();
+        let mut cmd = Command::cargo_bin("rtw").unwrap();
+        cmd.arg("-d")
+            .arg(test_dir_path)
+            .arg("summary")
+            .arg("08:00")
+            .arg("-")
+            .arg("11:00")
+            .assert()
+            .success()
+            .stdout(predicates::str::starts_with("foo "));
     }
 
     #[test]
-    fn timeline_several_day_span() {
+    fn split_then_summary() {
         let test_dir = tempdir().expect("could not create temp directory");
         let test_dir_path = test_dir.path().to_str().unwrap();
         let mut cmd = Command::cargo_bin("rtw").unwrap();
         cmd.arg("-d")
             .arg(test_dir_path)
             .arg("track")
-            .arg("2019-12-24T19:43:00")
+            .arg("09:00")
             .arg("-")
-            .arg("2019-12-26T10:45:00")
-            .arg("christmas")
+            .arg("11:00")
+            .arg("foo")
             .assert()
             .success();
         let mut cmd = Command::cargo_bin("rtw").unwrap();
         cmd.arg("-d")
             .arg(test_dir_path)
-            .arg("timeline")
-            .arg("2019-12-20T10:43:00")
+            .arg("split")
+            .arg("0")
+            .arg("12:00")
+            .assert()
+            .failure();
+        let mut cmd = Command::cargo_bin("rtw").unwrap();
+        cmd.arg("-d")
+            .arg(test_dir_path)
+            .arg("split")
+            .arg("0")
+            .arg("10:00")
+            .arg("bar")
+            .assert()
+            .success()
+            .stdout(predicates::str::starts_with("Recorded foo"))
+            .stdout(predicates::str::contains("Recorded bar"));
+        let mut cmd = Command::cargo_bin("rtw").unwrap();
+        cmd.arg("-d")
+            .arg(test_dir_path)
+            .arg("summary")
+            .arg("--report")
+            .arg("08:00")
             .arg("-")
-            .arg("2019-12-30T10:43:00")
+            .arg("12:00")
             .assert()
-            .success();
+            .success()
+            .stdout(predicates::str::contains("foo 01:00:00"))
+            .stdout(predicates::str::contains("bar 01:00:00"));
     }
 
     #[test]
-    fn continue_none() {
+    fn merge_gap_then_ids() {
         let test_dir = tempdir().expect("could not create temp directory");
         let test_dir_path = test_dir.path().to_str().unwrap();
+        for (start, stop, tag) in &[
+            ("09:00", "10:00", "foo"),
+            ("10:05", "11:00", "foo"),
+            ("11:00", "12:00", "bar"),
+        ] {
+            let mut cmd = Command::cargo_bin("rtw").unwrap();
+            cmd.arg("-d")
+                .arg(test_dir_path)
+                .arg("track")
+                .arg(start)
+                .arg("-")
+                .arg(stop)
+                .arg(tag)
+                .assert()
+                .success();
+        }
         let mut cmd = Command::cargo_bin("rtw").unwrap();
         cmd.arg("-d")
             .arg(test_dir_path)
-            .arg("continue")
+            .arg("merge")
+            .arg("--gap")
+            .arg("1min")
             .assert()
             .success()
-            .stdout("No activity to continue from.\n");
+            .stdout("Nothing to merge.\n");
+        let mut cmd = Command::cargo_bin("rtw").unwrap();
+        cmd.arg("-d")
+            .arg(test_dir_path)
+            .arg("merge")
+            .arg("--gap")
+            .arg("10min")
+            .assert()
+            .success()
+            .stdout(predicates::str::starts_with("Merged  foo (2 activities)"));
+        let mut cmd = Command::cargo_bin("rtw").unwrap();
+        cmd.arg("-d")
+            .arg(test_dir_path)
+            .arg("summary")
+            .arg("--report")
+            .arg("08:00")
+            .arg("-")
+            .arg("13:00")
+            .assert()
+            .success()
+            .stdout(predicates::str::contains(
+                "foo 02:00:00 (net 01:55:00) (1 segment)",
+            ));
+        let mut cmd = Command::cargo_bin("rtw").unwrap();
+        cmd.arg("-d")
+            .arg(test_dir_path)
+            .arg("merge")
+            .arg("0")
+            .arg("1")
+            .assert()
+            .success()
+            .stdout(predicates::str::starts_with(
+                "Merged  foo bar (2 activities)",
+            ));
     }
 
     #[test]
-    fn continue_last() {
+    fn merge_gap_chain() {
         let test_dir = tempdir().expect("could not create temp directory");
         let test_dir_path = test_dir.path().to_str().unwrap();
+        for (start, stop) in &[("09:00", "09:30"), ("09:31", "10:00"), ("10:01", "10:30")] {
+            let mut cmd = Command::cargo_bin("rtw").unwrap();
+            cmd.arg("-d")
+                .arg(test_dir_path)
+                .arg("track")
+                .arg(start)
+                .arg("-")
+                .arg(stop)
+                .arg("foo")
+                .assert()
+                .success();
+        }
         let mut cmd = Command::cargo_bin("rtw").unwrap();
         cmd.arg("-d")
             .arg(test_dir_path)
-            .arg("track")
-            .arg("09:00")
+            .arg("merge")
+            .arg("--gap")
+            .arg("5m")
+            .assert()
+            .success()
+            .stdout(predicates::str::starts_with("Merged  foo (3 activities)"));
+    }
+
+    #[test]
+    fn import_timewarrior_twice() {
+        let test_dir = tempdir().expect("could not create temp directory");
+        let test_dir_path = test_dir.path().to_str().unwrap();
+        let timewarrior_dir = tempdir().expect("could not create temp directory");
+        std::fs::write(
+            timewarrior_dir.path().join("2020-12.data"),
+            concat!(
+                "inc 20201225T090000Z - 20201225T100000Z # foo \"bar baz\" # \"note\"\n",
+                "inc 20201225T093000Z - 20201225T103000Z # overlapping\n"
+            ),
+        )
+        .unwrap();
+        let mut cmd = Command::cargo_bin("rtw").unwrap();
+        cmd.arg("-d")
+            .arg(test_dir_path)
+            .arg("import")
+            .arg("timewarrior")
+            .arg(timewarrior_dir.path())
+            .assert()
+            .failure();
+        let mut cmd = Command::cargo_bin("rtw").unwrap();
+        cmd.arg("-d")
+            .arg(test_dir_path)
+            .arg("import")
+            .arg("timewarrior")
+            .arg("--skip-overlapping")
+            .arg(timewarrior_dir.path())
+            .assert()
+            .success()
+            .stdout("Skipped  1 overlapping activities\nImported 1 finished activities\n");
+        let mut cmd = Command::cargo_bin("rtw").unwrap();
+        cmd.arg("-d")
+            .arg(test_dir_path)
+            .arg("import")
+            .arg("timewarrior")
+            .arg("--skip-overlapping")
+            .arg(timewarrior_dir.path())
+            .assert()
+            .success()
+            .stdout(predicates::str::contains("Nothing to import."));
+        let mut cmd = Command::cargo_bin("rtw").unwrap();
+        cmd.arg("-d")
+            .arg(test_dir_path)
+            .arg("summary")
+            .arg("--description")
+            .arg("2020-12-24T00:00:00")
             .arg("-")
-            .arg("10:00")
-            .arg("foo")
+            .arg("2020-12-27T00:00:00")
             .assert()
             .success()
-            .stdout(predicates::str::contains("Recorded foo"));
+            .stdout(predicates::str::contains("foo bar baz"))
+            .stdout(predicates::str::contains("note"));
+    }
+
+    #[test]
+    fn undo_nothing() {
+        let test_dir = tempdir().expect("could not create temp directory");
+        let test_dir_path = test_dir.path().to_str().unwrap();
         let mut cmd = Command::cargo_bin("rtw").unwrap();
         cmd.arg("-d")
             .arg(test_dir_path)
-            .arg("continue")
+            .arg("undo")
             .assert()
             .success()
-            .stdout("Tracking foo\n");
+            .stdout("Nothing to undo.\n");
     }
 
     #[test]
-    fn continue_id_1() {
+    fn delete_then_undo_then_redo() {
         let test_dir = tempdir().expect("could not create temp directory");
         let test_dir_path = test_dir.path().to_str().unwrap();
         let mut cmd = Command::cargo_bin("rtw").unwrap();
         cmd.arg("-d")
             .arg(test_dir_path)
             .arg("track")
-            .arg("09:00")
+            .arg("2020-12-25T09:00:00")
             .arg("-")
-            .arg("10:00")
+            .arg("2020-12-25T10:00:00")
             .arg("foo")
             .assert()
             .success();
         let mut cmd = Command::cargo_bin("rtw").unwrap();
         cmd.arg("-d")
             .arg(test_dir_path)
-            .arg("track")
-            .arg("10:00")
-            .arg("-")
-            .arg("11:00")
-            .arg("unexpected")
+            .arg("delete")
+            .arg("0")
             .assert()
             .success();
         let mut cmd = Command::cargo_bin("rtw").unwrap();
         cmd.arg("-d")
             .arg(test_dir_path)
-            .arg("continue")
-            .arg("1") // next to last
+            .arg("undo")
             .assert()
             .success()
-            .stdout("Tracking foo\n");
-    }
-
-    #[test]
-    fn delete_none() {
-        let test_dir = tempdir().expect("could not create temp directory");
-        let test_dir_path = test_dir.path().to_str().unwrap();
+            .stdout(predicates::str::starts_with("Undo delete\nRecorded foo"));
         let mut cmd = Command::cargo_bin("rtw").unwrap();
         cmd.arg("-d")
             .arg(test_dir_path)
-            .arg("delete")
-            .arg("42")
+            .arg("summary")
+            .arg("2020-12-25T00:00:00")
+            .arg("-")
+            .arg("2020-12-26T00:00:00")
             .assert()
             .success()
-            .stdout("No activity found for id 42.\n");
+            .stdout(predicates::str::contains("foo"));
+        let mut cmd = Command::cargo_bin("rtw").unwrap();
+        cmd.arg("-d")
+            .arg(test_dir_path)
+            .arg("redo")
+            .assert()
+            .success()
+            .stdout(predicates::str::starts_with("Redo delete\nDeleted foo"));
+        let mut cmd = Command::cargo_bin("rtw").unwrap();
+        cmd.arg("-d")
+            .arg(test_dir_path)
+            .arg("summary")
+            .arg("2020-12-25T00:00:00")
+            .arg("-")
+            .arg("2020-12-26T00:00:00")
+            .assert()
+            .success()
+            .stdout("No filtered data found.\n");
     }
 
     #[test]
-    fn delete_some() {
+    fn start_then_stop_then_undo() {
         let test_dir = tempdir().expect("could not create temp directory");
         let test_dir_path = test_dir.path().to_str().unwrap();
         let mut cmd = Command::cargo_bin("rtw").unwrap();
         cmd.arg("-d")
             .arg(test_dir_path)
-            .arg("track")
-            .arg("09:00")
-            .arg("-")
-            .arg("10:00")
+            .arg("start")
             .arg("foo")
             .assert()
+            .success();
+        let mut cmd = Command::cargo_bin("rtw").unwrap();
+        cmd.arg("-d")
+            .arg(test_dir_path)
+            .arg("stop")
+            .assert()
+            .success();
+        let mut cmd = Command::cargo_bin("rtw").unwrap();
+        cmd.arg("-d")
+            .arg(test_dir_path)
+            .arg("undo")
+            .assert()
             .success()
-            .stdout(predicates::str::contains("Recorded foo"));
+            .stdout(predicates::str::starts_with("Undo stop\nDeleted foo"));
         let mut cmd = Command::cargo_bin("rtw").unwrap();
         cmd.arg("-d")
             .arg(test_dir_path)
-            .arg("delete")
-            .arg("0")
             .assert()
             .success()
-            .stdout(predicates::str::contains("Deleted foo"));
+            .stdout(predicates::str::starts_with("Tracking foo"));
     }
 
     #[test]
@@ -753,4 +2335,45 @@ mod tests {
             .success()
             .stdout(predicates::str::contains("Recorded foo"));
     }
+
+    #[test]
+    fn concurrent_track_no_lost_update() {
+        let test_dir = tempdir().expect("could not create temp directory");
+        let test_dir_path = test_dir.path().to_str().unwrap().to_string();
+        let workers: Vec<std::thread::JoinHandle<()>> = (0..2)
+            .map(|worker| {
+                let test_dir_path = test_dir_path.clone();
+                std::thread::spawn(move || {
+                    for hour in 0..10 {
+                        let start = format!("2020-12-25T{:02}:{}0:00", hour, worker * 2);
+                        let stop = format!("2020-12-25T{:02}:{}0:00", hour, worker * 2 + 1);
+                        let mut cmd = Command::cargo_bin("rtw").unwrap();
+                        cmd.arg("-d")
+                            .arg(&test_dir_path)
+                            .arg("track")
+                            .arg(start)
+                            .arg("-")
+                            .arg(stop)
+                            .arg("foo")
+                            .assert()
+                            .success();
+                    }
+                })
+            })
+            .collect();
+        for worker in workers {
+            worker.join().unwrap();
+        }
+        let mut cmd = Command::cargo_bin("rtw").unwrap();
+        cmd.arg("-d")
+            .arg(&test_dir_path)
+            .arg("summary")
+            .arg("--report")
+            .arg("2020-12-25T00:00:00")
+            .arg("-")
+            .arg("2020-12-26T00:00:00")
+            .assert()
+            .success()
+            .stdout(predicates::str::contains("foo 03:20:00 (20 segments)\n"));
+    }
 }